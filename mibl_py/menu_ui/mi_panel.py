@@ -1,5 +1,8 @@
 from .. node_tree.mi_node_tree import TREE_NAME
 from bpy.types import Panel
+from .. ops import mi_ops
+
+MONITOR_LINES = 20
 
 
 class MI_BL_Panel(Panel):
@@ -27,3 +30,43 @@ class MI_BL_Panel(Panel):
 
         layout.label(text="Server controls :")
         layout.prop(mibl_props, 'mi_use_system_ctlr', text="Use MC system control")
+
+
+class MI_BL_PanelMonitor(Panel):
+    """Latest MIDI messages of the server, to see why a mapping doesn't fire"""
+    bl_label = "MIDI Monitor"
+    bl_idname = "NODE_PT_" + TREE_NAME + "_monitor"
+    bl_parent_id = "NODE_PT_" + TREE_NAME
+    bl_space_type = 'NODE_EDITOR'
+    bl_region_type = 'UI'
+    bl_category = 'MiBl'
+    bl_options = {'DEFAULT_CLOSED'}
+
+    def draw(self, context):
+        layout = self.layout
+        mibl_props = context.scene.mibl
+
+        row = layout.row()
+        row.prop(mibl_props, 'mi_monitor', text="Record")
+        row.operator("mibl.clear_monitor", text="Clear")
+        layout.prop(mibl_props, 'mi_monitor_unmatched', text="Unmatched only")
+        layout.prop(mibl_props, 'mi_monitor_filter', text="Filter")
+
+        if mi_ops.mibl_rs is None:
+            layout.label(text="Server not started")
+            return
+
+        entries = mi_ops.mibl_rs.get_monitor_entries(
+            direction="in" if mibl_props.mi_monitor_unmatched else None,
+            matched=False if mibl_props.mi_monitor_unmatched else None,
+            contains=mibl_props.mi_monitor_filter or None,
+            limit=MONITOR_LINES
+        )
+
+        col = layout.column(align=True)
+        for seq, time, direction, mesg, decoded, events in reversed(entries):
+            if direction == "in":
+                names = ", ".join(name or str(index) for index, name in events)
+                col.label(text=f"{time:8.3f}  IN   {decoded}  -> {names or 'unmatched'}")
+            else:
+                col.label(text=f"{time:8.3f}  OUT  {decoded}")
@@ -11,6 +11,7 @@ from .. node_tree.mi_update import execute_active_node_tree
 from .. utils.mibl_utils import gen_timestamp
 from .. utils.blender_utils import update_count_ev, check_count_ev, clean_count_ev, update_markers, get_area, get_areas, set_persportho, set_prop_layout, set_view_orbit
 from mibllib import mibl_get_event_by_index, mibl_get_sys_event_len, mibl_pow
+from mibllib import mibl_forward_logs, mibl_set_log_level
 
 update_func = None
 mibl_rs = None
@@ -21,6 +22,7 @@ update_interval = 0.01
 timestamp_mode = 0
 frame_drop = False
 first_press = True
+group_selections = {}  # exclusive group name => position of the selected button
 
 
 def parse_sys(mibl_props, mibl_rs):
@@ -69,9 +71,13 @@ def parse_recipe(mibl_props):
 
 def parse_signals(context, mibl_props, sys_signals, timestamp_mode, curr_frame, frame_drop):
     sys_event_len = mibl_get_sys_event_len()
+    groups = dict(mibl_rs.get_exclusive_groups())
 
     for idx, signal in enumerate(sys_signals):
-        if signal[0] < (sys_event_len-1):
+        if signal[0] in groups:
+            # Exclusive group, the value is the position of the selected button
+            group_selections[groups[signal[0]]] = int(signal[1])
+        elif signal[0] < sys_event_len:
             sig_event = mibl_get_event_by_index(signal[0])
             update_count_ev(count_ev, sig_event[0], signal[1])
 
@@ -145,7 +151,7 @@ def parse_signals(context, mibl_props, sys_signals, timestamp_mode, curr_frame,
                 case _:
                     print("Event unknown ", list(signal))
         else:
-            trigger_idx = signal[0] - (sys_event_len - 1)
+            trigger_idx = signal[0] - sys_event_len
             if trigger_idx >= 0 and trigger_idx < len(mibl_props.mi_recipe.ingredients):
                 mibl_props.mi_trigger.idx = trigger_idx
                 mibl_props.mi_trigger.value = signal[1]
@@ -196,6 +202,16 @@ def update_midi_value(context):
 
     execute_active_node_tree()
 
+    # MIDI monitor, the panel shows the latest messages
+    if mibl_rs.get_monitor() != mibl_props.mi_monitor:
+        mibl_rs.set_monitor(mibl_props.mi_monitor)
+    if mibl_props.mi_monitor:
+        for area in get_areas(context, 'NODE_EDITOR'):
+            area.tag_redraw()
+
+    # Records of the server to the `logging` module ("mibl.input", "mibl.output", …)
+    mibl_forward_logs()
+
     return update_interval
 
 
@@ -229,6 +245,7 @@ class MI_BL_OT_update_server_state(Operator):
 
             mibl_rs.set_close_signal(False)
             mibl_rs.set_sysevent(True)
+            mibl_set_log_level("debug" if debug else "info")
             mibl_thread = threading.Thread(target=mibl_rs.mi_start_server_allow_thread, args=(debug,))
 
             scene.mibl.mi_run_server = True
@@ -268,3 +285,14 @@ class MI_BL_OT_update_server_state(Operator):
                     print("MIDI Server Stopped")
 
         return {'FINISHED'}
+
+
+class MI_BL_OT_clear_monitor(Operator):
+    bl_label = "Clear MIDI Monitor"
+    bl_idname = "mibl.clear_monitor"
+
+    def execute(self, context):
+        if mibl_rs is not None:
+            mibl_rs.clear_monitor()
+
+        return {'FINISHED'}
@@ -153,3 +153,17 @@ class PropsMiBl(PropertyGroup):
         name="mi_sys_params",
         type=MI_BL_SysParams
     )
+    mi_monitor: BoolProperty(
+        name="mi_monitor",
+        description="Record the MIDI messages received and sent by the server",
+        default=False
+    )
+    mi_monitor_unmatched: BoolProperty(
+        name="mi_monitor_unmatched",
+        description="Only show the incoming messages which didn't trigger an event",
+        default=False
+    )
+    mi_monitor_filter: StringProperty(
+        name="mi_monitor_filter",
+        description="Only show the messages or events containing this text"
+    )
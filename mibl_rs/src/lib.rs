@@ -1,45 +1,132 @@
-use crate::midi_server::container::Recipe;
+use crate::midi_server::container::{IngredientOptions, Recipe, RecipeOptions, ToggleStates};
+use crate::midi_server::control::Control;
+use crate::midi_server::dmx::{DmxInput, DmxMapping, DmxOutput, DmxProtocol};
+use crate::midi_server::gesture::{parse_binding, GestureRecognizer};
+use crate::midi_server::group::{group_names, GROUP_INDEX};
+use crate::midi_server::hires::HiResParam;
+use crate::midi_server::learn::learn_ingredients;
+use crate::midi_server::logger::{self, RECIPE, SERVER};
 use crate::midi_server::midi_main::init_midi_audio;
-use core::time;
+use crate::midi_server::modifier::{modifier_names, parse_modifiers, ModifierSet, ModifierState};
+use crate::midi_server::monitor::{MonitorFilter, MonitorHandle};
+use crate::midi_server::osc::{OscBridge, OscCommand};
+use crate::midi_server::output::OUTPUT_RATE;
+use crate::midi_server::recorder::{load_session, Direction, RecordingTransport, ReplayTransport};
+use crate::midi_server::rtp_midi::RtpMidiTransport;
+use crate::midi_server::smf::export_smf;
+#[cfg(feature = "websocket")]
+use crate::midi_server::transport::{InjectHandle, InjectingTransport};
+use crate::midi_server::transport::{
+    LoopbackHandle, LoopbackTransport, MidiTransport, MidirTransport,
+};
+#[cfg(feature = "websocket")]
+use crate::midi_server::web::WebBridge;
 use midi_server::container::{DeviceState, Event, ExtTrigger, SIGflag};
 use pyo3::prelude::*;
 use std::sync::mpsc::channel;
 use std::sync::{Arc, Mutex};
 use std::thread::{sleep, spawn};
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 mod midi_server;
 mod node_utils;
 
 #[derive(Clone, Debug)]
 struct MiBlRustProcessInner {
-    tx_triggers: Vec<ExtTrigger>,
     rx_triggers: Vec<ExtTrigger>,
     close_thread: bool,
     use_sysevent: bool,
     recipe: Recipe,
     recipe_need_update: bool,
+    recipe_options: RecipeOptions,
+    learn_target: Option<(String, bool)>,
+    learn_result: Option<(String, String, String, Recipe)>,
+    modifier_latch: ModifierSet,
+    reset_gesture: Option<(String, IngredientOptions)>,
+    panic_gesture: Option<(String, IngredientOptions)>,
+    panic_pending: bool,
+    toggle_states: ToggleStates,
+    toggle_updates: Vec<(u64, bool)>,
+    output_rate: u32,
     device_state: PyDeviceState,
     toggle_btn: u8,
     toggle_btn_sig: bool,
+    transport: String,
+    loopback: Option<LoopbackHandle>,
+    monitor: MonitorHandle,
+    record_path: Option<String>,
+    record_output: bool,
+    replay_path: Option<String>,
+    replay_speed: f32,
+    osc_listen: Option<String>,
+    osc_target: Option<String>,
+    rtp_midi_port: u16,
+    rtp_midi_peer: Option<String>,
+    web_listen: Option<String>,
+    dmx_protocol: Option<String>,
+    dmx_target: Option<String>,
+    dmx_refresh_rate: f32,
+    dmx_mapping: Vec<(String, u16, u16, bool)>,
+    dmx_params: Vec<(String, f32)>,
+    dmx_input_protocol: Option<String>,
+    dmx_input_threshold: f32,
+    dmx_input_mapping: Vec<(u16, u16, bool, u64)>,
 }
 
 impl MiBlRustProcessInner {
     fn new() -> Self {
         MiBlRustProcessInner {
-            tx_triggers: Vec::new(),
             rx_triggers: Vec::new(),
             close_thread: false,
             use_sysevent: true,
             recipe: Recipe::new(),
             recipe_need_update: true,
+            recipe_options: RecipeOptions::new(),
+            learn_target: None,
+            learn_result: None,
+            modifier_latch: 0,
+            reset_gesture: Some((
+                "button.utils_cancel".to_string(),
+                IngredientOptions::from([
+                    ("gesture".to_string(), "double_tap".to_string()),
+                    ("time".to_string(), "1000".to_string()),
+                ]),
+            )),
+            panic_gesture: None,
+            panic_pending: false,
+            toggle_states: ToggleStates::new(),
+            toggle_updates: Vec::new(),
+            output_rate: OUTPUT_RATE,
             device_state: PyDeviceState::default(),
             toggle_btn: 0,
             toggle_btn_sig: false,
+            transport: "midir".to_string(),
+            loopback: None,
+            monitor: MonitorHandle::default(),
+            record_path: None,
+            record_output: false,
+            replay_path: None,
+            replay_speed: 1.0,
+            osc_listen: None,
+            osc_target: None,
+            rtp_midi_port: 5004,
+            rtp_midi_peer: None,
+            web_listen: None,
+            dmx_protocol: None,
+            dmx_target: None,
+            dmx_refresh_rate: 30.0,
+            dmx_mapping: vec![],
+            dmx_params: vec![],
+            dmx_input_protocol: None,
+            dmx_input_threshold: 0.01,
+            dmx_input_mapping: vec![],
         }
     }
 }
 
+/// (seq, time, direction, mesg, decoded, [(index, name)]), *c.f* `get_monitor_entries`
+type MonitorRow = (u64, f64, &'static str, Vec<u8>, String, Vec<(u64, String)>);
+
 #[pyclass(frozen)]
 struct MiBlRustProcess {
     inner: Mutex<MiBlRustProcessInner>,
@@ -144,7 +231,7 @@ impl MiBlRustProcess {
                 3 => self.inner.lock().unwrap().device_state.faders_update = true,
                 4 => self.inner.lock().unwrap().device_state.chan_btns_update = true,
                 5 => self.inner.lock().unwrap().device_state.fps_update = true,
-                _ => println!("Attribute unknown"),
+                _ => log::warn!(target: SERVER, "Attribute unknown"),
             }
         }
     }
@@ -290,6 +377,47 @@ impl MiBlRustProcess {
         self.inner.lock().expect("lock not poisoned").recipe = recipe;
     }
 
+    /// The control name of each ingredient of the recipe (*e.g* "strip3.mute"), `None` if unknown.
+    fn get_recipe_controls(&self) -> Vec<Option<String>> {
+        self.inner
+            .lock()
+            .expect("lock not poisoned")
+            .recipe
+            .iter()
+            .map(|(mesg_in, _, _)| Control::from_midi(mesg_in).map(|control| control.to_string()))
+            .collect()
+    }
+
+    /// Set the recipe from control names : [(control, outputs, value), …]
+    /// *e.g* [("strip3.mute", [[0x90, 0x12, 0x7F]], 1.0), ("master.fader", [], None)]
+    fn set_recipe_controls(
+        &self,
+        controls: Vec<(String, Vec<Vec<u8>>, Option<f32>)>,
+    ) -> PyResult<()> {
+        let mut recipe = Recipe::new();
+        let mut options = RecipeOptions::new();
+
+        for (name, outputs, value) in controls {
+            let control: Control = name
+                .parse()
+                .map_err(pyo3::exceptions::PyValueError::new_err)?;
+
+            for ingredient in control.ingredients(outputs, value) {
+                options.insert(
+                    recipe.len(),
+                    [("control".to_string(), control.to_string())].into(),
+                );
+                recipe.push(ingredient);
+            }
+        }
+
+        let mut inner = self.inner.lock().expect("lock not poisoned");
+        inner.recipe = recipe;
+        inner.recipe_options = options;
+        inner.recipe_need_update = true;
+        Ok(())
+    }
+
     fn get_recipe_need_update(&self) -> bool {
         self.inner
             .lock()
@@ -304,6 +432,152 @@ impl MiBlRustProcess {
             .recipe_need_update = update
     }
 
+    fn get_recipe_options(&self) -> RecipeOptions {
+        self.inner
+            .lock()
+            .expect("lock not poisoned")
+            .recipe_options
+            .clone()
+    }
+
+    /// Options by ingredient position in the recipe, *e.g* {0: {"mode": "toggle"}}
+    fn set_recipe_options(&self, options: RecipeOptions) {
+        let mut inner = self.inner.lock().expect("lock not poisoned");
+        inner.recipe_options = options;
+        inner.recipe_need_update = true;
+    }
+
+    /// Capture the next control touched on the device for `target`,
+    /// with `append` the learned ingredients are added to the live recipe.
+    fn arm_learn(&self, target: String, append: bool) {
+        let mut inner = self.inner.lock().expect("lock not poisoned");
+        inner.learn_target = Some((target, append));
+        inner.learn_result = None;
+    }
+
+    fn cancel_learn(&self) {
+        self.inner.lock().expect("lock not poisoned").learn_target = None;
+    }
+
+    fn get_learn_armed(&self) -> bool {
+        self.inner
+            .lock()
+            .expect("lock not poisoned")
+            .learn_target
+            .is_some()
+    }
+
+    /// (target, kind, control, ingredients) of the last learned control, kind is one of
+    /// "bang", "toggle", "continuous", "relative". The result is consumed.
+    fn get_learn_result(&self) -> Option<(String, String, String, Recipe)> {
+        self.inner
+            .lock()
+            .expect("lock not poisoned")
+            .learn_result
+            .take()
+    }
+
+    fn get_modifier_latch(&self) -> Vec<&'static str> {
+        modifier_names(self.inner.lock().expect("lock not poisoned").modifier_latch)
+    }
+
+    /// Modifiers in latched mode on next server start (*e.g* "shift+alt"), the others are momentary.
+    fn set_modifier_latch(&self, modifiers: String) -> PyResult<()> {
+        self.inner.lock().expect("lock not poisoned").modifier_latch =
+            parse_modifiers(&modifiers).map_err(pyo3::exceptions::PyValueError::new_err)?;
+        Ok(())
+    }
+
+    fn get_reset_gesture(&self) -> Option<(String, IngredientOptions)> {
+        self.inner
+            .lock()
+            .expect("lock not poisoned")
+            .reset_gesture
+            .clone()
+    }
+
+    /// Gesture resetting the device on next server start, *e.g* ("button.utils_cancel",
+    /// {"gesture": "long_press", "time": "2000"}), `None` to disable.
+    fn set_reset_gesture(
+        &self,
+        control: Option<String>,
+        options: IngredientOptions,
+    ) -> PyResult<()> {
+        if let Some(control) = &control {
+            parse_binding(control, &options).map_err(pyo3::exceptions::PyValueError::new_err)?;
+        }
+
+        self.inner.lock().expect("lock not poisoned").reset_gesture =
+            control.map(|control| (control, options));
+        Ok(())
+    }
+
+    fn get_panic_gesture(&self) -> Option<(String, IngredientOptions)> {
+        self.inner
+            .lock()
+            .expect("lock not poisoned")
+            .panic_gesture
+            .clone()
+    }
+
+    /// Gesture of the panic on next server start (a System Reset 0xFF always panics),
+    /// `None` to disable.
+    fn set_panic_gesture(
+        &self,
+        control: Option<String>,
+        options: IngredientOptions,
+    ) -> PyResult<()> {
+        if let Some(control) = &control {
+            parse_binding(control, &options).map_err(pyo3::exceptions::PyValueError::new_err)?;
+        }
+
+        self.inner.lock().expect("lock not poisoned").panic_gesture =
+            control.map(|control| (control, options));
+        Ok(())
+    }
+
+    /// `True` once after each panic, the Python side should drop its pending state.
+    fn take_panic_signal(&self) -> bool {
+        std::mem::take(&mut self.inner.lock().expect("lock not poisoned").panic_pending)
+    }
+
+    /// (trigger index, name) of the exclusive groups of the recipe, the value of a group
+    /// trigger is the position of the selected button in the group.
+    fn get_exclusive_groups(&self) -> Vec<(u64, String)> {
+        let inner = self.inner.lock().expect("lock not poisoned");
+
+        group_names(inner.use_sysevent, &inner.recipe_options)
+            .into_iter()
+            .enumerate()
+            .map(|(position, name)| (GROUP_INDEX + position as u64, name))
+            .collect()
+    }
+
+    /// {event index: on} of the toggle events, kept across recipe updates and server restarts.
+    fn get_toggle_states(&self) -> ToggleStates {
+        self.inner
+            .lock()
+            .expect("lock not poisoned")
+            .toggle_states
+            .clone()
+    }
+
+    /// Restore the state of a toggle event, its LED is updated by the running server.
+    fn set_toggle_state(&self, index: u64, on: bool) {
+        let mut inner = self.inner.lock().expect("lock not poisoned");
+        inner.toggle_states.insert(index, on);
+        inner.toggle_updates.push((index, on));
+    }
+
+    fn get_output_rate(&self) -> u32 {
+        self.inner.lock().expect("lock not poisoned").output_rate
+    }
+
+    /// Bytes by second sent to the device on next server start, 0 for no cap.
+    fn set_output_rate(&self, rate: u32) {
+        self.inner.lock().expect("lock not poisoned").output_rate = rate;
+    }
+
     fn get_toggle_btn(&self) -> u8 {
         self.inner.lock().expect("lock not poisoned").toggle_btn
     }
@@ -320,19 +594,420 @@ impl MiBlRustProcess {
         self.inner.lock().expect("lock not poisoned").toggle_btn_sig = state;
     }
 
+    fn get_transport(&self) -> String {
+        self.inner
+            .lock()
+            .expect("lock not poisoned")
+            .transport
+            .clone()
+    }
+
+    /// "midir" to use the system MIDI device, "loopback" to use an in-memory device (see `inject_midi`),
+    /// "replay" to replay a recorded session (see `set_replay`), "rtpmidi" for a network session (see `set_rtp_midi`)
+    fn set_transport(&self, transport: String) -> PyResult<()> {
+        match transport.as_str() {
+            "midir" | "loopback" | "replay" | "rtpmidi" => {
+                self.inner.lock().expect("lock not poisoned").transport = transport;
+                Ok(())
+            }
+            _ => Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "Unknown transport : {}",
+                transport
+            ))),
+        }
+    }
+
+    /// Send a message to the server as if the loopback device sent it.
+    fn inject_midi(&self, stamp: u64, mesg: Vec<u8>) -> bool {
+        let loopback = self
+            .inner
+            .lock()
+            .expect("lock not poisoned")
+            .loopback
+            .clone();
+
+        match loopback {
+            Some(loopback) => loopback.inject(stamp, &mesg),
+            None => false,
+        }
+    }
+
+    /// All the messages sent by the server to the loopback device since the last call.
+    fn take_sent_midi(&self) -> Vec<Vec<u8>> {
+        match &self.inner.lock().expect("lock not poisoned").loopback {
+            Some(loopback) => loopback.take_sent(),
+            None => vec![],
+        }
+    }
+
+    fn get_monitor(&self) -> bool {
+        self.inner
+            .lock()
+            .expect("lock not poisoned")
+            .monitor
+            .is_enabled()
+    }
+
+    /// Record the messages received and sent by the server, for a live MIDI log.
+    fn set_monitor(&self, enabled: bool) {
+        self.inner
+            .lock()
+            .expect("lock not poisoned")
+            .monitor
+            .set_enabled(enabled);
+    }
+
+    /// The monitored messages, oldest first : (seq, time, direction, mesg, decoded, [(index, name)]).
+    /// `direction` "in" or "out", `matched` to keep the messages which triggered events (or not),
+    /// `contains` a text of the decoded message or event names, `since` a sequence number already
+    /// shown, `limit` to keep the latest entries.
+    #[pyo3(signature = (direction=None, matched=None, contains=None, since=None, limit=None))]
+    fn get_monitor_entries(
+        &self,
+        direction: Option<String>,
+        matched: Option<bool>,
+        contains: Option<String>,
+        since: Option<u64>,
+        limit: Option<usize>,
+    ) -> PyResult<Vec<MonitorRow>> {
+        let direction = match direction.as_deref() {
+            None => None,
+            Some("in") => Some(Direction::In),
+            Some("out") => Some(Direction::Out),
+            Some(direction) => {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "Unknown direction : {}",
+                    direction
+                )))
+            }
+        };
+        let filter = MonitorFilter {
+            direction,
+            matched,
+            contains,
+            since,
+            limit,
+        };
+        let monitor = self
+            .inner
+            .lock()
+            .expect("lock not poisoned")
+            .monitor
+            .clone();
+
+        Ok(monitor
+            .query(&filter)
+            .into_iter()
+            .map(|entry| {
+                (
+                    entry.seq,
+                    entry.time,
+                    entry.direction.as_str(),
+                    entry.mesg,
+                    entry.decoded,
+                    entry.events,
+                )
+            })
+            .collect())
+    }
+
+    fn clear_monitor(&self) {
+        self.inner
+            .lock()
+            .expect("lock not poisoned")
+            .monitor
+            .clear();
+    }
+
+    fn get_record(&self) -> (Option<String>, bool) {
+        let inner = self.inner.lock().expect("lock not poisoned");
+        (inner.record_path.clone(), inner.record_output)
+    }
+
+    /// Record the incoming MIDI (and outgoing if `record_output`) into `path` on next server start.
+    /// `None` to stop recording.
+    fn set_record(&self, path: Option<String>, record_output: bool) {
+        let mut inner = self.inner.lock().expect("lock not poisoned");
+        inner.record_path = path;
+        inner.record_output = record_output;
+    }
+
+    fn get_replay(&self) -> (Option<String>, f32) {
+        let inner = self.inner.lock().expect("lock not poisoned");
+        (inner.replay_path.clone(), inner.replay_speed)
+    }
+
+    /// Replay the session recorded in `path` (or a Standard MIDI File) on next server start,
+    /// instead of the MIDI device.
+    /// `speed` 1.0 is the original speed, 0.0 as fast as possible. `None` to go back to the device.
+    fn set_replay(&self, path: Option<String>, speed: f32) {
+        let mut inner = self.inner.lock().expect("lock not poisoned");
+        inner.transport = match path {
+            Some(_) => "replay".to_string(),
+            None => "midir".to_string(),
+        };
+        inner.replay_path = path;
+        inner.replay_speed = speed;
+    }
+
+    /// Export the incoming control data of a recorded session to a Standard MIDI File,
+    /// using the device fps and timestamp for the SMPTE header.
+    fn export_smf(&self, session_path: String, smf_path: String) -> PyResult<()> {
+        let fps = self.get_fps();
+        let start = self.get_timestamp();
+
+        load_session(&session_path)
+            .and_then(|session| export_smf(&session, fps, start, &smf_path))
+            .map_err(pyo3::exceptions::PyIOError::new_err)
+    }
+
+    fn get_rtp_midi(&self) -> (u16, Option<String>) {
+        let inner = self.inner.lock().expect("lock not poisoned");
+        (inner.rtp_midi_port, inner.rtp_midi_peer.clone())
+    }
+
+    /// Use a RTP-MIDI (AppleMIDI) network session on next server start, on UDP `port` (control) and `port + 1` (data).
+    /// Without `peer` wait for an invitation, else invite `peer` (*e.g* "192.168.1.20:5004").
+    fn set_rtp_midi(&self, port: u16, peer: Option<String>) {
+        let mut inner = self.inner.lock().expect("lock not poisoned");
+        inner.transport = "rtpmidi".to_string();
+        inner.rtp_midi_port = port;
+        inner.rtp_midi_peer = peer;
+    }
+
+    fn get_osc(&self) -> (Option<String>, Option<String>) {
+        let inner = self.inner.lock().expect("lock not poisoned");
+        (inner.osc_listen.clone(), inner.osc_target.clone())
+    }
+
+    /// Open an OSC endpoint on next server start : listen on `listen` (*e.g* "0.0.0.0:9000") for
+    /// device state commands, publish triggers to `target` (*e.g* "192.168.1.20:9001").
+    fn set_osc(&self, listen: Option<String>, target: Option<String>) {
+        let mut inner = self.inner.lock().expect("lock not poisoned");
+        inner.osc_listen = listen;
+        inner.osc_target = target;
+    }
+
+    fn get_web(&self) -> Option<String> {
+        self.inner
+            .lock()
+            .expect("lock not poisoned")
+            .web_listen
+            .clone()
+    }
+
+    /// Serve the virtual control surface on next server start (*e.g* "127.0.0.1:8080").
+    /// Needs the crate built with the `websocket` feature.
+    fn set_web(&self, listen: Option<String>) {
+        self.inner.lock().expect("lock not poisoned").web_listen = listen;
+    }
+
+    fn get_dmx_output(&self) -> (Option<String>, Option<String>, f32) {
+        let inner = self.inner.lock().expect("lock not poisoned");
+        (
+            inner.dmx_protocol.clone(),
+            inner.dmx_target.clone(),
+            inner.dmx_refresh_rate,
+        )
+    }
+
+    /// Send DMX on next server start : `protocol` "artnet" or "sacn" (`None` to disable),
+    /// `target` IP (broadcast for Art-Net and multicast for sACN if `None`), `refresh_rate` in Hz.
+    fn set_dmx_output(
+        &self,
+        protocol: Option<String>,
+        target: Option<String>,
+        refresh_rate: f32,
+    ) -> PyResult<()> {
+        if let Some(protocol) = &protocol {
+            DmxProtocol::from_name(protocol).map_err(pyo3::exceptions::PyValueError::new_err)?;
+        }
+
+        let mut inner = self.inner.lock().expect("lock not poisoned");
+        inner.dmx_protocol = protocol;
+        inner.dmx_target = target;
+        inner.dmx_refresh_rate = refresh_rate;
+        Ok(())
+    }
+
+    fn get_dmx_mapping(&self) -> Vec<(String, u16, u16, bool)> {
+        self.inner
+            .lock()
+            .expect("lock not poisoned")
+            .dmx_mapping
+            .clone()
+    }
+
+    /// DMX output mapping : (trigger index or name, universe, channel (1 to 512), 16 bits)
+    fn set_dmx_mapping(&self, mapping: Vec<(String, u16, u16, bool)>) -> PyResult<()> {
+        for (source, universe, channel, wide) in &mapping {
+            DmxMapping::new(source, *universe, *channel, *wide)
+                .map_err(pyo3::exceptions::PyValueError::new_err)?;
+        }
+
+        self.inner.lock().expect("lock not poisoned").dmx_mapping = mapping;
+        Ok(())
+    }
+
+    /// Send a named parameter (0.0 to 1.0) to the DMX channels mapped on `name`.
+    fn set_dmx_param(&self, name: String, value: f32) {
+        self.inner
+            .lock()
+            .expect("lock not poisoned")
+            .dmx_params
+            .push((name, value));
+    }
+
+    fn get_dmx_input(&self) -> (Option<String>, f32) {
+        let inner = self.inner.lock().expect("lock not poisoned");
+        (inner.dmx_input_protocol.clone(), inner.dmx_input_threshold)
+    }
+
+    /// Listen to DMX on next server start : `protocol` "artnet" or "sacn" (`None` to disable),
+    /// a trigger is sent when a channel moved by `threshold` (0.0 to 1.0) at least.
+    fn set_dmx_input(&self, protocol: Option<String>, threshold: f32) -> PyResult<()> {
+        if let Some(protocol) = &protocol {
+            DmxProtocol::from_name(protocol).map_err(pyo3::exceptions::PyValueError::new_err)?;
+        }
+
+        let mut inner = self.inner.lock().expect("lock not poisoned");
+        inner.dmx_input_protocol = protocol;
+        inner.dmx_input_threshold = threshold;
+        Ok(())
+    }
+
+    fn get_dmx_input_mapping(&self) -> Vec<(u16, u16, bool, u64)> {
+        self.inner
+            .lock()
+            .expect("lock not poisoned")
+            .dmx_input_mapping
+            .clone()
+    }
+
+    /// DMX input mapping : (universe, channel (1 to 512), 16 bits, trigger index)
+    fn set_dmx_input_mapping(&self, mapping: Vec<(u16, u16, bool, u64)>) -> PyResult<()> {
+        for (universe, channel, wide, index) in &mapping {
+            DmxMapping::new(&index.to_string(), *universe, *channel, *wide)
+                .map_err(pyo3::exceptions::PyValueError::new_err)?;
+        }
+
+        self.inner
+            .lock()
+            .expect("lock not poisoned")
+            .dmx_input_mapping = mapping;
+        Ok(())
+    }
+
     fn mi_start_server_allow_thread(&self, debug: bool, py: Python) {
         py.allow_threads(|| mi_start_server(self, debug));
     }
 }
 
+impl MiBlRustProcess {
+    /// Turn a captured message into ingredients for the armed target.
+    fn apply_learned(&self, mesg: &[u8]) {
+        let mut inner = self.inner.lock().expect("lock not poisoned");
+
+        let Some((target, append)) = inner.learn_target.take() else {
+            return;
+        };
+
+        let Some((kind, control, ingredients)) = learn_ingredients(mesg) else {
+            log::warn!(target: RECIPE, "Unable to learn {:X?}", mesg);
+            return;
+        };
+
+        log::info!(target: RECIPE, "Learned {} as {} ({})", target, control, kind.as_str());
+
+        if append {
+            for (ingredient, mut options) in ingredients.iter().cloned() {
+                options.insert("target".to_string(), target.clone());
+                let position = inner.recipe.len();
+                inner.recipe_options.insert(position, options);
+                inner.recipe.push(ingredient);
+            }
+
+            inner.recipe_need_update = true;
+        }
+
+        inner.learn_result = Some((
+            target,
+            kind.as_str().to_string(),
+            control.to_string(),
+            ingredients
+                .into_iter()
+                .map(|(ingredient, _)| ingredient)
+                .collect(),
+        ));
+    }
+
+    fn take_dmx_params(&self) -> Vec<(String, f32)> {
+        std::mem::take(&mut self.inner.lock().expect("lock not poisoned").dmx_params)
+    }
+
+    /// Apply an OSC command exactly as the Python setters do.
+    fn apply_osc_command(&self, command: OscCommand) {
+        match command {
+            OscCommand::Fader(fader_num, value) => {
+                let mut faders = self.get_faders();
+                match faders.iter_mut().find(|fader| fader.0 == fader_num) {
+                    Some(fader) => fader.1 = value,
+                    None => faders.push((fader_num, value)),
+                }
+                self.set_faders(faders);
+                self.set_devicestate_update(vec![3]);
+            }
+            OscCommand::Lcd(lcd_string) => {
+                self.set_lcd_string(lcd_string);
+                self.set_devicestate_update(vec![1]);
+            }
+            OscCommand::Vpot(vpot_idx, mode, value) => {
+                let mut vpots = self.get_vpots();
+                match vpots.iter_mut().find(|vpot| vpot[0] == vpot_idx) {
+                    Some(vpot) => *vpot = [vpot_idx, mode, value],
+                    None => vpots.push([vpot_idx, mode, value]),
+                }
+                self.set_vpots(vpots);
+                self.set_devicestate_update(vec![2]);
+            }
+            OscCommand::Timecode(timecode) => {
+                self.set_timestamp(timecode[0], timecode[1], timecode[2], timecode[3]);
+            }
+        }
+    }
+}
+
 fn mi_start_server(mibl: &MiBlRustProcess, debug: bool) {
     let (tx_channel_rx, rx_channel_rx) = channel::<Vec<ExtTrigger>>();
-    let (tx_channel_tx, rx_channel_tx) = channel::<Vec<ExtTrigger>>();
-    let (tx_device_state, rx_device_state) = channel::<DeviceState>();
+
+    let binding = |gesture: Option<(String, IngredientOptions)>| {
+        gesture.and_then(
+            |(control, options)| match parse_binding(&control, &options) {
+                Ok(binding) => Some(binding),
+                Err(err) => {
+                    log::warn!(target: RECIPE, "Gesture of {} ignored : {}", control, err);
+                    None
+                }
+            },
+        )
+    };
+    let reset_gesture = binding(mibl.get_reset_gesture());
+    let panic_gesture = binding(mibl.get_panic_gesture());
 
     let int_signal_arc = Arc::new(Mutex::new(SIGflag {
         debug,
         use_sys_event: mibl.get_sysevent(),
+        recipe_options: mibl.get_recipe_options(),
+        modifiers: ModifierState::new(mibl.inner.lock().expect("lock not poisoned").modifier_latch),
+        gestures: GestureRecognizer::new(reset_gesture, panic_gesture),
+        toggle_states: mibl.get_toggle_states(),
+        output_rate: mibl.get_output_rate(),
+        monitor: mibl
+            .inner
+            .lock()
+            .expect("lock not poisoned")
+            .monitor
+            .clone(),
         ..Default::default()
     }));
 
@@ -353,16 +1028,172 @@ fn mi_start_server(mibl: &MiBlRustProcess, debug: bool) {
 
     let mut duration: u64 = 1000 / fps;
 
+    let transport_kind = mibl.get_transport();
+    let (record_path, record_output) = mibl.get_record();
+    let (replay_path, replay_speed) = mibl.get_replay();
+    let (rtp_midi_port, rtp_midi_peer) = mibl.get_rtp_midi();
+    let (loopback_transport, loopback_handle) = LoopbackTransport::new("MiBL loopback");
+    mibl.inner.lock().expect("lock not poisoned").loopback = match transport_kind.as_str() {
+        "loopback" => Some(loopback_handle),
+        _ => None,
+    };
+
+    let triggers_events: Arc<Mutex<Option<Vec<Event>>>> = Arc::new(Mutex::new(None));
+    let triggers_events_clone = Arc::clone(&triggers_events);
+
+    let osc_bridge = match mibl.get_osc() {
+        (Some(listen), target) => match OscBridge::bind(&listen, target.as_deref()) {
+            Ok(osc_bridge) => {
+                if let Ok(addr) = osc_bridge.local_addr() {
+                    log::info!(target: SERVER, "OSC bridge listening on {}", addr);
+                }
+                Some(osc_bridge)
+            }
+            Err(err) => {
+                log::warn!(target: SERVER, "Unable to start OSC bridge, continue : {}", err);
+                None
+            }
+        },
+        (None, _) => None,
+    };
+
+    let dmx_output = match mibl.get_dmx_output() {
+        (Some(protocol), target, refresh_rate) => {
+            let mappings = mibl
+                .get_dmx_mapping()
+                .iter()
+                .filter_map(|(source, universe, channel, wide)| {
+                    DmxMapping::new(source, *universe, *channel, *wide).ok()
+                })
+                .collect();
+
+            match DmxProtocol::from_name(&protocol).and_then(|protocol| {
+                DmxOutput::start(protocol, target.as_deref(), refresh_rate, mappings)
+            }) {
+                Ok(dmx_output) => Some(dmx_output),
+                Err(err) => {
+                    log::warn!(target: SERVER, "Unable to start DMX output, continue : {}", err);
+                    None
+                }
+            }
+        }
+        (None, _, _) => None,
+    };
+
+    let mut dmx_input = match mibl.get_dmx_input() {
+        (Some(protocol), threshold) => {
+            let mappings = mibl
+                .get_dmx_input_mapping()
+                .iter()
+                .filter_map(|(universe, channel, wide, index)| {
+                    DmxMapping::new(&index.to_string(), *universe, *channel, *wide).ok()
+                })
+                .collect();
+
+            match DmxProtocol::from_name(&protocol)
+                .and_then(|protocol| DmxInput::bind(protocol, threshold, mappings))
+            {
+                Ok(dmx_input) => Some(dmx_input),
+                Err(err) => {
+                    log::warn!(target: SERVER, "Unable to start DMX input, continue : {}", err);
+                    None
+                }
+            }
+        }
+        (None, _) => None,
+    };
+
+    let web_listen = mibl.get_web();
+    #[cfg(feature = "websocket")]
+    let inject_handle = InjectHandle::default();
+    #[cfg(feature = "websocket")]
+    let inject_handle_clone = inject_handle.clone();
+
+    #[cfg(feature = "websocket")]
+    let web_bridge = match &web_listen {
+        Some(listen) => match WebBridge::bind(listen) {
+            Ok(web_bridge) => Some(web_bridge),
+            Err(err) => {
+                log::warn!(
+                    target: SERVER,
+                    "Unable to start the virtual control surface, continue : {}",
+                    err
+                );
+                None
+            }
+        },
+        None => None,
+    };
+    #[cfg(feature = "websocket")]
+    let mut last_web_state: Option<DeviceState> = None;
+
+    #[cfg(not(feature = "websocket"))]
+    if web_listen.is_some() {
+        log::warn!(
+            target: SERVER,
+            "Virtual control surface unavailable : built without the websocket feature"
+        );
+    }
+
     let midi_audio_thread = spawn(move || {
         let sender_tx = tx_channel_rx.clone();
-        let sender_device_state = tx_device_state.clone();
+
+        let mut transport: Box<dyn MidiTransport> = match transport_kind.as_str() {
+            "loopback" => Box::new(loopback_transport),
+            "replay" => match ReplayTransport::open(
+                replay_path.as_deref().unwrap_or_default(),
+                replay_speed,
+            ) {
+                Ok(transport) => Box::new(transport),
+                Err(err) => {
+                    log::error!(target: SERVER, "Unable to open the session to replay : {}", err);
+                    return;
+                }
+            },
+            "rtpmidi" => {
+                match RtpMidiTransport::connect("MiBL", rtp_midi_port, rtp_midi_peer.as_deref()) {
+                    Ok(transport) => Box::new(transport),
+                    Err(err) => {
+                        log::error!(
+                            target: SERVER,
+                            "Unable to open RTP-MIDI session, continue : {}",
+                            err
+                        );
+                        return;
+                    }
+                }
+            }
+            _ => match MidirTransport::connect() {
+                Ok(transport) => Box::new(transport),
+                Err(err) => {
+                    log::error!(target: SERVER, "Unable to initialize device, continue : {}", err);
+                    return;
+                }
+            },
+        };
+
+        #[cfg(feature = "websocket")]
+        if web_listen.is_some() {
+            transport = Box::new(InjectingTransport::new(transport, &inject_handle_clone));
+        }
+
+        if let Some(record_path) = record_path {
+            transport = match RecordingTransport::new(transport, &record_path, record_output) {
+                Ok(transport) => Box::new(transport),
+                Err(err) => {
+                    log::error!(target: SERVER, "Unable to record session : {}", err);
+                    return;
+                }
+            };
+        }
 
         init_midi_audio(
+            transport,
             sender_tx,
-            sender_device_state,
             int_signal_arc_clone,
             recipe_arc_clone,
             device_state_clone,
+            triggers_events_clone,
         );
     });
 
@@ -416,11 +1247,35 @@ fn mi_start_server(mibl: &MiBlRustProcess, debug: bool) {
             }
         }
 
+        if std::mem::take(&mut int_signal_arc.lock().unwrap().panic_signal) {
+            mibl.inner.lock().expect("lock not poisoned").panic_pending = true;
+        }
+
+        let toggle_updates =
+            std::mem::take(&mut mibl.inner.lock().expect("lock not poisoned").toggle_updates);
+        let mut sig_flag = int_signal_arc.lock().unwrap();
+        sig_flag.toggle_updates.extend(toggle_updates);
+
+        if std::mem::take(&mut sig_flag.toggles_changed) {
+            mibl.inner.lock().expect("lock not poisoned").toggle_states =
+                sig_flag.toggle_states.clone();
+        }
+        drop(sig_flag);
+
+        let learned = int_signal_arc.lock().unwrap().learned.take();
+
+        if let Some(mesg) = learned {
+            mibl.apply_learned(&mesg);
+        }
+
+        int_signal_arc.lock().unwrap().learn_armed = mibl.get_learn_armed();
+
         if mibl.get_recipe_need_update() {
             let py_recipe = mibl.get_recipe();
-            println!("Get recipe from python : {:?}", py_recipe);
+            log::debug!(target: RECIPE, "Get recipe from python : {:?}", py_recipe);
             *recipe_arc.lock().unwrap() = py_recipe;
             int_signal_arc.lock().unwrap().use_sys_event = mibl.get_sysevent();
+            int_signal_arc.lock().unwrap().recipe_options = mibl.get_recipe_options();
             int_signal_arc.lock().unwrap().update_recipe = true;
             mibl.set_recipe_need_update(false);
         }
@@ -438,13 +1293,64 @@ fn mi_start_server(mibl: &MiBlRustProcess, debug: bool) {
             timestamp_py[3],
         );
 
-        if let Ok(triggers) = rx_channel_rx.try_recv() {
-            mibl.set_triggers(triggers);
+        let mut incoming: Vec<Vec<ExtTrigger>> = rx_channel_rx.try_iter().collect();
+
+        if let Some(dmx_input) = dmx_input.as_mut() {
+            let dmx_triggers = dmx_input.poll();
+
+            if !dmx_triggers.is_empty() {
+                incoming.push(dmx_triggers);
+            }
+        }
+
+        // Python gets the triggers of the last tick, like before the bridges
+        let mut tick_triggers: Vec<ExtTrigger> = vec![];
+
+        for triggers in incoming {
+            if let Some(osc_bridge) = &osc_bridge {
+                osc_bridge.publish_triggers(&triggers, &triggers_events.lock().unwrap());
+            }
+
+            if let Some(dmx_output) = &dmx_output {
+                dmx_output.apply_triggers(&triggers, &triggers_events.lock().unwrap());
+            }
+
+            #[cfg(feature = "websocket")]
+            if let Some(web_bridge) = &web_bridge {
+                web_bridge.publish_triggers(&triggers, &triggers_events.lock().unwrap());
+            }
+
+            tick_triggers.extend(triggers);
         }
 
-        if let Ok(device_state) = rx_device_state.try_recv() {
-            println!("Hey !");
-            println!("{:?}", device_state);
+        if !tick_triggers.is_empty() {
+            mibl.set_triggers(tick_triggers);
+        }
+
+        if let Some(osc_bridge) = &osc_bridge {
+            for command in osc_bridge.poll() {
+                mibl.apply_osc_command(command);
+            }
+        }
+
+        for (name, value) in mibl.take_dmx_params() {
+            if let Some(dmx_output) = &dmx_output {
+                dmx_output.set_param(&name, value);
+            }
+        }
+
+        #[cfg(feature = "websocket")]
+        if let Some(web_bridge) = &web_bridge {
+            let current_state = device_state.lock().unwrap().clone();
+
+            if last_web_state.as_ref() != Some(&current_state) {
+                web_bridge.publish_state(&current_state);
+                last_web_state = Some(current_state);
+            }
+
+            for input in web_bridge.poll() {
+                inject_handle.inject(0, &input.to_midi());
+            }
         }
 
         sleep(Duration::from_millis(duration));
@@ -559,11 +1465,96 @@ fn mibl_get_sys_event_len() -> usize {
     node_utils::sys_event::get_sys_event_len()
 }
 
+// CONTROL FUNCTIONS
+#[pyfunction]
+fn mibl_control_from_midi(mesg: Vec<u8>) -> Option<String> {
+    Control::from_midi(&mesg).map(|control| control.to_string())
+}
+
+#[pyfunction]
+fn mibl_control_to_midi(name: String, value: f32) -> PyResult<Option<Vec<u8>>> {
+    let control: Control = name
+        .parse()
+        .map_err(pyo3::exceptions::PyValueError::new_err)?;
+    Ok(control.to_midi(value))
+}
+
+#[pyfunction]
+fn mibl_control_ingredients(
+    name: String,
+    outputs: Vec<Vec<u8>>,
+    value: Option<f32>,
+) -> PyResult<Recipe> {
+    let control: Control = name
+        .parse()
+        .map_err(pyo3::exceptions::PyValueError::new_err)?;
+    Ok(control.ingredients(outputs, value))
+}
+
+// HIGH RESOLUTION FUNCTIONS
+/// The messages setting the 14-bit CC, NRPN or RPN waited for by `ingredient` to `value`.
+#[pyfunction]
+fn mibl_hires_to_midi(ingredient: Vec<u8>, value: f32) -> PyResult<Vec<Vec<u8>>> {
+    match HiResParam::from_ingredient(&ingredient) {
+        Some(param) => Ok(param.to_midi(value)),
+        None => Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "Not a 14-bit CC, NRPN or RPN ingredient : {:X?}",
+            ingredient
+        ))),
+    }
+}
+
+// LOG FUNCTIONS
+/// Set the log level ("off", "error", "warn", "info", "debug" or "trace") of a target
+/// ("mibl::server", "mibl::input", "mibl::output", "mibl::recipe", "mibl::timing"), or of all
+/// targets if `target` is `None`.
+#[pyfunction]
+#[pyo3(signature = (level, target=None))]
+fn mibl_set_log_level(level: String, target: Option<String>) -> PyResult<()> {
+    logger::set_level(target.as_deref(), &level).map_err(pyo3::exceptions::PyValueError::new_err)
+}
+
+/// The log level of each target.
+#[pyfunction]
+fn mibl_get_log_levels() -> Vec<(String, String)> {
+    logger::levels()
+        .into_iter()
+        .map(|(target, level)| (target.to_string(), level.to_string().to_lowercase()))
+        .collect()
+}
+
+/// The records waiting, (Python `logging` level, target, message).
+#[pyfunction]
+fn mibl_drain_logs() -> Vec<(u8, String, String)> {
+    logger::drain()
+        .into_iter()
+        .map(|(level, target, message)| (logger::python_level(level), target, message))
+        .collect()
+}
+
+/// Send the records waiting to Python `logging`, the logger of "mibl::input" is "mibl.input".
+/// Return the number of records sent.
+#[pyfunction]
+fn mibl_forward_logs(py: Python) -> PyResult<usize> {
+    let logging = py.import("logging")?;
+    let records = logger::drain();
+
+    for (level, target, message) in records.iter() {
+        logging
+            .call_method1("getLogger", (target.replace("::", "."),))?
+            .call_method1("log", (logger::python_level(*level), message))?;
+    }
+
+    Ok(records.len())
+}
+
 /// A Python module implemented in Rust. The name of this function must match
 /// the `lib.name` setting in the `Cargo.toml`, else Python will not be able to
 /// import the module.
 #[pymodule]
 fn mibllib(_py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
+    logger::init();
+
     m.add_class::<MiBlRustProcess>()?;
     // MATH FUNCTION
     m.add_function(wrap_pyfunction!(mibl_add, m)?)?;
@@ -586,5 +1577,16 @@ fn mibllib(_py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(mibl_map_range, m)?)?;
     m.add_function(wrap_pyfunction!(mibl_get_event_by_index, m)?)?;
     m.add_function(wrap_pyfunction!(mibl_get_sys_event_len, m)?)?;
+    // CONTROL FUNCTION
+    m.add_function(wrap_pyfunction!(mibl_control_from_midi, m)?)?;
+    m.add_function(wrap_pyfunction!(mibl_control_to_midi, m)?)?;
+    m.add_function(wrap_pyfunction!(mibl_control_ingredients, m)?)?;
+    // HIGH RESOLUTION FUNCTION
+    m.add_function(wrap_pyfunction!(mibl_hires_to_midi, m)?)?;
+    // LOG FUNCTION
+    m.add_function(wrap_pyfunction!(mibl_set_log_level, m)?)?;
+    m.add_function(wrap_pyfunction!(mibl_get_log_levels, m)?)?;
+    m.add_function(wrap_pyfunction!(mibl_drain_logs, m)?)?;
+    m.add_function(wrap_pyfunction!(mibl_forward_logs, m)?)?;
     Ok(())
 }
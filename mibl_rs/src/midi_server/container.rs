@@ -1,49 +1,37 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use crate::midi_server::curve::Transfer;
+use crate::midi_server::dispatch::DispatchTable;
+use crate::midi_server::gesture::{Gesture, GestureRecognizer};
+use crate::midi_server::hires::HiResTracker;
+use crate::midi_server::modifier::{ModifierSet, ModifierState};
+use crate::midi_server::monitor::MonitorHandle;
+use crate::midi_server::smoothing::{Smoother, Smoothing};
+
 pub const MAX_MIDI_MSG_SIZE: usize = 16;
 
 pub type Ingredient = (Vec<u8>, Vec<Vec<u8>>, Option<f32>);
 pub type Recipe = Vec<Ingredient>;
+pub type IngredientOptions = HashMap<String, String>; // e.g. "mode" => "toggle"
+pub type RecipeOptions = HashMap<usize, IngredientOptions>; // ingredient position => options
 pub type ExtTrigger = (u64, f32);
-pub type WaitData = (u64, Vec<Vec<u8>>);
+pub type ToggleStates = BTreeMap<u64, bool>; // event index => on
 pub type TriggerResult = (Option<Vec<RawMidi>>, Option<Vec<ExtTrigger>>);
 
-#[derive(Debug, Clone, Copy, Default)]
-pub struct CCflag {
-    pub cc_lsb_flag: bool,
-    pub cc_channel: u8,
-    pub cc_num: u8,
-    pub cc_msb_value: u8,
-    pub cc_note: u8,
-}
-
-impl CCflag {
-    pub fn new(
-        cc_lsb_flag: bool,
-        cc_channel: u8,
-        cc_num: u8,
-        cc_msb_value: u8,
-        cc_note: u8,
-    ) -> Self {
-        Self {
-            cc_lsb_flag,
-            cc_channel,
-            cc_num,
-            cc_msb_value,
-            cc_note,
-        }
-    }
-}
-
 #[derive(Debug, Clone, Default)]
 pub struct SIGflag {
-    pub reset_signal: bool,
     pub note_on: bool,
     pub note_bang: bool,
     pub note_bang_value: u8,
-    pub note_led_on: Vec<u8>,
+    pub toggle_states: ToggleStates,
+    pub toggle_updates: Vec<(u64, bool)>,
+    pub toggles_changed: bool,
     pub note_toggle: u8,
     pub note_need_toggle: bool,
-    pub cc_flag: CCflag,
+    pub hires: HiResTracker,
     pub update_recipe: bool,
+    pub dispatch: Arc<DispatchTable>, // rebuilt with the trigger events
     pub update_lcd_vec: bool,
     pub update_lcd_string: bool,
     pub update_vpot: bool,
@@ -52,17 +40,31 @@ pub struct SIGflag {
     pub update_fps: bool,
     pub stop_thread: bool,
     pub use_sys_event: bool,
+    pub recipe_options: RecipeOptions,
+    pub learn_armed: bool,
+    pub learned: Option<Vec<u8>>,
+    pub gestures: GestureRecognizer,
+    pub modifiers: ModifierState,
+    pub smoother: Smoother,
+    pub output_rate: u32,   // bytes by second to the device, 0 for no cap
+    pub output_reset: bool, // the output stage forgets what the surface shows
+    pub monitor: MonitorHandle,
+    pub panic_signal: bool,
     pub debug: bool,
 }
 
+/// A MIDI message stored inline, so it is built and copied without allocation. Only a SysEx
+/// longer than `MAX_MIDI_MSG_SIZE` is stored on the heap.
 #[derive(Clone)]
 pub struct RawMidi {
     /// The amount of time passed, in frames, relative to the start of the process cycle.
     pub delta_frames: u64, // stamp
 
-    data: Vec<u8>,
+    data: [u8; MAX_MIDI_MSG_SIZE],
 
     len: u8,
+
+    sysex: Option<Box<[u8]>>,
 }
 
 impl RawMidi {
@@ -71,13 +73,25 @@ impl RawMidi {
     /// * `delta_frames` - The amount of time passed, in frames, relative to the start of the process cycle.
     /// * `data` - The raw bytes of the midi message.
     ///
-    /// This returns an error if the length of `data` is greater than `MAX_MIDI_MSG_SIZE` (16).
+    /// This returns an error if the length of `data` is greater than `MAX_MIDI_MSG_SIZE` (16),
+    /// unless `data` is a SysEx.
     pub fn new(delta_frames: u64, data: &[u8]) -> Result<Self, usize> {
         if data.len() <= MAX_MIDI_MSG_SIZE {
+            let mut inline = [0; MAX_MIDI_MSG_SIZE];
+            inline[..data.len()].copy_from_slice(data);
+
             Ok(Self {
                 delta_frames,
-                data: data.to_vec(),
+                data: inline,
                 len: data.len() as u8,
+                sysex: None,
+            })
+        } else if data.first() == Some(&0xF0) {
+            Ok(Self {
+                delta_frames,
+                data: [0; MAX_MIDI_MSG_SIZE],
+                len: 0,
+                sysex: Some(data.into()),
             })
         } else {
             Err(data.len())
@@ -86,7 +100,10 @@ impl RawMidi {
 
     /// The raw midi data.
     pub fn data(&self) -> &[u8] {
-        &self.data[0..usize::from(self.len)]
+        match &self.sysex {
+            Some(sysex) => sysex,
+            None => &self.data[0..usize::from(self.len)],
+        }
     }
 
     /// Delta frames.
@@ -129,8 +146,8 @@ impl std::fmt::Debug for RawMidi {
             f,
             "Raw MIDI: {{ delta frames: {}, len: {}, data: {:X?} }}",
             self.delta_frames,
-            self.len,
-            &self.data[0..usize::from(self.len)]
+            self.data().len(),
+            self.data()
         )
     }
 }
@@ -139,8 +156,9 @@ impl Default for RawMidi {
     fn default() -> Self {
         RawMidi {
             delta_frames: 0,
-            data: vec![0; MAX_MIDI_MSG_SIZE],
+            data: [0; MAX_MIDI_MSG_SIZE],
             len: 0,
+            sysex: None,
         }
     }
 }
@@ -156,9 +174,15 @@ pub struct Event {
     mod_amount: Option<f32>, // Increase/Descrease by
     note_bang: bool,
     toggable: bool,
+    gesture: Option<Gesture>,
+    modifiers: Option<ModifierSet>,
+    group: Option<u64>, // trigger index of the exclusive group
+    transfer: Option<Transfer>,
+    smoothing: Option<Smoothing>,
 }
 
 impl Event {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         index: u64,
         name: String,
@@ -181,6 +205,11 @@ impl Event {
                 mod_amount,
                 note_bang,
                 toggable,
+                gesture: None,
+                modifiers: None,
+                group: None,
+                transfer: None,
+                smoothing: None,
             })
         } else {
             Err("mod_rule must be one of the following value : 0,1,2".to_string())
@@ -203,11 +232,12 @@ impl Event {
         &self.mesg_out
     }
 
+    #[allow(dead_code)]
     pub fn get_mod_amount(&self) -> Option<f32> {
         match self.mod_amount {
             Some(amount) => match self.mod_rule {
                 0 | 1 => Some(amount),
-                2 => Some(amount * -1.0),
+                2 => Some(-amount),
                 _ => None,
             },
             None => None,
@@ -225,6 +255,56 @@ impl Event {
     pub fn get_toggable(&self) -> bool {
         self.toggable
     }
+
+    /// An event with a gesture is triggered by the `GestureRecognizer` only.
+    pub fn with_gesture(mut self, gesture: Option<Gesture>) -> Self {
+        self.gesture = gesture;
+        self
+    }
+
+    pub fn get_gesture(&self) -> Option<&Gesture> {
+        self.gesture.as_ref()
+    }
+
+    /// The modifier layer of the event, `None` for no layer.
+    pub fn with_modifiers(mut self, modifiers: Option<ModifierSet>) -> Self {
+        self.modifiers = modifiers;
+        self
+    }
+
+    pub fn get_modifiers(&self) -> Option<ModifierSet> {
+        self.modifiers
+    }
+
+    /// The exclusive group of the event (see `src/midi_server/group.rs`), `None` for no group.
+    pub fn with_group(mut self, group: Option<u64>) -> Self {
+        self.group = group;
+        self
+    }
+
+    pub fn get_group(&self) -> Option<u64> {
+        self.group
+    }
+
+    /// The range and curve applied to the device values (see `src/midi_server/curve.rs`).
+    pub fn with_transfer(mut self, transfer: Option<Transfer>) -> Self {
+        self.transfer = transfer;
+        self
+    }
+
+    pub fn get_transfer(&self) -> Option<&Transfer> {
+        self.transfer.as_ref()
+    }
+
+    /// The smoothing of the device values (see `src/midi_server/smoothing.rs`).
+    pub fn with_smoothing(mut self, smoothing: Option<Smoothing>) -> Self {
+        self.smoothing = smoothing;
+        self
+    }
+
+    pub fn get_smoothing(&self) -> Option<Smoothing> {
+        self.smoothing
+    }
 }
 
 impl Default for Event {
@@ -239,6 +319,11 @@ impl Default for Event {
             mod_amount: None,
             note_bang: false,
             toggable: false,
+            gesture: None,
+            modifiers: None,
+            group: None,
+            transfer: None,
+            smoothing: None,
         }
     }
 }
@@ -274,7 +359,7 @@ impl std::fmt::Debug for Event {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct DeviceState {
     timestamp: [usize; 4],
     lcd_vec: Option<Vec<(u8, u8, String)>>,
@@ -286,6 +371,7 @@ pub struct DeviceState {
 }
 
 impl DeviceState {
+    #[allow(dead_code)]
     pub fn new(
         timestamp: [usize; 4],
         lcd_vec: Option<Vec<(u8, u8, String)>>, // (lcd_#, line_#, Message)
@@ -436,6 +522,7 @@ impl MidiMesg {
 pub struct MidiProcess {
     pub debug: Option<MidiMesg>,
     pub to_send: TriggerResult,
+    pub matched: Vec<u64>, // index of the events triggered
 }
 
 pub type MidiResult = Result<MidiProcess, &'static str>;
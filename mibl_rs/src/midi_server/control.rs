@@ -0,0 +1,281 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::midi_server::container::Ingredient;
+use crate::midi_server::midi_send_mesg::convert_value_to_lsb_msb;
+use crate::midi_server::sys_event::SYS_EVENT_ARRAY;
+
+// CONTROL NAMES (strips are numbered from 1 to 8, like on the device)
+//   strip<n>.rec | .solo | .mute | .select    Notes 0x00->0x1F
+//   strip<n>.vpot                              CC 0x10->0x17 (relative), LED ring CC 0x30->0x37
+//   strip<n>.vpot.push                         Notes 0x20->0x27
+//   strip<n>.fader | master.fader              Pitch bend 0xE0->0xE8
+//   strip<n>.fader.touch | master.fader.touch  Notes 0x68->0x70
+//   flip                                       Note 0x32
+//   jog                                        CC 0x3C (relative)
+//   transport.<key>                            Notes 0x54->0x5F, 0x65
+//   button.<sys event name>                    Any other button of SYS_EVENT_ARRAY (e.g. "button.func_f1")
+
+const STRIPS: u8 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransportKey {
+    Marker,
+    Nudge,
+    Cycle,
+    Drop,
+    Replace,
+    Click,
+    Solo,
+    Prev,
+    Next,
+    Stop,
+    Play,
+    Rec,
+    Scrub,
+}
+
+const TRANSPORT_KEYS: [(TransportKey, u8, &str); 13] = [
+    (TransportKey::Marker, 0x54, "marker"),
+    (TransportKey::Nudge, 0x55, "nudge"),
+    (TransportKey::Cycle, 0x56, "cycle"),
+    (TransportKey::Drop, 0x57, "drop"),
+    (TransportKey::Replace, 0x58, "replace"),
+    (TransportKey::Click, 0x59, "click"),
+    (TransportKey::Solo, 0x5A, "solo"),
+    (TransportKey::Prev, 0x5B, "prev"),
+    (TransportKey::Next, 0x5C, "next"),
+    (TransportKey::Stop, 0x5D, "stop"),
+    (TransportKey::Play, 0x5E, "play"),
+    (TransportKey::Rec, 0x5F, "rec"),
+    (TransportKey::Scrub, 0x65, "scrub"),
+];
+
+/// A control of a Mackie Control device, strips are indexed from 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Control {
+    Rec(u8),
+    Solo(u8),
+    Mute(u8),
+    Select(u8),
+    VPot(u8),
+    VPotPush(u8),
+    Fader(u8), // 8 = master
+    FaderTouch(u8),
+    Flip,
+    Jog,
+    Transport(TransportKey),
+    Button(u8), // note
+}
+
+impl Control {
+    /// The control sending `mesg` (note on/off, pitch bend or relative CC).
+    pub fn from_midi(mesg: &[u8]) -> Option<Control> {
+        if mesg.len() < 2 {
+            return None;
+        }
+
+        match (mesg[0], mesg[1]) {
+            (0x80 | 0x90, note) => Control::from_note(note),
+            (0xE0..=0xE8, _) => Some(Control::Fader(mesg[0] & 0x0F)),
+            (0xB0, 0x10..=0x17) => Some(Control::VPot(mesg[1] & 0x07)),
+            (0xB0, 0x3C) => Some(Control::Jog),
+            _ => None,
+        }
+    }
+
+    fn from_note(note: u8) -> Option<Control> {
+        match note {
+            0x00..=0x07 => Some(Control::Rec(note)),
+            0x08..=0x0F => Some(Control::Solo(note - 0x08)),
+            0x10..=0x17 => Some(Control::Mute(note - 0x10)),
+            0x18..=0x1F => Some(Control::Select(note - 0x18)),
+            0x20..=0x27 => Some(Control::VPotPush(note - 0x20)),
+            0x32 => Some(Control::Flip),
+            0x68..=0x70 => Some(Control::FaderTouch(note - 0x68)),
+            _ => match TRANSPORT_KEYS.iter().find(|(_, value, _)| *value == note) {
+                Some((key, _, _)) => Some(Control::Transport(*key)),
+                None => SYS_EVENT_ARRAY
+                    .iter()
+                    .any(|(value, _)| *value == note)
+                    .then_some(Control::Button(note)),
+            },
+        }
+    }
+
+    /// The note of a button, `None` for faders, V-Pots and jog wheel.
+    pub fn note(&self) -> Option<u8> {
+        match *self {
+            Control::Rec(strip) => Some(strip),
+            Control::Solo(strip) => Some(0x08 + strip),
+            Control::Mute(strip) => Some(0x10 + strip),
+            Control::Select(strip) => Some(0x18 + strip),
+            Control::VPotPush(strip) => Some(0x20 + strip),
+            Control::FaderTouch(strip) => Some(0x68 + strip),
+            Control::Flip => Some(0x32),
+            Control::Transport(key) => TRANSPORT_KEYS
+                .iter()
+                .find(|(transport_key, _, _)| *transport_key == key)
+                .map(|(_, note, _)| *note),
+            Control::Button(note) => Some(note),
+            Control::VPot(_) | Control::Fader(_) | Control::Jog => None,
+        }
+    }
+
+    /// Channel buttons and flip are note bangs : the event waits for the press and the release.
+    pub fn is_bang(&self) -> bool {
+        matches!(
+            self,
+            Control::Rec(_)
+                | Control::Solo(_)
+                | Control::Mute(_)
+                | Control::Select(_)
+                | Control::Flip
+        )
+    }
+
+    pub fn is_relative(&self) -> bool {
+        matches!(self, Control::VPot(_) | Control::Jog)
+    }
+
+    /// The ingredients waiting for this control, relative controls give one ingredient by
+    /// direction (`value` clockwise, `-value` counterclockwise).
+    pub fn ingredients(&self, outputs: Vec<Vec<u8>>, value: Option<f32>) -> Vec<Ingredient> {
+        match (*self, self.note()) {
+            (Control::Fader(fader), _) => vec![(vec![0xE0 | fader, 0x00, 0x00], outputs, None)],
+            (Control::VPot(strip), _) => relative_ingredients(0x10 + strip, outputs, value),
+            (Control::Jog, _) => relative_ingredients(0x3C, outputs, value),
+            (control, Some(note)) if control.is_bang() => {
+                vec![(vec![0x90, note, 0x7F, 0x80, note, 0x00], outputs, value)]
+            }
+            (_, note) => vec![(vec![0x90, note.unwrap_or_default(), 0x7F], outputs, value)],
+        }
+    }
+
+    /// The message to send to the device to show `value` (0.0 to 1.0) : button LED, fader
+    /// position or V-Pot LED ring. `None` for the jog wheel.
+    pub fn to_midi(self, value: f32) -> Option<Vec<u8>> {
+        let value = value.clamp(0.0, 1.0);
+
+        match self {
+            Control::Fader(fader) => {
+                let (lsb, msb) = convert_value_to_lsb_msb(value);
+                Some(vec![0xE0 | fader, lsb, msb])
+            }
+            Control::VPot(strip) => Some(vec![0xB0, 0x30 + strip, (value * 11.0).round() as u8]),
+            Control::Jog => None,
+            _ => self
+                .note()
+                .map(|note| vec![0x90, note, if value >= 0.5 { 0x7F } else { 0x00 }]),
+        }
+    }
+}
+
+fn relative_ingredients(cc: u8, outputs: Vec<Vec<u8>>, value: Option<f32>) -> Vec<Ingredient> {
+    let value = value.unwrap_or(1.0);
+
+    vec![
+        (vec![0xB0, cc, 0x01], outputs.clone(), Some(value)),
+        (vec![0xB0, cc, 0x41], outputs, Some(-value)),
+    ]
+}
+
+impl fmt::Display for Control {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let strip = |strip: &u8, name: &str| match strip {
+            8 => format!("master.{}", name),
+            _ => format!("strip{}.{}", strip + 1, name),
+        };
+
+        let name = match self {
+            Control::Rec(n) => strip(n, "rec"),
+            Control::Solo(n) => strip(n, "solo"),
+            Control::Mute(n) => strip(n, "mute"),
+            Control::Select(n) => strip(n, "select"),
+            Control::VPot(n) => strip(n, "vpot"),
+            Control::VPotPush(n) => strip(n, "vpot.push"),
+            Control::Fader(n) => strip(n, "fader"),
+            Control::FaderTouch(n) => strip(n, "fader.touch"),
+            Control::Flip => "flip".to_string(),
+            Control::Jog => "jog".to_string(),
+            Control::Transport(key) => format!(
+                "transport.{}",
+                TRANSPORT_KEYS
+                    .iter()
+                    .find(|(transport_key, _, _)| transport_key == key)
+                    .map(|(_, _, name)| *name)
+                    .unwrap_or_default()
+            ),
+            Control::Button(note) => format!(
+                "button.{}",
+                SYS_EVENT_ARRAY
+                    .iter()
+                    .find(|(value, _)| value == note)
+                    .map(|(_, name)| name.to_lowercase())
+                    .unwrap_or_else(|| format!("{:02x}", note))
+            ),
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Control {
+    type Err = String;
+
+    /// Parse a control name, *e.g* "strip3.mute", "master.fader", "transport.play"
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let name = name.trim().to_lowercase();
+        let unknown = || format!("Unknown control : {}", name);
+
+        if let Some(key) = name.strip_prefix("transport.") {
+            return TRANSPORT_KEYS
+                .iter()
+                .find(|(_, _, key_name)| *key_name == key)
+                .map(|(key, _, _)| Control::Transport(*key))
+                .ok_or_else(unknown);
+        }
+
+        if let Some(button) = name.strip_prefix("button.") {
+            return SYS_EVENT_ARRAY
+                .iter()
+                .find(|(_, sys_name)| sys_name.to_lowercase() == button)
+                .map(|(note, _)| Control::Button(*note))
+                .ok_or_else(unknown);
+        }
+
+        match name.as_str() {
+            "flip" => return Ok(Control::Flip),
+            "jog" => return Ok(Control::Jog),
+            _ => (),
+        }
+
+        let (strip, part) = name.split_once('.').ok_or_else(unknown)?;
+        let strip = match strip {
+            "master" => STRIPS,
+            _ => match strip.strip_prefix("strip").map(str::parse::<u8>) {
+                Some(Ok(n)) if (1..=STRIPS).contains(&n) => n - 1,
+                _ => return Err(unknown()),
+            },
+        };
+
+        let control = match part {
+            "rec" => Control::Rec(strip),
+            "solo" => Control::Solo(strip),
+            "mute" => Control::Mute(strip),
+            "select" => Control::Select(strip),
+            "vpot" => Control::VPot(strip),
+            "vpot.push" => Control::VPotPush(strip),
+            "fader" => Control::Fader(strip),
+            "fader.touch" => Control::FaderTouch(strip),
+            _ => return Err(unknown()),
+        };
+
+        // Only the fader exists on the master strip
+        match control {
+            Control::Fader(_) | Control::FaderTouch(_) => Ok(control),
+            _ if strip < STRIPS => Ok(control),
+            _ => Err(unknown()),
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use crate::midi_server::container::{Event, IngredientOptions};
+
+// VALUE TRANSFER
+// Declared per ingredient in the recipe options, applied to the values sent by the device
+// (faders, velocities, CC) before the trigger is sent to Python :
+//   {"min": "-1", "max": "1"}                Output range (default 0 to 1)
+//   {"invert": "true"}                       1 -> 0
+//   {"curve": "linear"}                      Default
+//   {"curve": "log"}                         Fast start, log10(1 + 9x)
+//   {"curve": "exp"}                         Slow start, (10^x - 1) / 9
+//   {"curve": "s_curve"}                     Smoothstep, 3x² - 2x³
+//   {"curve": "stepped", "steps": "5"}       Quantised on "steps" values (default 8)
+//   {"curve": "table", "table": "0:0,0.5:0.8,1:1"}   Breakpoints x:y, linear between them
+// The inverse transfer gives the fader position of a value pushed by Python.
+
+const STEPS: u32 = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Curve {
+    Linear,
+    Log,
+    Exp,
+    Smoothstep, // S-curve
+    Stepped(u32),
+    Table(Vec<(f32, f32)>), // sorted by x
+}
+
+impl Curve {
+    fn apply(&self, x: f32) -> f32 {
+        match self {
+            Curve::Linear => x,
+            Curve::Log => (1.0 + 9.0 * x).log10(),
+            Curve::Exp => (10f32.powf(x) - 1.0) / 9.0,
+            Curve::Smoothstep => x * x * (3.0 - 2.0 * x),
+            Curve::Stepped(steps) => quantise(x, *steps),
+            Curve::Table(table) => interpolate(table.iter().copied(), x),
+        }
+    }
+
+    fn inverse(&self, y: f32) -> f32 {
+        match self {
+            Curve::Linear => y,
+            Curve::Log => (10f32.powf(y) - 1.0) / 9.0,
+            Curve::Exp => (1.0 + 9.0 * y).log10(),
+            Curve::Smoothstep => 0.5 - ((1.0 - 2.0 * y).asin() / 3.0).sin(),
+            Curve::Stepped(steps) => quantise(y, *steps),
+            Curve::Table(table) => {
+                let mut swapped: Vec<(f32, f32)> = table.iter().map(|(x, y)| (*y, *x)).collect();
+                swapped.sort_by(|a, b| a.0.total_cmp(&b.0));
+                interpolate(swapped.into_iter(), y)
+            }
+        }
+    }
+}
+
+fn quantise(x: f32, steps: u32) -> f32 {
+    let intervals = (steps.max(2) - 1) as f32;
+    (x * intervals).round() / intervals
+}
+
+/// Linear interpolation between the breakpoints, constant out of them.
+fn interpolate(mut points: impl Iterator<Item = (f32, f32)>, x: f32) -> f32 {
+    let Some(mut previous) = points.next() else {
+        return x;
+    };
+
+    if x <= previous.0 {
+        return previous.1;
+    }
+
+    for point in points {
+        if x <= point.0 {
+            let span = point.0 - previous.0;
+
+            return match span > 0.0 {
+                true => previous.1 + (point.1 - previous.1) * (x - previous.0) / span,
+                false => point.1,
+            };
+        }
+
+        previous = point;
+    }
+
+    previous.1
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transfer {
+    min: f32,
+    max: f32,
+    invert: bool,
+    curve: Curve,
+}
+
+impl Transfer {
+    /// The transfer declared in the options of an ingredient, if any.
+    pub fn from_options(options: &IngredientOptions) -> Result<Option<Transfer>, String> {
+        if !["min", "max", "invert", "curve"]
+            .iter()
+            .any(|key| options.contains_key(*key))
+        {
+            return Ok(None);
+        }
+
+        let number = |key: &str, default: f32| -> Result<f32, String> {
+            match options.get(key) {
+                Some(value) => value
+                    .trim()
+                    .parse::<f32>()
+                    .map_err(|err| format!("Bad {} {} : {}", key, value, err)),
+                None => Ok(default),
+            }
+        };
+
+        let curve = match options.get("curve").map(String::as_str) {
+            None | Some("linear") => Curve::Linear,
+            Some("log") => Curve::Log,
+            Some("exp") => Curve::Exp,
+            Some("s_curve") => Curve::Smoothstep,
+            Some("stepped") => Curve::Stepped(match options.get("steps") {
+                Some(steps) => steps
+                    .trim()
+                    .parse::<u32>()
+                    .map_err(|err| format!("Bad steps {} : {}", steps, err))?,
+                None => STEPS,
+            }),
+            Some("table") => {
+                let table = options
+                    .get("table")
+                    .ok_or("Table curve without \"table\"")?;
+                let mut points = table
+                    .split(',')
+                    .map(|point| {
+                        let (x, y) = point
+                            .split_once(':')
+                            .ok_or(format!("Bad breakpoint {}, x:y expected", point))?;
+                        let parse = |value: &str| {
+                            value
+                                .trim()
+                                .parse::<f32>()
+                                .map_err(|err| format!("Bad breakpoint {} : {}", point, err))
+                        };
+
+                        Ok((parse(x)?, parse(y)?))
+                    })
+                    .collect::<Result<Vec<(f32, f32)>, String>>()?;
+
+                points.sort_by(|a, b| a.0.total_cmp(&b.0));
+                Curve::Table(points)
+            }
+            Some(other) => return Err(format!("Unknown curve : {}", other)),
+        };
+
+        Ok(Some(Transfer {
+            min: number("min", 0.0)?,
+            max: number("max", 1.0)?,
+            invert: options.get("invert").is_some_and(|invert| invert == "true"),
+            curve,
+        }))
+    }
+
+    /// Device value (0.0 to 1.0) => value sent to Python.
+    pub fn apply(&self, value: f32) -> f32 {
+        let value = value.clamp(0.0, 1.0);
+        let value = if self.invert { 1.0 - value } else { value };
+
+        self.min + (self.max - self.min) * self.curve.apply(value)
+    }
+
+    /// Value pushed by Python => device value (0.0 to 1.0).
+    pub fn inverse(&self, value: f32) -> f32 {
+        let range = self.max - self.min;
+        let value = match range != 0.0 {
+            true => ((value - self.min) / range).clamp(0.0, 1.0),
+            false => 0.0,
+        };
+        let value = self.curve.inverse(value).clamp(0.0, 1.0);
+
+        if self.invert {
+            1.0 - value
+        } else {
+            value
+        }
+    }
+}
+
+/// The position of `fader` showing `value` pushed by Python, through the inverse transfer
+/// of its event.
+pub fn fader_position(events: &Option<Vec<Event>>, fader: u8, value: f32) -> f32 {
+    events
+        .iter()
+        .flatten()
+        .find(|event| event.get_mesg_in().first() == Some(&(0xE0 + fader)))
+        .and_then(Event::get_transfer)
+        .map_or(value, |transfer| transfer.inverse(value))
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+
+use crate::midi_server::container::Event;
+use crate::midi_server::hires::HiResParam;
+
+// TRIGGER DISPATCH
+// The events are indexed by (status, channel, data1) when the recipe is crafted, so an incoming
+// message is only compared to the events of its bucket instead of the whole trigger vector :
+//   Note on/off (and note bangs)   (0x90, channel, note)
+//   Pitch bend                     (0xE0, channel, 0)        any value
+//   Control change                 (0xB0, channel, cc)
+//   14-bit CC n                    (0xB0, channel, n) and (0xB0, channel, n + 32)
+//   NRPN / RPN                     (0xB0, channel, 0x06 | 0x26 | 0x60 | 0x61), the data entry
+//   Channel pressure               (0xD0, channel, 0)
+// The positions in a bucket keep the order of the trigger vector.
+
+pub type DispatchKey = (u8, u8, u8);
+
+/// The bucket of a message (or of the message waited for by an event).
+pub fn dispatch_key(mesg: &[u8]) -> Option<DispatchKey> {
+    let status = *mesg.first()?;
+    let data1 = mesg.get(1).copied().unwrap_or_default();
+
+    match (status & 0xF0, status & 0x0F) {
+        (0x80 | 0x90, channel) => Some((0x90, channel, data1)),
+        (0xE0, channel) => Some((0xE0, channel, 0)),
+        (0xD0, channel) => Some((0xD0, channel, 0)),
+        (0xF0, _) => Some((status, 0, 0)),
+        (command, channel) => Some((command, channel, data1)),
+    }
+}
+
+/// The buckets of an event.
+fn event_keys(event: &Event) -> Vec<DispatchKey> {
+    let mesg_in = event.get_mesg_in();
+
+    match HiResParam::from_ingredient(mesg_in) {
+        Some(HiResParam::Cc14(channel, cc)) => {
+            vec![(0xB0, channel, cc), (0xB0, channel, cc + 0x20)]
+        }
+        Some(HiResParam::Nrpn(channel, _) | HiResParam::Rpn(channel, _)) => {
+            [0x06, 0x26, 0x60, 0x61]
+                .iter()
+                .map(|cc| (0xB0, channel, *cc))
+                .collect()
+        }
+        None => dispatch_key(mesg_in).into_iter().collect(),
+    }
+}
+
+/// Positions in the trigger vector by bucket, stored in `SIGflag` and rebuilt with the recipe.
+#[derive(Debug, Clone, Default)]
+pub struct DispatchTable {
+    buckets: HashMap<DispatchKey, Vec<usize>>,
+}
+
+impl DispatchTable {
+    pub fn new(events: &Option<Vec<Event>>) -> Self {
+        let mut buckets: HashMap<DispatchKey, Vec<usize>> = HashMap::new();
+
+        for (position, event) in events.iter().flatten().enumerate() {
+            for key in event_keys(event) {
+                buckets.entry(key).or_default().push(position);
+            }
+        }
+
+        DispatchTable { buckets }
+    }
+
+    /// The positions of the events which may be triggered by `mesg`.
+    pub fn candidates(&self, mesg: &[u8]) -> &[usize] {
+        dispatch_key(mesg)
+            .and_then(|key| self.buckets.get(&key))
+            .map_or(&[], Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi_server::hires::HiResTracker;
+    use crate::midi_server::midi_event::craft_recipe;
+
+    /// The sys events with faders, notes, a note bang, a V-Pot, a 14-bit CC, an NRPN and an RPN.
+    fn events() -> Vec<Event> {
+        let mut recipe: Vec<_> = (0xE0..=0xE8)
+            .map(|status| (vec![status, 0x00, 0x00], vec![], None))
+            .collect();
+        recipe.extend([
+            (vec![0x90, 0x10, 0x7F], vec![], Some(1.0)),
+            (vec![0x80, 0x11, 0x40], vec![], Some(1.0)),
+            (vec![0x90, 0x12, 0x7F, 0x80, 0x12, 0x00], vec![], Some(1.0)),
+            (vec![0xB0, 0x10, 0x41], vec![], None),
+            (vec![0xB0, 0x07, 0x27], vec![], None),
+            (vec![0xB1, 0x63, 0x01, 0x62, 0x02], vec![], None),
+            (vec![0xB2, 0x65, 0x00, 0x64, 0x00], vec![], None),
+        ]);
+
+        craft_recipe(&true, Some(&recipe), &Default::default())
+            .unwrap()
+            .unwrap()
+    }
+
+    /// Every kind of message on a few channels (a note on is followed by its note off), then the
+    /// messages setting the high resolution parameters of the recipe.
+    fn stream(events: &[Event]) -> Vec<Vec<u8>> {
+        let mut stream = vec![];
+
+        for channel in 0..3 {
+            for data1 in 0..0x80 {
+                for data2 in [0x00, 0x01, 0x40, 0x41, 0x7F] {
+                    for command in [0x90, 0x80, 0xB0, 0xD0, 0xE0] {
+                        stream.push(vec![command | channel, data1, data2]);
+                    }
+                }
+            }
+        }
+
+        for event in events {
+            if let Some(param) = HiResParam::from_ingredient(event.get_mesg_in()) {
+                stream.extend(param.to_midi(0.5));
+            }
+        }
+
+        stream
+    }
+
+    /// The state of the server before a message is processed : the note of a note bang (see
+    /// `midi_main.rs`) and the high resolution parameter set by the message.
+    #[derive(Default)]
+    struct State {
+        note_on: Option<u8>,
+        hires: HiResTracker,
+    }
+
+    impl State {
+        fn next(&mut self, mesg: &[u8]) -> (Option<u8>, Option<HiResParam>) {
+            let bang = match mesg[0] {
+                0x90 => {
+                    self.note_on = Some(mesg[1]);
+                    None
+                }
+                0x80 if self.note_on == Some(mesg[1]) => self.note_on,
+                _ => {
+                    self.note_on = None;
+                    None
+                }
+            };
+            let hires = match mesg {
+                [status, cc, value] if status & 0xF0 == 0xB0 => {
+                    self.hires.on_cc(status & 0x0F, *cc, *value)
+                }
+                _ => None,
+            };
+
+            (bang, hires.map(|(param, _)| param))
+        }
+    }
+
+    /// The condition of the original dispatch, which compared the message to every event of the
+    /// trigger vector. Its fader flag stayed set for the following events, it is checked for each
+    /// event here.
+    fn original_match(
+        event: &Event,
+        mesg: &[u8],
+        bang: Option<u8>,
+        hires: Option<HiResParam>,
+    ) -> bool {
+        let mesg_in = event.get_mesg_in();
+        let note_bang = mesg_in.len() >= 3 && event.get_bang_signal() && bang == Some(mesg_in[1]);
+        let is_fader = mesg_in.len() >= 3 && mesg[0] & 0xF0 == 0xE0 && mesg[0] == mesg_in[0];
+        let hires = hires.is_some() && HiResParam::from_ingredient(mesg_in) == hires;
+
+        note_bang || mesg == mesg_in || is_fader || hires
+    }
+
+    #[test]
+    fn table_finds_the_events_of_the_linear_scan() {
+        let events = events();
+        let table = DispatchTable::new(&Some(events.clone()));
+        let mut state = State::default();
+        let mut triggered = 0;
+
+        for mesg in stream(&events) {
+            let (bang, hires) = state.next(&mesg);
+            let scan: Vec<usize> = (0..events.len())
+                .filter(|position| original_match(&events[*position], &mesg, bang, hires))
+                .collect();
+            let indexed: Vec<usize> = table
+                .candidates(&mesg)
+                .iter()
+                .copied()
+                .filter(|position| original_match(&events[*position], &mesg, bang, hires))
+                .collect();
+
+            assert_eq!(scan, indexed, "Events triggered by {:02X?}", mesg);
+            triggered += scan.len();
+        }
+
+        assert!(triggered > 0);
+    }
+}
@@ -0,0 +1,488 @@
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::midi_server::container::{Event, ExtTrigger};
+use crate::midi_server::logger::{OUTPUT, SERVER};
+
+// ART-NET 4 (ArtDmx, UDP 6454)
+// "Art-Net\0" + OpCode 0x5000 (LE) + ProtVer 14 (BE) + Sequence + Physical + SubUni + Net + Length (BE) + data
+//
+// sACN / E1.31 (UDP 5568, multicast 239.255.<universe hi>.<universe lo>)
+// Root layer (38 bytes) : preamble 0x0010, postamble 0x0000, "ASC-E1.17\0\0\0", flags & length, vector 4, CID
+// Framing layer (77 bytes) : flags & length, vector 2, source name (64), priority, sync address, sequence, options, universe
+// DMP layer (11 bytes + data) : flags & length, vector 2, address type 0xA1, first address 0, increment 1, count, START code 0
+
+pub const DMX_UNIVERSE_SIZE: usize = 512;
+pub const ARTNET_PORT: u16 = 6454;
+pub const SACN_PORT: u16 = 5568;
+
+const ARTNET_ID: &[u8; 8] = b"Art-Net\0";
+const ARTNET_OP_DMX: u16 = 0x5000;
+const ARTNET_VERSION: u16 = 14;
+const SACN_ID: &[u8; 12] = b"ASC-E1.17\0\0\0";
+const SACN_PRIORITY: u8 = 100;
+const SACN_SOURCE_NAME: &str = "MiBL";
+const SACN_OPTION_PREVIEW: u8 = 0x80;
+const SACN_OPTION_TERMINATED: u8 = 0x40;
+const DMX_MAX_PACKET: usize = 1144;
+
+pub type DmxUniverse = [u8; DMX_UNIVERSE_SIZE];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmxProtocol {
+    ArtNet,
+    Sacn,
+}
+
+impl DmxProtocol {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "artnet" | "art-net" => Ok(DmxProtocol::ArtNet),
+            "sacn" | "e1.31" => Ok(DmxProtocol::Sacn),
+            _ => Err(format!("Unknown DMX protocol : {}", name)),
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        match self {
+            DmxProtocol::ArtNet => ARTNET_PORT,
+            DmxProtocol::Sacn => SACN_PORT,
+        }
+    }
+}
+
+/// sACN multicast address of a universe.
+pub fn sacn_multicast(universe: u16) -> Ipv4Addr {
+    Ipv4Addr::new(239, 255, (universe >> 8) as u8, universe as u8)
+}
+
+pub fn encode_artnet(universe: u16, sequence: u8, data: &DmxUniverse) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(18 + DMX_UNIVERSE_SIZE);
+
+    packet.extend(ARTNET_ID);
+    packet.extend(ARTNET_OP_DMX.to_le_bytes());
+    packet.extend(ARTNET_VERSION.to_be_bytes());
+    packet.push(sequence);
+    packet.push(0); // Physical
+    packet.push(universe as u8); // SubUni
+    packet.push((universe >> 8) as u8 & 0x7F); // Net
+    packet.extend((DMX_UNIVERSE_SIZE as u16).to_be_bytes());
+    packet.extend(data);
+
+    packet
+}
+
+pub fn encode_sacn(universe: u16, sequence: u8, cid: &[u8; 16], data: &DmxUniverse) -> Vec<u8> {
+    let flags_length = |len: usize| (0x7000 | len as u16).to_be_bytes();
+    let packet_len = 126 + DMX_UNIVERSE_SIZE;
+    let mut packet = Vec::with_capacity(packet_len);
+
+    // Root layer
+    packet.extend(0x0010u16.to_be_bytes());
+    packet.extend(0x0000u16.to_be_bytes());
+    packet.extend(SACN_ID);
+    packet.extend(flags_length(packet_len - 16));
+    packet.extend(0x0000_0004u32.to_be_bytes());
+    packet.extend(cid);
+
+    // Framing layer
+    packet.extend(flags_length(packet_len - 38));
+    packet.extend(0x0000_0002u32.to_be_bytes());
+    let mut source_name = [0u8; 64];
+    source_name[..SACN_SOURCE_NAME.len()].copy_from_slice(SACN_SOURCE_NAME.as_bytes());
+    packet.extend(source_name);
+    packet.push(SACN_PRIORITY);
+    packet.extend(0u16.to_be_bytes()); // Synchronization address
+    packet.push(sequence);
+    packet.push(0); // Options
+    packet.extend(universe.to_be_bytes());
+
+    // DMP layer
+    packet.extend(flags_length(packet_len - 115));
+    packet.push(0x02);
+    packet.push(0xA1);
+    packet.extend(0x0000u16.to_be_bytes());
+    packet.extend(0x0001u16.to_be_bytes());
+    packet.extend((DMX_UNIVERSE_SIZE as u16 + 1).to_be_bytes());
+    packet.push(0x00); // START code
+    packet.extend(data);
+
+    packet
+}
+
+/// Read an ArtDmx packet : (universe, channels data)
+pub fn decode_artnet(packet: &[u8]) -> Option<(u16, &[u8])> {
+    if packet.len() < 18
+        || &packet[..8] != ARTNET_ID
+        || u16::from_le_bytes([packet[8], packet[9]]) != ARTNET_OP_DMX
+    {
+        return None;
+    }
+
+    let universe = (packet[15] as u16 & 0x7F) << 8 | packet[14] as u16;
+    let len = (u16::from_be_bytes([packet[16], packet[17]]) as usize).min(DMX_UNIVERSE_SIZE);
+
+    packet.get(18..18 + len).map(|data| (universe, data))
+}
+
+/// Read a sACN data packet : (universe, channels data). Preview data and stream terminated packets are ignored.
+pub fn decode_sacn(packet: &[u8]) -> Option<(u16, &[u8])> {
+    if packet.len() < 126
+        || &packet[4..16] != SACN_ID
+        || packet[18..22] != [0, 0, 0, 4]
+        || packet[40..44] != [0, 0, 0, 2]
+        || packet[117] != 0x02
+        || packet[125] != 0x00
+        || packet[112] & (SACN_OPTION_PREVIEW | SACN_OPTION_TERMINATED) != 0
+    {
+        return None;
+    }
+
+    let universe = u16::from_be_bytes([packet[113], packet[114]]);
+    let count = u16::from_be_bytes([packet[123], packet[124]]) as usize;
+    let len = count.saturating_sub(1).min(DMX_UNIVERSE_SIZE);
+
+    packet.get(126..126 + len).map(|data| (universe, data))
+}
+
+/// What drives a DMX channel : an `ExtTrigger` index, or a name (`Event` name or parameter set from Python).
+#[derive(Debug, Clone, PartialEq)]
+pub enum DmxSource {
+    Index(u64),
+    Name(String),
+}
+
+impl DmxSource {
+    /// A number is a trigger index, anything else a name.
+    pub fn parse(source: &str) -> Self {
+        match source.trim().parse::<u64>() {
+            Ok(index) => DmxSource::Index(index),
+            Err(_) => DmxSource::Name(source.trim().to_string()),
+        }
+    }
+}
+
+/// Channel (1 to 512) of a universe driven by a source, on 8 bits or on 16 bits (coarse + fine on the next channel).
+#[derive(Debug, Clone, PartialEq)]
+pub struct DmxMapping {
+    pub source: DmxSource,
+    pub universe: u16,
+    pub channel: u16,
+    pub wide: bool,
+}
+
+impl DmxMapping {
+    pub fn new(source: &str, universe: u16, channel: u16, wide: bool) -> Result<Self, String> {
+        let last_channel = channel as usize + wide as usize;
+
+        if channel == 0 || last_channel > DMX_UNIVERSE_SIZE {
+            return Err(format!("DMX channel out of range : {}", channel));
+        }
+
+        Ok(Self {
+            source: DmxSource::parse(source),
+            universe,
+            channel,
+            wide,
+        })
+    }
+
+    /// Read the normalized value (0.0 to 1.0) of the channel, `None` if the universe is too short.
+    fn read(&self, data: &[u8]) -> Option<f32> {
+        let idx = self.channel as usize - 1;
+
+        match self.wide {
+            true => data
+                .get(idx..idx + 2)
+                .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]) as f32 / 65535.0),
+            false => data.get(idx).map(|&byte| byte as f32 / 255.0),
+        }
+    }
+
+    /// Write a normalized value (0.0 to 1.0) in the universe.
+    fn write(&self, data: &mut DmxUniverse, value: f32) {
+        let idx = self.channel as usize - 1;
+        let value = value.clamp(0.0, 1.0);
+
+        if self.wide {
+            let [coarse, fine] = ((value * 65535.0).round() as u16).to_be_bytes();
+            data[idx] = coarse;
+            data[idx + 1] = fine;
+        } else {
+            data[idx] = (value * 255.0).round() as u8;
+        }
+    }
+}
+
+struct DmxOutputShared {
+    universes: BTreeMap<u16, DmxUniverse>,
+}
+
+/// Send DMX universes over Art-Net or sACN at a fixed refresh rate, driven by triggers and named parameters.
+///
+/// All mapped universes are sent on each refresh (DMX receivers expect a continuous stream).
+pub struct DmxOutput {
+    mappings: Vec<DmxMapping>,
+    shared: Arc<Mutex<DmxOutputShared>>,
+    closed: Arc<AtomicBool>,
+    sender_thread: Option<JoinHandle<()>>,
+}
+
+impl DmxOutput {
+    /// `target` is an IP (or "ip:port"), Art-Net defaults to broadcast, sACN to the multicast group of each universe.
+    pub fn start(
+        protocol: DmxProtocol,
+        target: Option<&str>,
+        refresh_rate: f32,
+        mappings: Vec<DmxMapping>,
+    ) -> Result<Self, String> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))
+            .map_err(|err| format!("Unable to open DMX socket : {}", err))?;
+
+        let target = match target {
+            Some(target) => Some(resolve_target(target, protocol.port())?),
+            None => None,
+        };
+
+        if protocol == DmxProtocol::ArtNet && target.is_none() {
+            socket
+                .set_broadcast(true)
+                .map_err(|err| format!("Unable to broadcast Art-Net : {}", err))?;
+        }
+
+        let universes = mappings
+            .iter()
+            .map(|mapping| (mapping.universe, [0u8; DMX_UNIVERSE_SIZE]))
+            .collect();
+        let shared = Arc::new(Mutex::new(DmxOutputShared { universes }));
+        let closed = Arc::new(AtomicBool::new(false));
+        let period = Duration::from_secs_f32(1.0 / refresh_rate.clamp(1.0, 44.0));
+
+        let sender_thread = {
+            let shared = Arc::clone(&shared);
+            let closed = Arc::clone(&closed);
+            let cid: [u8; 16] = rand::random();
+
+            spawn(move || {
+                let mut sequence: u8 = 0;
+
+                while !closed.load(Ordering::Relaxed) {
+                    let start = Instant::now();
+                    let universes = shared.lock().unwrap().universes.clone();
+
+                    // Sequence 0 disable the reordering in Art-Net, so skip it
+                    sequence = sequence.checked_add(1).unwrap_or(1);
+
+                    for (universe, data) in universes {
+                        let (packet, to) = match protocol {
+                            DmxProtocol::ArtNet => (
+                                encode_artnet(universe, sequence, &data),
+                                target.unwrap_or(SocketAddr::V4(SocketAddrV4::new(
+                                    Ipv4Addr::BROADCAST,
+                                    ARTNET_PORT,
+                                ))),
+                            ),
+                            DmxProtocol::Sacn => (
+                                encode_sacn(universe, sequence, &cid, &data),
+                                target.unwrap_or(SocketAddr::V4(SocketAddrV4::new(
+                                    sacn_multicast(universe),
+                                    SACN_PORT,
+                                ))),
+                            ),
+                        };
+
+                        if let Err(err) = socket.send_to(&packet, to) {
+                            log::warn!(
+                                target: OUTPUT,
+                                "Unable to send DMX universe {} : {}",
+                                universe,
+                                err
+                            );
+                        }
+                    }
+
+                    sleep(period.saturating_sub(start.elapsed()));
+                }
+            })
+        };
+
+        Ok(Self {
+            mappings,
+            shared,
+            closed,
+            sender_thread: Some(sender_thread),
+        })
+    }
+
+    fn write<F: Fn(&DmxSource) -> bool>(&self, is_source: F, value: f32) {
+        let mut shared = self.shared.lock().unwrap();
+
+        for mapping in self
+            .mappings
+            .iter()
+            .filter(|mapping| is_source(&mapping.source))
+        {
+            if let Some(data) = shared.universes.get_mut(&mapping.universe) {
+                mapping.write(data, value);
+            }
+        }
+    }
+
+    pub fn apply_triggers(&self, triggers: &[ExtTrigger], events: &Option<Vec<Event>>) {
+        for (index, value) in triggers {
+            let name = events.as_ref().and_then(|events| {
+                events
+                    .iter()
+                    .find(|event| event.get_index() == index)
+                    .map(Event::get_name)
+            });
+
+            self.write(
+                |source| match source {
+                    DmxSource::Index(source_index) => source_index == index,
+                    DmxSource::Name(source_name) => Some(source_name.as_str()) == name,
+                },
+                *value,
+            );
+        }
+    }
+
+    pub fn set_param(&self, name: &str, value: f32) {
+        self.write(
+            |source| matches!(source, DmxSource::Name(source_name) if source_name == name),
+            value,
+        );
+    }
+}
+
+impl Drop for DmxOutput {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+
+        if let Some(sender_thread) = self.sender_thread.take() {
+            let _ = sender_thread.join();
+        }
+    }
+}
+
+fn resolve_target(target: &str, default_port: u16) -> Result<SocketAddr, String> {
+    let with_port = match target.contains(':') {
+        true => target.to_string(),
+        false => format!("{}:{}", target, default_port),
+    };
+
+    with_port
+        .to_socket_addrs()
+        .map_err(|err| format!("Bad DMX target {} : {}", target, err))?
+        .next()
+        .ok_or(format!("DMX target {} not found", target))
+}
+
+/// Listen to Art-Net or sACN and convert channel changes into `ExtTrigger`, like the MIDI path does.
+///
+/// Each mapping sends its `DmxSource::Index`, only when the value moved by `threshold` at least
+/// (or reached 0.0 or 1.0), so the desk does not flood the server with its continuous stream.
+pub struct DmxInput {
+    protocol: DmxProtocol,
+    socket: UdpSocket,
+    mappings: Vec<DmxMapping>,
+    threshold: f32,
+    last_values: Vec<Option<f32>>,
+}
+
+impl DmxInput {
+    pub fn bind(
+        protocol: DmxProtocol,
+        threshold: f32,
+        mappings: Vec<DmxMapping>,
+    ) -> Result<Self, String> {
+        let socket = UdpSocket::bind(("0.0.0.0", protocol.port()))
+            .map_err(|err| format!("Unable to bind DMX port {} : {}", protocol.port(), err))?;
+
+        socket
+            .set_nonblocking(true)
+            .map_err(|err| format!("Unable to set DMX socket non blocking : {}", err))?;
+
+        if protocol == DmxProtocol::Sacn {
+            let mut universes: Vec<u16> = mappings.iter().map(|mapping| mapping.universe).collect();
+            universes.sort_unstable();
+            universes.dedup();
+
+            for universe in universes {
+                socket
+                    .join_multicast_v4(&sacn_multicast(universe), &Ipv4Addr::UNSPECIFIED)
+                    .map_err(|err| {
+                        format!("Unable to join sACN universe {} : {}", universe, err)
+                    })?;
+            }
+        }
+
+        Ok(Self {
+            protocol,
+            socket,
+            last_values: vec![None; mappings.len()],
+            mappings,
+            threshold: threshold.max(0.0),
+        })
+    }
+
+    fn update(&mut self, universe: u16, data: &[u8], triggers: &mut Vec<ExtTrigger>) {
+        for (mapping, last_value) in self.mappings.iter().zip(self.last_values.iter_mut()) {
+            let index = match (&mapping.source, mapping.universe == universe) {
+                (DmxSource::Index(index), true) => *index,
+                _ => continue,
+            };
+
+            let value = match mapping.read(data) {
+                Some(value) => value,
+                None => continue,
+            };
+
+            let changed = match last_value {
+                Some(last) => {
+                    (value - *last).abs() >= self.threshold
+                        || ((value == 0.0 || value == 1.0) && value != *last)
+                }
+                None => true,
+            };
+
+            if changed {
+                *last_value = Some(value);
+                triggers.push((index, value));
+            }
+        }
+    }
+
+    /// Read all pending packets and return the triggers to hand to the client.
+    pub fn poll(&mut self) -> Vec<ExtTrigger> {
+        let mut triggers = vec![];
+        let mut buffer = [0u8; DMX_MAX_PACKET];
+
+        loop {
+            let len = match self.socket.recv_from(&mut buffer) {
+                Ok((len, _)) => len,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    log::error!(target: SERVER, "Unable to read DMX socket : {}", err);
+                    break;
+                }
+            };
+
+            let packet = match self.protocol {
+                DmxProtocol::ArtNet => decode_artnet(&buffer[..len]),
+                DmxProtocol::Sacn => decode_sacn(&buffer[..len]),
+            };
+
+            if let Some((universe, data)) = packet {
+                self.update(universe, data, &mut triggers);
+            }
+        }
+
+        triggers
+    }
+}
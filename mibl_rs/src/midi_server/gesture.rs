@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::midi_server::container::{Event, ExtTrigger, IngredientOptions};
+use crate::midi_server::control::Control;
+
+// BUTTON GESTURES
+// Declared per ingredient in the recipe options :
+//   {"gesture": "press"}                                   Note on
+//   {"gesture": "release"}                                 Note off
+//   {"gesture": "long_press", "time": "800"}               Held for "time" ms (default 500), sent once while held
+//   {"gesture": "double_tap", "time": "250"}               Second press within "time" ms (default 300)
+//   {"gesture": "chord", "with": "button.mod_shift"}       Press while all the "with" buttons are held
+//                                                          ('+' separated control names or note numbers)
+// An event with a gesture is only triggered by the recogniser, not by the usual note matching.
+// The device reset and the panic are declared the same way, on a control (see `parse_binding`).
+
+const LONG_PRESS: Duration = Duration::from_millis(500);
+const DOUBLE_TAP: Duration = Duration::from_millis(300);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Gesture {
+    Press,
+    Release,
+    LongPress(Duration),
+    DoubleTap(Duration),
+    Chord(Vec<u8>), // notes to hold
+}
+
+impl Gesture {
+    /// The gesture declared in the options of an ingredient, if any.
+    pub fn from_options(options: &IngredientOptions) -> Result<Option<Gesture>, String> {
+        let time = |default: Duration| -> Result<Duration, String> {
+            match options.get("time") {
+                Some(time) => time
+                    .parse::<u64>()
+                    .map(Duration::from_millis)
+                    .map_err(|err| format!("Bad gesture time {} : {}", time, err)),
+                None => Ok(default),
+            }
+        };
+
+        let gesture = match options.get("gesture").map(String::as_str) {
+            None => return Ok(None),
+            Some("press") => Gesture::Press,
+            Some("release") => Gesture::Release,
+            Some("long_press") => Gesture::LongPress(time(LONG_PRESS)?),
+            Some("double_tap") => Gesture::DoubleTap(time(DOUBLE_TAP)?),
+            Some("chord") => {
+                let with = options
+                    .get("with")
+                    .ok_or("Chord gesture without \"with\"")?;
+                let notes = with
+                    .split('+')
+                    .map(|name| match name.trim().parse::<u8>() {
+                        Ok(note) => Ok(note),
+                        Err(_) => name
+                            .parse::<Control>()?
+                            .note()
+                            .ok_or(format!("{} is not a button", name)),
+                    })
+                    .collect::<Result<Vec<u8>, String>>()?;
+
+                Gesture::Chord(notes)
+            }
+            Some(other) => return Err(format!("Unknown gesture : {}", other)),
+        };
+
+        Ok(Some(gesture))
+    }
+}
+
+/// (note, gesture) of a `control` name (or note number) and its gesture options.
+pub fn parse_binding(control: &str, options: &IngredientOptions) -> Result<(u8, Gesture), String> {
+    let note = match control.trim().parse::<u8>() {
+        Ok(note) => note,
+        Err(_) => control
+            .parse::<Control>()?
+            .note()
+            .ok_or(format!("{} is not a button", control))?,
+    };
+
+    match Gesture::from_options(options)? {
+        Some(gesture) => Ok((note, gesture)),
+        None => Err("Missing \"gesture\" option".to_string()),
+    }
+}
+
+/// What a recognised gesture does.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GestureAction {
+    Trigger(ExtTrigger),
+    Reset,
+    Panic,
+}
+
+/// Timestamps the button transitions and sends the triggers of the gesture events,
+/// and recognises the reset and panic gestures of the server.
+#[derive(Debug, Clone, Default)]
+pub struct GestureRecognizer {
+    reset: Option<(u8, Gesture)>,
+    panic: Option<(u8, Gesture)>,
+    bindings: Vec<(u8, Gesture, GestureAction)>, // rebuilt with the trigger events
+    held: HashMap<u8, Instant>,                  // note => pressed at
+    last_press: HashMap<u8, Instant>,            // note => previous press, for double tap
+    long_sent: Vec<(u8, usize)>,                 // (note, binding) of the long presses sent
+}
+
+impl GestureRecognizer {
+    /// `reset` and `panic` : (note, gesture) of the device reset and of the panic.
+    pub fn new(reset: Option<(u8, Gesture)>, panic: Option<(u8, Gesture)>) -> Self {
+        let mut recognizer = Self {
+            reset,
+            panic,
+            ..Default::default()
+        };
+
+        recognizer.set_events(&None);
+        recognizer
+    }
+
+    /// Build the gestures to recognise : (note, gesture, action), called when the recipe changes.
+    pub fn set_events(&mut self, events: &Option<Vec<Event>>) {
+        self.bindings = events
+            .iter()
+            .flatten()
+            .filter_map(|event| {
+                let mesg_in = event.get_mesg_in();
+
+                match event.get_gesture() {
+                    Some(gesture) if mesg_in.len() >= 2 => Some((
+                        mesg_in[1],
+                        gesture.clone(),
+                        GestureAction::Trigger((
+                            *event.get_index(),
+                            event.get_val_out().unwrap_or(1.0),
+                        )),
+                    )),
+                    _ => None,
+                }
+            })
+            .collect();
+
+        if let Some((note, gesture)) = &self.reset {
+            self.bindings
+                .push((*note, gesture.clone(), GestureAction::Reset));
+        }
+
+        if let Some((note, gesture)) = &self.panic {
+            self.bindings
+                .push((*note, gesture.clone(), GestureAction::Panic));
+        }
+
+        // The long presses sent refer to the previous bindings, a button still held does not
+        // fire its long press again
+        let sent: Vec<u8> = self.long_sent.iter().map(|(note, _)| *note).collect();
+        self.long_sent = self
+            .bindings
+            .iter()
+            .enumerate()
+            .filter(|(_, (note, gesture, _))| {
+                matches!(gesture, Gesture::LongPress(_)) && sent.contains(note)
+            })
+            .map(|(position, (note, _, _))| (*note, position))
+            .collect();
+    }
+
+    /// Track a button transition, return the actions of press, release, double tap and chords.
+    pub fn on_message(&mut self, mesg: &[u8], now: Instant) -> Vec<GestureAction> {
+        if mesg.len() < 3 || !matches!(mesg[0], 0x80 | 0x90) {
+            return vec![];
+        }
+
+        let note = mesg[1];
+        let pressed = mesg[0] == 0x90 && mesg[2] > 0;
+
+        let previous = if pressed {
+            self.held.insert(note, now);
+            self.last_press.insert(note, now)
+        } else {
+            self.held.remove(&note);
+            self.long_sent.retain(|(sent, _)| *sent != note);
+            None
+        };
+
+        let mut actions = vec![];
+        let mut double_tap = false;
+
+        for (binding_note, gesture, action) in &self.bindings {
+            if *binding_note != note {
+                continue;
+            }
+
+            let recognised = match gesture {
+                Gesture::Press => pressed,
+                Gesture::Release => !pressed,
+                Gesture::DoubleTap(time) => {
+                    pressed
+                        && previous.is_some_and(|previous| now.duration_since(previous) <= *time)
+                }
+                Gesture::Chord(with) => {
+                    pressed && with.iter().all(|note| self.held.contains_key(note))
+                }
+                Gesture::LongPress(_) => false,
+            };
+
+            if recognised {
+                double_tap |= matches!(gesture, Gesture::DoubleTap(_));
+                actions.push(action.clone());
+            }
+        }
+
+        if double_tap {
+            // A third tap starts a new double tap
+            self.last_press.remove(&note);
+        }
+
+        actions
+    }
+
+    /// Return the actions of the buttons held long enough, called by the server loop.
+    pub fn poll(&mut self, now: Instant) -> Vec<GestureAction> {
+        let mut actions = vec![];
+        let mut sent = vec![];
+
+        for (position, (note, gesture, action)) in self.bindings.iter().enumerate() {
+            let Gesture::LongPress(time) = gesture else {
+                continue;
+            };
+
+            let long_pressed = self
+                .held
+                .get(note)
+                .is_some_and(|pressed_at| now.duration_since(*pressed_at) >= *time);
+
+            if long_pressed && !self.long_sent.contains(&(*note, position)) {
+                actions.push(action.clone());
+                sent.push((*note, position));
+            }
+        }
+
+        self.long_sent.extend(sent);
+        actions
+    }
+
+    /// Forget the buttons held, *e.g* after a panic.
+    pub fn clear(&mut self) {
+        self.held.clear();
+        self.last_press.clear();
+        self.long_sent.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recipe_update_does_not_repeat_a_long_press() {
+        let long_press = Gesture::LongPress(Duration::from_millis(500));
+        let mut recognizer = GestureRecognizer::new(Some((0x10, long_press)), None);
+        let start = Instant::now();
+        let after = |ms| start + Duration::from_millis(ms);
+
+        recognizer.on_message(&[0x90, 0x10, 0x7F], start);
+        assert_eq!(recognizer.poll(after(600)), vec![GestureAction::Reset]);
+
+        recognizer.set_events(&None);
+        assert!(recognizer.poll(after(700)).is_empty());
+
+        // Released then held again, the long press is sent again
+        recognizer.on_message(&[0x80, 0x10, 0x00], after(800));
+        recognizer.on_message(&[0x90, 0x10, 0x7F], after(900));
+        assert!(recognizer.poll(after(1000)).is_empty());
+        assert_eq!(recognizer.poll(after(1400)), vec![GestureAction::Reset]);
+    }
+}
@@ -0,0 +1,157 @@
+use crate::midi_server::container::{Event, ExtTrigger, RecipeOptions, ToggleStates};
+
+// EXCLUSIVE GROUPS (radio buttons)
+// A button joins a group with the recipe option {"group": "<name>"}, e.g. EA_Track/EA_PAN/EA_EQ/…
+// or the VIEW_* buttons. With the sys events, TRANS_Stop and TRANS_Play form the "transport" group,
+// and TRANS_Prev / TRANS_Next switch Play off (without selecting Stop).
+// Activating a member lights it and turns off the other members. The selection is kept in the
+// `ToggleStates` (so it is restored like the toggles) and sent to Python as a single trigger :
+//   (GROUP_INDEX + group position, position of the selected member in the group)
+
+pub const GROUP_INDEX: u64 = 0x1_0000_0000;
+
+pub const SYS_GROUPS: [(&str, &[u8]); 1] = [("transport", &[0x5D, 0x5E])];
+
+/// (button, group member) : the sys buttons switching a member of a group off.
+pub const SYS_SWITCH_OFF: [(u8, u8); 2] = [(0x5B, 0x5E), (0x5C, 0x5E)];
+
+/// The names of the groups, in the order of their trigger index.
+pub fn group_names(use_sys: bool, options: &RecipeOptions) -> Vec<String> {
+    let mut names: Vec<String> = match use_sys {
+        true => SYS_GROUPS
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .collect(),
+        false => vec![],
+    };
+
+    let mut positions: Vec<&usize> = options.keys().collect();
+    positions.sort();
+
+    for name in positions
+        .into_iter()
+        .filter_map(|position| options[position].get("group"))
+    {
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+
+    names
+}
+
+/// The trigger index of the group `name`.
+pub fn group_index(names: &[String], name: &str) -> Option<u64> {
+    names
+        .iter()
+        .position(|group| group == name)
+        .map(|position| GROUP_INDEX + position as u64)
+}
+
+/// The group of a sys event button.
+pub fn sys_group(note: u8) -> Option<&'static str> {
+    SYS_GROUPS
+        .iter()
+        .find(|(_, notes)| notes.contains(&note))
+        .map(|(name, _)| *name)
+}
+
+fn members(events: &[Event], group: u64) -> impl Iterator<Item = &Event> {
+    events
+        .iter()
+        .filter(move |event| event.get_group() == Some(group) && event.get_mesg_in().len() >= 2)
+}
+
+/// Select `event` in its group : update the states of the members, return their LED
+/// messages and the trigger of the group. `None` if the event has no group.
+pub fn select(
+    event: &Event,
+    events: &[Event],
+    toggle_states: &mut ToggleStates,
+) -> Option<(Vec<Vec<u8>>, ExtTrigger)> {
+    let group = event.get_group()?;
+    let mut leds = vec![];
+    let mut selected = 0;
+
+    for (position, member) in members(events, group).enumerate() {
+        let on = member.get_index() == event.get_index();
+
+        if on {
+            selected = position;
+        }
+
+        toggle_states.insert(*member.get_index(), on);
+        leds.push(vec![
+            0x90,
+            member.get_mesg_in()[1],
+            if on { 0x7F } else { 0x00 },
+        ]);
+    }
+
+    Some((leds, (group, selected as f32)))
+}
+
+/// Switch off the group member of a sys button (see `SYS_SWITCH_OFF`) : update its state, return
+/// its LED message. `None` if the button switches nothing off or the member has no group.
+pub fn switch_off(
+    event: &Event,
+    events: &[Event],
+    toggle_states: &mut ToggleStates,
+) -> Option<Vec<u8>> {
+    let note = match event.get_mesg_in().as_slice() {
+        [0x80 | 0x90, note, ..] => *note,
+        _ => return None,
+    };
+    let (_, member_note) = SYS_SWITCH_OFF.iter().find(|(button, _)| *button == note)?;
+    let member = events.iter().find(|member| {
+        member.get_group().is_some() && member.get_mesg_in().get(1) == Some(member_note)
+    })?;
+
+    toggle_states.insert(*member.get_index(), false);
+    Some(vec![0x90, *member_note, 0x00])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi_server::midi_event::craft_recipe;
+
+    fn sys_event(events: &[Event], note: u8) -> &Event {
+        events
+            .iter()
+            .find(|event| event.get_mesg_in().get(1) == Some(&note))
+            .unwrap()
+    }
+
+    #[test]
+    fn prev_and_next_switch_play_off() {
+        let events = craft_recipe(&true, None, &Default::default())
+            .unwrap()
+            .unwrap();
+        let (stop, play) = (sys_event(&events, 0x5D), sys_event(&events, 0x5E));
+
+        for note in [0x5B, 0x5C] {
+            let mut toggle_states = ToggleStates::default();
+            select(play, &events, &mut toggle_states).unwrap();
+
+            let led = switch_off(sys_event(&events, note), &events, &mut toggle_states);
+
+            assert_eq!(led, Some(vec![0x90, 0x5E, 0x00]));
+            assert_eq!(toggle_states.get(play.get_index()), Some(&false));
+            assert_eq!(toggle_states.get(stop.get_index()), Some(&false));
+        }
+
+        // Without the sys events, there is no transport group
+        let events = craft_recipe(
+            &false,
+            Some(&vec![(vec![0x90, 0x5B, 0x7F], vec![], Some(1.0))]),
+            &Default::default(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            switch_off(&events[0], &events, &mut ToggleStates::default()),
+            None
+        );
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::midi_server::math_utils::{decode_14bit, encode_14bit, MAX_14BIT};
+
+// HIGH RESOLUTION CONTROLLERS
+// 14-bit CC : MSB on CC n (0x00->0x1F), LSB on CC n + 32. An MSB is sent at once (so a 7 bits
+//   controller works too), the following LSB refines it.
+// NRPN : CC 0x63 (MSB) / 0x62 (LSB) select the parameter. RPN : CC 0x65 (MSB) / 0x64 (LSB),
+//   0x7F 0x7F is the null RPN. The value of the selected parameter is set by the data entry
+//   CC 0x06 (MSB, clears the LSB) / 0x26 (LSB), or moved by the increment CC 0x60 and
+//   decrement CC 0x61 (by the data byte, at least 1).
+// In a recipe, the ingredient waiting for such a parameter is :
+//   [0xB0 | channel, n, n + 32]                  14-bit CC n
+//   [0xB0 | channel, 0x63, msb, 0x62, lsb]       NRPN (msb << 7 | lsb)
+//   [0xB0 | channel, 0x65, msb, 0x64, lsb]       RPN
+
+const DATA_MSB: u8 = 0x06;
+const DATA_LSB: u8 = 0x26;
+const INCREMENT: u8 = 0x60;
+const DECREMENT: u8 = 0x61;
+const NRPN_LSB: u8 = 0x62;
+const NRPN_MSB: u8 = 0x63;
+const RPN_LSB: u8 = 0x64;
+const RPN_MSB: u8 = 0x65;
+const NULL_PARAM: u16 = 0x3FFF;
+
+/// A high resolution parameter, channels are indexed from 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HiResParam {
+    Cc14(u8, u8), // (channel, MSB controller)
+    Nrpn(u8, u16),
+    Rpn(u8, u16),
+}
+
+impl HiResParam {
+    /// The parameter waited for by a recipe ingredient.
+    pub fn from_ingredient(mesg_in: &[u8]) -> Option<HiResParam> {
+        if mesg_in.first().is_none_or(|status| status & 0xF0 != 0xB0) {
+            return None;
+        }
+
+        let channel = mesg_in[0] & 0x0F;
+
+        match mesg_in[1..] {
+            [cc @ 0x00..=0x1F, lsb] if lsb == cc + 0x20 && cc != DATA_MSB => {
+                Some(HiResParam::Cc14(channel, cc))
+            }
+            [NRPN_MSB, msb, NRPN_LSB, lsb] => Some(HiResParam::Nrpn(channel, number(msb, lsb))),
+            [RPN_MSB, msb, RPN_LSB, lsb] => Some(HiResParam::Rpn(channel, number(msb, lsb))),
+            _ => None,
+        }
+    }
+
+    /// The messages setting the parameter to `value` (0.0 to 1.0).
+    pub fn to_midi(self, value: f32) -> Vec<Vec<u8>> {
+        let (lsb, msb) = encode_14bit(value);
+
+        let select = |channel: u8, select_msb: u8, select_lsb: u8, param: u16| {
+            let status = 0xB0 | channel;
+
+            vec![
+                vec![status, select_msb, (param >> 7 & 0x7F) as u8],
+                vec![status, select_lsb, (param & 0x7F) as u8],
+                vec![status, DATA_MSB, msb],
+                vec![status, DATA_LSB, lsb],
+            ]
+        };
+
+        match self {
+            HiResParam::Cc14(channel, cc) => vec![
+                vec![0xB0 | channel, cc, msb],
+                vec![0xB0 | channel, cc + 0x20, lsb],
+            ],
+            HiResParam::Nrpn(channel, param) => select(channel, NRPN_MSB, NRPN_LSB, param),
+            HiResParam::Rpn(channel, param) => {
+                let mut mesgs = select(channel, RPN_MSB, RPN_LSB, param);
+                // Back to the null RPN, a stray data entry changes nothing
+                mesgs.push(vec![0xB0 | channel, RPN_MSB, 0x7F]);
+                mesgs.push(vec![0xB0 | channel, RPN_LSB, 0x7F]);
+                mesgs
+            }
+        }
+    }
+}
+
+fn number(msb: u8, lsb: u8) -> u16 {
+    (msb as u16 & 0x7F) << 7 | lsb as u16 & 0x7F
+}
+
+impl fmt::Display for HiResParam {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HiResParam::Cc14(channel, cc) => write!(f, "CC14 #{} (ch {})", cc, channel + 1),
+            HiResParam::Nrpn(channel, param) => write!(f, "NRPN #{} (ch {})", param, channel + 1),
+            HiResParam::Rpn(channel, param) => write!(f, "RPN #{} (ch {})", param, channel + 1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+enum Selected {
+    #[default]
+    None,
+    Nrpn(u16),
+    Rpn(u16),
+}
+
+/// The state of the high resolution controllers of a channel.
+#[derive(Debug, Clone, Default)]
+struct ChannelState {
+    cc_msb: [u8; 32],
+    selected: Selected,
+    values: HashMap<HiResParam, u16>, // 14 bits value of the NRPN and RPN
+}
+
+/// Pairs the MSB / LSB of the controllers and tracks the NRPN and RPN, stored in `SIGflag`.
+#[derive(Debug, Clone, Default)]
+pub struct HiResTracker {
+    channels: HashMap<u8, ChannelState>,
+}
+
+impl HiResTracker {
+    /// Track a control change, return the parameter changed and its value (0.0 to 1.0).
+    pub fn on_cc(&mut self, channel: u8, cc: u8, value: u8) -> Option<(HiResParam, f32)> {
+        let state = self.channels.entry(channel).or_default();
+        let value = value & 0x7F;
+
+        let param = match state.selected {
+            Selected::Nrpn(param) => Some(HiResParam::Nrpn(channel, param)),
+            Selected::Rpn(param) if param != NULL_PARAM => Some(HiResParam::Rpn(channel, param)),
+            _ => None,
+        };
+
+        let raw = match cc {
+            NRPN_MSB | NRPN_LSB | RPN_MSB | RPN_LSB => {
+                let current = match state.selected {
+                    Selected::Nrpn(param) if matches!(cc, NRPN_MSB | NRPN_LSB) => param,
+                    Selected::Rpn(param) if matches!(cc, RPN_MSB | RPN_LSB) => param,
+                    _ => 0,
+                };
+                let param = match cc {
+                    NRPN_MSB | RPN_MSB => (value as u16) << 7 | current & 0x7F,
+                    _ => current & 0x3F80 | value as u16,
+                };
+
+                state.selected = match cc {
+                    NRPN_MSB | NRPN_LSB => Selected::Nrpn(param),
+                    _ => Selected::Rpn(param),
+                };
+                return None;
+            }
+            DATA_MSB | DATA_LSB | INCREMENT | DECREMENT => {
+                let param = param?;
+                let current = state.values.get(&param).copied().unwrap_or_default();
+                let step = value.max(1) as u16;
+
+                let raw = match cc {
+                    DATA_MSB => (value as u16) << 7,
+                    DATA_LSB => current & 0x3F80 | value as u16,
+                    INCREMENT => current.saturating_add(step).min(MAX_14BIT),
+                    _ => current.saturating_sub(step),
+                };
+
+                state.values.insert(param, raw);
+                return Some((param, decode_14bit((raw & 0x7F) as u8, (raw >> 7) as u8)));
+            }
+            0x00..=0x1F => {
+                state.cc_msb[cc as usize] = value;
+                (value as u16) << 7
+            }
+            0x20..=0x3F => (state.cc_msb[(cc - 0x20) as usize] as u16) << 7 | value as u16,
+            _ => return None,
+        };
+
+        let param = HiResParam::Cc14(channel, cc & 0x1F);
+        Some((param, decode_14bit((raw & 0x7F) as u8, (raw >> 7) as u8)))
+    }
+}
@@ -0,0 +1,68 @@
+use crate::midi_server::container::{Ingredient, IngredientOptions};
+use crate::midi_server::control::Control;
+
+// MIDI LEARN
+// The next control touched on the device is captured and turned into recipe ingredients :
+//   Channel buttons (Rec/Solo/Mute/Select 0x00->0x1F) => Toggle, press + release [0x90, n, 0x7F, 0x80, n, 0x00]
+//   Other buttons (notes, flip)                        => Bang, press [0x90, n, 0x7F] (flip : press + release)
+//   Faders (pitch bend 0xE0->0xE8)                     => Continuous, value sent to the client
+//   V-Pots / Jog wheel (relative CC 0x10->0x17, 0x3C)   => Relative, one ingredient by direction (+1.0 / -1.0)
+// Fader touch (0x68->0x70), note off and other messages are not learnable, learning keeps waiting.
+
+pub type LearnedIngredients = Vec<(Ingredient, IngredientOptions)>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LearnKind {
+    Bang,
+    Toggle,
+    Continuous,
+    Relative,
+}
+
+impl LearnKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LearnKind::Bang => "bang",
+            LearnKind::Toggle => "toggle",
+            LearnKind::Continuous => "continuous",
+            LearnKind::Relative => "relative",
+        }
+    }
+}
+
+/// Classify an incoming message, `None` if it can't be learned.
+pub fn classify(mesg: &[u8]) -> Option<LearnKind> {
+    if mesg.len() < 3 {
+        return None;
+    }
+
+    match (Control::from_midi(mesg)?, mesg[0], mesg[2]) {
+        (Control::FaderTouch(_), _, _) | (_, 0x80, _) | (_, 0x90, 0x00) => None,
+        (Control::Fader(_), _, _) => Some(LearnKind::Continuous),
+        (control, _, value) if control.is_relative() => {
+            (value & 0x3F != 0).then_some(LearnKind::Relative)
+        }
+        (Control::Rec(_) | Control::Solo(_) | Control::Mute(_) | Control::Select(_), _, _) => {
+            Some(LearnKind::Toggle)
+        }
+        _ => Some(LearnKind::Bang),
+    }
+}
+
+/// The control touched, and the recipe ingredients (with their options) matching it.
+pub fn learn_ingredients(mesg: &[u8]) -> Option<(LearnKind, Control, LearnedIngredients)> {
+    let kind = classify(mesg)?;
+    let control = Control::from_midi(mesg)?;
+    let options = IngredientOptions::from([
+        ("mode".to_string(), kind.as_str().to_string()),
+        ("control".to_string(), control.to_string()),
+    ]);
+
+    let ingredients = control
+        .ingredients(vec![], Some(1.0))
+        .into_iter()
+        .map(|ingredient| (ingredient, options.clone()))
+        .collect();
+
+    Some((kind, control, ingredients))
+}
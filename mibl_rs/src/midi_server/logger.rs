@@ -0,0 +1,138 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+
+// LOGGING
+// All the server output goes through `log`, with a target by part of the server :
+//   mibl::server   Start and stop, transports, bridges
+//   mibl::input    Messages of the device, triggers sent to Python
+//   mibl::output   Messages sent to the device (LCD, faders, V-Pots, LEDs)
+//   mibl::recipe   Recipe crafting, options and learn
+//   mibl::timing   Timecode, fps and output rate
+// The level is set by target (`Info` by default), the records of other crates use the default
+// level. The records are buffered, Python drains them into its `logging` module (the logger
+// of "mibl::input" is "mibl.input"). At most `LOG_BUFFER_SIZE` records wait, the oldest are
+// dropped.
+
+pub const SERVER: &str = "mibl::server";
+pub const INPUT: &str = "mibl::input";
+pub const OUTPUT: &str = "mibl::output";
+pub const RECIPE: &str = "mibl::recipe";
+pub const TIMING: &str = "mibl::timing";
+pub const TARGETS: [&str; 5] = [SERVER, INPUT, OUTPUT, RECIPE, TIMING];
+
+pub const LOG_BUFFER_SIZE: usize = 4096;
+
+/// (level, target, message)
+pub type LogRecord = (Level, String, String);
+
+struct BufferedLogger {
+    levels: Mutex<BTreeMap<&'static str, LevelFilter>>,
+    default_level: Mutex<LevelFilter>,
+    records: Mutex<VecDeque<LogRecord>>,
+}
+
+static LOGGER: BufferedLogger = BufferedLogger {
+    levels: Mutex::new(BTreeMap::new()),
+    default_level: Mutex::new(LevelFilter::Info),
+    records: Mutex::new(VecDeque::new()),
+};
+
+impl BufferedLogger {
+    fn level(&self, target: &str) -> LevelFilter {
+        match self.levels.lock().unwrap().get(target) {
+            Some(level) => *level,
+            None => *self.default_level.lock().unwrap(),
+        }
+    }
+}
+
+impl Log for BufferedLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut records = self.records.lock().unwrap();
+
+        if records.len() >= LOG_BUFFER_SIZE {
+            records.pop_front();
+        }
+
+        records.push_back((
+            record.level(),
+            record.target().to_string(),
+            record.args().to_string(),
+        ));
+    }
+
+    fn flush(&self) {}
+}
+
+/// Install the logger, once by process (*c.f* the Python module initialization).
+pub fn init() {
+    if log::set_logger(&LOGGER).is_ok() {
+        update_max_level();
+    }
+}
+
+/// The most verbose level of all targets, so the disabled records are skipped by `log`.
+fn update_max_level() {
+    let levels = LOGGER.levels.lock().unwrap();
+    let default_level = *LOGGER.default_level.lock().unwrap();
+
+    log::set_max_level(levels.values().copied().fold(default_level, Ord::max));
+}
+
+/// Set the level of `target`, or of all targets if `None`.
+pub fn set_level(target: Option<&str>, level: &str) -> Result<(), String> {
+    let level = level
+        .parse::<LevelFilter>()
+        .map_err(|_| format!("Unknown log level : {}", level))?;
+
+    match target {
+        Some(target) => {
+            let target = TARGETS
+                .iter()
+                .find(|known| **known == target)
+                .ok_or(format!("Unknown log target : {}", target))?;
+
+            LOGGER.levels.lock().unwrap().insert(target, level);
+        }
+        None => {
+            LOGGER.levels.lock().unwrap().clear();
+            *LOGGER.default_level.lock().unwrap() = level;
+        }
+    }
+
+    update_max_level();
+    Ok(())
+}
+
+/// The level of each target.
+pub fn levels() -> Vec<(&'static str, LevelFilter)> {
+    TARGETS
+        .iter()
+        .map(|target| (*target, LOGGER.level(target)))
+        .collect()
+}
+
+/// The records waiting for Python, oldest first.
+pub fn drain() -> Vec<LogRecord> {
+    LOGGER.records.lock().unwrap().drain(..).collect()
+}
+
+/// The numeric level of Python `logging`.
+pub fn python_level(level: Level) -> u8 {
+    match level {
+        Level::Error => 40,
+        Level::Warn => 30,
+        Level::Info => 20,
+        Level::Debug => 10,
+        Level::Trace => 5,
+    }
+}
@@ -14,3 +14,73 @@ pub fn split_digits(number_to_split: &usize, vector_size: u8) -> Vec<u8> {
 
     digits
 }
+
+// 14-BIT VALUES (pitch bend, 14-bit CC, NRPN)
+// Two 7 bits data bytes, 0x0000 -> 0x3FFF, centered on 0x2000 (pitch bend at rest).
+// Each half is linear so 0.0, 0.5 and 1.0 are exact, and any raw value round-trips :
+// decode then encode gives back the same bytes.
+
+pub const MAX_14BIT: u16 = 0x3FFF;
+const CENTER_14BIT: u16 = 0x2000;
+
+/// Normalised value (0.0 to 1.0) => (lsb, msb) 7 bits data bytes.
+pub fn encode_14bit(value: f32) -> (u8, u8) {
+    let value = value.clamp(0.0, 1.0);
+    let center = CENTER_14BIT as f32;
+
+    let raw = if value <= 0.5 {
+        (value * 2.0 * center).round() as u16
+    } else {
+        CENTER_14BIT + ((value - 0.5) * 2.0 * (MAX_14BIT - CENTER_14BIT) as f32).round() as u16
+    };
+
+    ((raw & 0x7F) as u8, (raw >> 7 & 0x7F) as u8)
+}
+
+/// (lsb, msb) 7 bits data bytes => normalised value (0.0 to 1.0).
+pub fn decode_14bit(lsb: u8, msb: u8) -> f32 {
+    let raw = (msb as u16 & 0x7F) << 7 | lsb as u16 & 0x7F;
+
+    if raw <= CENTER_14BIT {
+        raw as f32 / (2.0 * CENTER_14BIT as f32)
+    } else {
+        0.5 + (raw - CENTER_14BIT) as f32 / (2.0 * (MAX_14BIT - CENTER_14BIT) as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw(lsb: u8, msb: u8) -> u16 {
+        (msb as u16) << 7 | lsb as u16
+    }
+
+    #[test]
+    fn every_14bit_value_round_trips() {
+        for value in 0..=MAX_14BIT {
+            let (lsb, msb) = ((value & 0x7F) as u8, (value >> 7) as u8);
+            let (lsb_out, msb_out) = encode_14bit(decode_14bit(lsb, msb));
+
+            assert_eq!(raw(lsb_out, msb_out), value, "Raw value {:#06X}", value);
+        }
+    }
+
+    #[test]
+    fn landmarks_are_exact() {
+        for (value, expected) in [(0.0, 0x0000), (0.5, 0x2000), (1.0, 0x3FFF)] {
+            let (lsb, msb) = encode_14bit(value);
+
+            assert_eq!(raw(lsb, msb), expected);
+            assert_eq!(decode_14bit(lsb, msb), value);
+        }
+    }
+
+    #[test]
+    fn out_of_range_values_are_clamped() {
+        assert_eq!(encode_14bit(-0.5), encode_14bit(0.0));
+        assert_eq!(encode_14bit(1.5), encode_14bit(1.0));
+        assert_eq!(encode_14bit(f32::NEG_INFINITY), (0x00, 0x00));
+        assert_eq!(encode_14bit(f32::INFINITY), (0x7F, 0x7F));
+    }
+}
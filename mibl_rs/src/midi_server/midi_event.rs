@@ -1,11 +1,18 @@
 use std::vec;
 
-use crate::midi_server::container::{Event, MidiMesg, Recipe};
+use crate::midi_server::container::{Event, MidiMesg, Recipe, RecipeOptions};
+use crate::midi_server::control::Control;
+use crate::midi_server::curve::Transfer;
+use crate::midi_server::gesture::Gesture;
+use crate::midi_server::group::{group_index, group_names, sys_group};
+use crate::midi_server::hires::HiResParam;
+use crate::midi_server::logger::{INPUT, RECIPE};
+use crate::midi_server::math_utils::decode_14bit;
+use crate::midi_server::modifier::parse_modifiers;
+use crate::midi_server::smoothing::Smoothing;
 use crate::midi_server::sys_event::SYS_EVENT_ARRAY;
 use crate::node_utils::sys_event::convert_half;
 
-const SIZE: usize = 16;
-const EPSILON: f32 = 0.01;
 const CHROM_RANGE: [&str; 12] = [
     "A", "A#", "B", "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#",
 ];
@@ -24,59 +31,24 @@ const CHROM_RANGE: [&str; 12] = [
 pub fn craft_recipe(
     use_sys: &bool,
     custom_events: Option<&Recipe>,
+    options: &RecipeOptions,
 ) -> Result<Option<Vec<Event>>, String> {
     let mut events: Vec<Event> = Vec::new();
     let mut event_idx: u64 = 0;
+    let groups = group_names(*use_sys, options);
 
     if *use_sys {
         for (idx, (value, name)) in SYS_EVENT_ARRAY.iter().enumerate() {
             event_idx = idx as u64;
+            let group = sys_group(*value).and_then(|name| group_index(&groups, name));
             let tmp_events = match *value {
-                0x5B => {
+                // Prev / Next switch Play off through the "transport" group (see `group::switch_off`)
+                0x5B | 0x5C => {
                     vec![Event::new(
                         event_idx,
                         name.to_string(),
                         vec![0x90, *value, 0x7F, 0x80, *value, 0x00],
-                        Some(vec![vec![0x90, *value, 0x00], vec![0x90, 0x5E, 0x00]]),
-                        Some(1.0),
-                        0,
-                        None,
-                        true,
-                        false,
-                    )]
-                }
-                0x5C => {
-                    vec![Event::new(
-                        event_idx,
-                        name.to_string(),
-                        vec![0x90, *value, 0x7F, 0x80, *value, 0x00],
-                        Some(vec![vec![0x90, *value, 0x00], vec![0x90, 0x5E, 0x00]]),
-                        Some(1.0),
-                        0,
-                        None,
-                        true,
-                        false,
-                    )]
-                }
-                0x5D => {
-                    vec![Event::new(
-                        event_idx,
-                        name.to_string(),
-                        vec![0x90, *value, 0x7F, 0x80, *value, 0x00],
-                        Some(vec![vec![0x90, *value, 0x7F], vec![0x90, 0x5E, 0x00]]),
-                        Some(1.0),
-                        0,
-                        None,
-                        true,
-                        false,
-                    )]
-                }
-                0x5E => {
-                    vec![Event::new(
-                        event_idx,
-                        name.to_string(),
-                        vec![0x90, *value, 0x7F, 0x80, *value, 0x00],
-                        Some(vec![vec![0x90, *value, 0x7F], vec![0x90, 0x5D, 0x00]]),
+                        Some(vec![vec![0x90, *value, 0x00]]),
                         Some(1.0),
                         0,
                         None,
@@ -148,77 +120,84 @@ pub fn craft_recipe(
                     true,
                     false,
                 )],
+                // The LEDs of the group members are handled by their group
                 _ => vec![Event::new(
                     event_idx,
                     name.to_string(),
                     vec![0x90, *value, 0x7F, 0x80, *value, 0x00],
-                    Some(vec![vec![0x90, *value, 0x00]]),
+                    group.map_or(Some(vec![vec![0x90, *value, 0x00]]), |_| None),
                     Some(1.0),
                     0,
                     None,
                     true,
-                    true,
+                    group.is_none(),
                 )],
             };
 
             for ev in tmp_events {
                 match ev {
-                    Ok(ev) => events.push(ev),
+                    Ok(ev) => events.push(ev.with_group(group)),
                     Err(err) => panic!("{}", err),
                 }
             }
         }
     }
 
+    // The custom events are numbered after the last sys event, their index is unique
+    // (*e.g* the key of `ToggleStates`)
+    if *use_sys {
+        event_idx = SYS_EVENT_ARRAY.len() as u64;
+    }
+
     if let Some(custom_events) = custom_events {
-        for (ev_in, evs_out, val_out) in custom_events {
+        for (position, (ev_in, evs_out, val_out)) in custom_events.iter().enumerate() {
             let mut note_bang = false;
             let mut vec_out: Vec<Vec<u8>> = vec![];
-            let mut toggable = false;
+            let group = match options
+                .get(&position)
+                .and_then(|options| options.get("group"))
+            {
+                Some(name) if matches!(ev_in[0], 0x80 | 0x90) => group_index(&groups, name),
+                Some(name) => {
+                    log::warn!(
+                        target: RECIPE,
+                        "Group {} ignored for {:X?} : not a button",
+                        name,
+                        ev_in
+                    );
+                    None
+                }
+                None => None,
+            };
+            // A group member is lit by its group, not toggled
+            let toggable = group.is_none()
+                && options
+                    .get(&position)
+                    .and_then(|options| options.get("mode"))
+                    .is_some_and(|mode| mode == "toggle");
 
             let event: Option<Event> = match ev_in[0] {
                 0x90 | 0x80 => {
-                    let name: Option<String> = match ev_in[1] {
-                        0x00..=0x07 => {
-                            note_bang = true;
-                            Some("Rec track button #".to_string() + &ev_in[1].to_string())
-                        }
-                        0x08..=0x0F => {
-                            note_bang = true;
-                            Some("Solo track button #".to_string() + &(ev_in[1] - 0x07).to_string())
+                    let name: Option<String> = match Control::from_midi(ev_in) {
+                        Some(control) => {
+                            note_bang = control.is_bang();
+                            Some(control.to_string())
                         }
-                        0x10..=0x17 => {
-                            note_bang = true;
-                            Some("Mute track button #".to_string() + &(ev_in[1] ^ 0x10).to_string())
-                        }
-                        0x18..=0x1F => {
-                            note_bang = true;
-                            Some(
-                                "Select track button #".to_string()
-                                    + &((ev_in[1] ^ 0x10) - 0x07).to_string(),
-                            )
-                        }
-                        0x20..=0x27 => Some(
-                            "Pan click track button #".to_string() + &(ev_in[1] ^ 0x20).to_string(),
-                        ),
-                        0x32 => {
-                            note_bang = true;
-                            Some("Main track flip button".to_string())
-                        }
-                        0x68..=0x70 => Some(
-                            "Fader Touched #".to_string() + &((ev_in[1] - 0x07) ^ 0x60).to_string(),
-                        ),
-                        _ => {
-                            println!("Unable to generate name for event : {:X?}", ev_in[1]);
+                        None => {
+                            log::warn!(
+                                target: RECIPE,
+                                "Unable to generate name for event : {:X?}",
+                                ev_in[1]
+                            );
                             None
                         }
                     };
 
-                    if name.is_some() {
+                    if let Some(name) = name {
                         let mut vec_in = vec![ev_in[0], ev_in[1], 0x7F];
 
                         if note_bang || ev_in.len() == 6 {
-                            println!("Event seems a Note Bang");
+                            log::debug!(target: RECIPE, "Event seems a Note Bang");
                             vec_in = ev_in.to_vec();
                         }
 
@@ -232,7 +211,7 @@ pub fn craft_recipe(
 
                         match Event::new(
                             event_idx,
-                            name.unwrap(),
+                            name,
                             vec_in,
                             Some(vec_out),
                             *val_out,
@@ -243,7 +222,11 @@ pub fn craft_recipe(
                         ) {
                             Ok(ev) => Some(ev),
                             Err(err) => {
-                                println!("Unable to create custom events : {}", err);
+                                log::warn!(
+                                    target: RECIPE,
+                                    "Unable to create custom events : {}",
+                                    err
+                                );
                                 None
                             }
                         }
@@ -251,10 +234,15 @@ pub fn craft_recipe(
                         None
                     }
                 }
-                0xB0 => {
-                    let name = (ev_in[1] ^ 0x10).to_string();
+                0xB0..=0xBF => {
+                    let hires = HiResParam::from_ingredient(ev_in);
+                    let name = match (hires, Control::from_midi(ev_in)) {
+                        (Some(param), _) => param.to_string(),
+                        (None, Some(control)) => control.to_string(),
+                        (None, None) => format!("CC #{}", ev_in[1]),
+                    };
 
-                    if ev_in[2] == 0x01 || ev_in[2] == 0x41 {
+                    if hires.is_some() || ev_in.len() == 3 && matches!(ev_in[2], 0x01 | 0x41) {
                         match Event::new(
                             event_idx,
                             name,
@@ -268,16 +256,20 @@ pub fn craft_recipe(
                         ) {
                             Ok(ev) => Some(ev),
                             Err(err) => {
-                                println!("Unable to create custom events : {}", err);
-                                break;
+                                log::warn!(
+                                    target: RECIPE,
+                                    "Unable to create custom events : {}",
+                                    err
+                                );
+                                None
                             }
-                        };
-                    };
-
-                    None
+                        }
+                    } else {
+                        None
+                    }
                 }
-                0xE0..=0xE7 => {
-                    let name = format!("Fader #{}", (ev_in[0] ^ 0xE0));
+                0xE0..=0xE8 => {
+                    let name = Control::Fader(ev_in[0] & 0x0F).to_string();
 
                     match Event::new(
                         event_idx,
@@ -292,7 +284,7 @@ pub fn craft_recipe(
                     ) {
                         Ok(ev) => Some(ev),
                         Err(err) => {
-                            println!("Unable to create custom events : {}", err);
+                            log::warn!(target: RECIPE, "Unable to create custom events : {}", err);
                             None
                         }
                     }
@@ -300,9 +292,67 @@ pub fn craft_recipe(
                 _ => None,
             };
 
+            // The recogniser only tracks the buttons, a fader or V-Pot event would never fire
+            let gesture = match options.get(&position).map(Gesture::from_options) {
+                Some(Ok(Some(_))) if !matches!(ev_in[0], 0x80 | 0x90) => {
+                    log::warn!(target: RECIPE, "Gesture ignored for {:X?} : not a button", ev_in);
+                    None
+                }
+                Some(Ok(gesture)) => gesture,
+                Some(Err(err)) => {
+                    log::warn!(target: RECIPE, "Gesture ignored for {:X?} : {}", ev_in, err);
+                    None
+                }
+                None => None,
+            };
+
+            let modifiers = match options
+                .get(&position)
+                .and_then(|options| options.get("modifiers"))
+                .map(|names| parse_modifiers(names))
+            {
+                Some(Ok(modifiers)) => Some(modifiers),
+                Some(Err(err)) => {
+                    log::warn!(target: RECIPE, "Modifiers ignored for {:X?} : {}", ev_in, err);
+                    None
+                }
+                None => None,
+            };
+
+            let transfer = match options.get(&position).map(Transfer::from_options) {
+                Some(Ok(transfer)) => transfer,
+                Some(Err(err)) => {
+                    log::warn!(target: RECIPE, "Transfer ignored for {:X?} : {}", ev_in, err);
+                    None
+                }
+                None => None,
+            };
+
+            let smoothing = match options.get(&position).map(Smoothing::from_options) {
+                Some(Ok(smoothing)) => smoothing,
+                Some(Err(err)) => {
+                    log::warn!(target: RECIPE, "Smoothing ignored for {:X?} : {}", ev_in, err);
+                    None
+                }
+                None => None,
+            };
+
             match event {
-                Some(ev) => events.push(ev),
-                None => println!("Event not added into queue… {:X?} => {:X?}", ev_in, evs_out),
+                Some(ev) => events.push(
+                    ev.with_gesture(gesture)
+                        .with_modifiers(modifiers)
+                        .with_group(group)
+                        .with_transfer(transfer)
+                        .with_smoothing(smoothing),
+                ),
+                None => {
+                    log::warn!(
+                        target: RECIPE,
+                        "Event not added into queue… {:X?} => {:X?}",
+                        ev_in,
+                        evs_out
+                    )
+                }
             }
 
             event_idx += 1;
@@ -342,7 +392,7 @@ pub fn get_note_name(note: u8) -> &'static str {
             return CHROM_RANGE[note_idx];
         }
         0..=20 => return "NiR",
-        _ => log::error!("Note value superior to 127 !"),
+        _ => log::error!(target: INPUT, "Note value superior to 127 !"),
     }
 
     ""
@@ -353,7 +403,7 @@ pub fn get_octave(note: u8) -> u8 {
         21..=127 => {
             return (note / 12) - 1;
         }
-        _ => log::warn!("Note not in standard range !"),
+        _ => log::warn!(target: INPUT, "Note not in standard range !"),
     }
 
     10
@@ -376,12 +426,12 @@ pub fn process_note(cmd: u8, note: u8, vel: u8) -> MidiMesg {
 
     if cmd == 0x80 || vel == 0 {
         if note_octave == 10 {
-            println!("Note off : {}", note_num);
+            log::debug!(target: INPUT, "Note off : {}", note_num);
             midi_mesg.name = format!("Note off : {}", note_num);
             midi_mesg.value = 0.0;
             return midi_mesg;
         }
-        println!("Note off : {}{}", note_name, note_octave);
+        log::debug!(target: INPUT, "Note off : {}{}", note_name, note_octave);
         midi_mesg.name = format!("Note off : {}{}", note_name, note_octave);
         midi_mesg.value = 0.0;
         return midi_mesg;
@@ -390,13 +440,13 @@ pub fn process_note(cmd: u8, note: u8, vel: u8) -> MidiMesg {
     let note_vel = convert_half(vel);
 
     if note_octave == 10 {
-        println!("Note on : {} (vel: {})", note_num, note_vel);
+        log::debug!(target: INPUT, "Note on : {} (vel: {})", note_num, note_vel);
         midi_mesg.name = format!("Note on : {} (vel: {})", note_num, note_vel);
         midi_mesg.value = note_vel;
         return midi_mesg;
     }
 
-    println!("Note on : {}{} (vel: {})", note_name, note_octave, note_vel);
+    log::debug!(target: INPUT, "Note on : {}{} (vel: {})", note_name, note_octave, note_vel);
     midi_mesg.name = format!("Note on : {}{} (vel: {})", note_name, note_octave, note_vel);
     midi_mesg.value = note_vel;
 
@@ -430,10 +480,10 @@ pub fn process_cc(cc_num: u8, cc_msb_value: u8, cc_lsb_value: Option<u8>) -> Mid
 
     fn print_cc_value(cc_num: u8, cc_value: u16) {
         match cc_num {
-            0x00 => println!("CC not used in Blender Midi"),
-            0x01 => println!("Modulation wheel : {}", cc_value),
-            0x05 => println!("Portamento : {}", cc_value),
-            _ => println!("CC #{} : {}", cc_num, cc_value),
+            0x00 => log::debug!(target: INPUT, "CC not used in Blender Midi"),
+            0x01 => log::debug!(target: INPUT, "Modulation wheel : {}", cc_value),
+            0x05 => log::debug!(target: INPUT, "Portamento : {}", cc_value),
+            _ => log::debug!(target: INPUT, "CC #{} : {}", cc_num, cc_value),
         }
     }
 
@@ -444,7 +494,7 @@ pub fn process_cc(cc_num: u8, cc_msb_value: u8, cc_lsb_value: Option<u8>) -> Mid
         }
         Some(cc_lsb_value) => {
             let u16_cc_value = (cc_msb_value as u16) << 7 | cc_lsb_value as u16;
-            midi_mesg.value = (u16_cc_value as f32) / 16384.0;
+            midi_mesg.value = decode_14bit(cc_lsb_value, cc_msb_value);
             print_cc_value(cc_num, u16_cc_value);
         }
     };
@@ -455,10 +505,10 @@ pub fn process_cc(cc_num: u8, cc_msb_value: u8, cc_lsb_value: Option<u8>) -> Mid
 }
 
 pub fn process_sys(event: &[u8]) {
-    println!("SysEx not implemented yet !");
+    log::debug!(target: INPUT, "SysEx not implemented yet !");
 
     if !event.contains(&0xF7) {
-        log::warn!("SysEx send without tail !");
+        log::warn!(target: INPUT, "SysEx send without tail !");
     }
 
     let _end_pos = event.iter().position(|&x| x == 0xF7);
@@ -468,12 +518,61 @@ pub fn process_pitch_bend(pitch: (u8, u8)) -> MidiMesg {
     let mut midi_mesg = MidiMesg::new();
 
     let (lsb, msb) = pitch;
-    let u16_pitch = (msb as u16) << 7 | lsb as u16;
-    let norm_pitch = (u16_pitch as f32) / 16384.0;
-    println!("Pitch bend values : {}", norm_pitch);
+    let norm_pitch = decode_14bit(lsb, msb);
+    log::trace!(target: INPUT, "Pitch bend values : {}", norm_pitch);
 
     midi_mesg.name = "Pitch bend".to_string();
     midi_mesg.value = norm_pitch;
 
     midi_mesg
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_events_follow_the_sys_events() {
+        let recipe = vec![
+            (vec![0x90, 0x10, 0x7F], vec![], Some(1.0)),
+            (vec![0xE0, 0x00, 0x00], vec![], None),
+        ];
+        let events = craft_recipe(&true, Some(&recipe), &Default::default())
+            .unwrap()
+            .unwrap();
+        let last_sys = events
+            .iter()
+            .filter(|event| *event.get_index() < SYS_EVENT_ARRAY.len() as u64)
+            .map(|event| *event.get_index())
+            .max();
+        let custom: Vec<u64> = events[events.len() - 2..]
+            .iter()
+            .map(|event| *event.get_index())
+            .collect();
+
+        assert_eq!(last_sys, Some(SYS_EVENT_ARRAY.len() as u64 - 1));
+        assert_eq!(
+            custom,
+            vec![
+                SYS_EVENT_ARRAY.len() as u64,
+                SYS_EVENT_ARRAY.len() as u64 + 1
+            ]
+        );
+    }
+
+    #[test]
+    fn prev_and_next_leave_play_to_its_group() {
+        let events = craft_recipe(&true, None, &Default::default())
+            .unwrap()
+            .unwrap();
+
+        for note in [0x5B, 0x5C] {
+            let event = events
+                .iter()
+                .find(|event| event.get_mesg_in().get(1) == Some(&note))
+                .unwrap();
+
+            assert_eq!(event.get_mesg_data(), &Some(vec![vec![0x90, note, 0x00]]));
+        }
+    }
+}
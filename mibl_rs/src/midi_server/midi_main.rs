@@ -1,194 +1,491 @@
-use log::{error, info, warn};
-use midir::MidiOutputConnection;
-
 use crate::midi_server::container::{DeviceState, Event, ExtTrigger, RawMidi, Recipe, SIGflag};
+use crate::midi_server::curve::fader_position;
+use crate::midi_server::dispatch::DispatchTable;
+use crate::midi_server::gesture::GestureAction;
+use crate::midi_server::learn::classify;
+use crate::midi_server::logger::{INPUT, OUTPUT, RECIPE, SERVER, TIMING};
 use crate::midi_server::midi_event::craft_recipe;
 use crate::midi_server::midi_process_mesg::process_midi_mesg;
 use crate::midi_server::midi_send_mesg::{
     gen_lcd_string, initialize_mc_device, reset_mc_device, signal_handling, timestamp_gen,
+    toggle_leds,
 };
-use crate::midi_server::setup_client_params::setup_client_params;
+use crate::midi_server::output::OutputStage;
+use crate::midi_server::recorder::Direction;
+use crate::midi_server::ring::input_ring;
+use crate::midi_server::transport::MidiTransport;
 use std::sync::mpsc::{channel, Sender};
 use std::sync::{Arc, Mutex};
-use std::thread::sleep;
-use std::time::Duration;
+use std::thread::{park_timeout, sleep, spawn};
+use std::time::{Duration, Instant};
 
-pub fn init_midi_audio(
+pub fn init_midi_audio<T: MidiTransport>(
+    mut transport: T,
     tx: Sender<Vec<ExtTrigger>>,
-    ext_signal: Sender<DeviceState>,
     int_signal: Arc<Mutex<SIGflag>>,
     recipe: Arc<Mutex<Recipe>>,
     device_params: Arc<Mutex<DeviceState>>,
+    triggers_events: Arc<Mutex<Option<Vec<Event>>>>,
 ) {
     let debug = int_signal.lock().unwrap().debug;
+    let mut output = OutputStage::new(int_signal.lock().unwrap().output_rate);
+    let monitor = int_signal.lock().unwrap().monitor.clone();
+
+    if debug {
+        log::debug!(target: SERVER, "Connect to port : {}", transport.port_name());
+        log::debug!(target: SERVER, "Available ports : {:?}", transport.ports());
+    }
+
+    sleep(Duration::from_millis(50)); // Wait a little time after creating connection output
+
+    // Built first, the LEDs of the toggled events are part of the initialization
+    let recipe_lock = recipe.lock().unwrap().clone();
+    let mut opt_recipe = None;
+    let use_sys_event = int_signal.lock().unwrap().use_sys_event;
+    let recipe_options = int_signal.lock().unwrap().recipe_options.clone();
+
+    if !recipe_lock.is_empty() {
+        opt_recipe = Some(&recipe_lock);
+    }
+
+    *triggers_events.lock().unwrap() =
+        match craft_recipe(&use_sys_event, opt_recipe, &recipe_options) {
+            Ok(events) => {
+                log::debug!(target: RECIPE, "Build triggers before conn_in");
+                events
+            }
+            Err(err) => panic!("Unable to create the trigger table ! {}", err),
+        };
+    let mut sig_flag = int_signal.lock().unwrap();
+    sig_flag.dispatch = Arc::new(DispatchTable::new(&triggers_events.lock().unwrap()));
+    sig_flag
+        .gestures
+        .set_events(&triggers_events.lock().unwrap());
+    drop(sig_flag);
+
+    drop(recipe_lock);
+
+    match device_params.lock() {
+        Ok(device_params_lock) => {
+            let events = triggers_events.lock().unwrap();
+            let toggles = toggle_leds(&int_signal.lock().unwrap().toggle_states, &events);
+
+            // The faders show the values of Python through the transfer of their events
+            let mut init_values = device_params_lock.clone();
+            init_values.set_faders(
+                device_params_lock
+                    .get_faders()
+                    .iter()
+                    .map(|(fader, value)| (*fader, fader_position(&events, *fader, *value)))
+                    .collect(),
+            );
+            drop(events);
+
+            let init_mesgs = initialize_mc_device(&init_values, &toggles).unwrap();
 
-    match setup_client_params() {
-        Ok(params) => {
             if debug {
-                println!("Connect to port : {}", params.port_name);
+                log::debug!(target: OUTPUT, "Sending all messages to midi device now…");
             }
 
-            let mut conn_out: MidiOutputConnection = match params
-                .midi_output
-                .connect(&params.midi_output_port, "bl-midi-out")
-            {
-                Ok(out) => out,
-                Err(err) => {
-                    println!("Unable to connect to output : {}", err);
-                    return;
+            let init_mesgs_len = init_mesgs.len();
+
+            for (idx, mesg) in init_mesgs.iter().enumerate() {
+                log::debug!(
+                    target: OUTPUT,
+                    "Sending mesg {}/{} : {:04X?}",
+                    (idx + 1),
+                    init_mesgs_len,
+                    mesg.data()
+                );
+
+                monitor.record(Direction::Out, mesg.data(), vec![]);
+                if transport.send(mesg.data()).is_ok() {
+                    output.mark_shown(mesg.data());
                 }
-            };
+                sleep(Duration::from_millis(10));
+            }
+        }
+        Err(err) => {
+            log::error!(
+                target: SERVER,
+                "Unable to access device state for initialization, closing thread : {}",
+                err
+            );
+            transport.close();
+            return;
+        }
+    }
 
-            sleep(Duration::from_millis(50)); // Wait a little time after creating connection output
+    if debug {
+        log::debug!(target: RECIPE, "Triggers Events : {:?}", triggers_events.lock().unwrap());
 
-            match device_params.lock() {
-                Ok(device_params_lock) => {
-                    let init_mesgs = initialize_mc_device(&device_params_lock).unwrap();
+        log::debug!(target: SERVER, "Initialization done!");
+    }
 
-                    if debug {
-                        println!("Sending all messages to midi device now…");
-                    }
+    let (int_tx, int_rx) = channel();
+    let tick_tx = tx.clone();
+
+    let midi_datas = (
+        int_signal.clone(),
+        int_tx.clone(),
+        tx,
+        triggers_events.clone(),
+        device_params.clone(),
+    );
+
+    // The transport callback only queues the messages, the worker processes them
+    // (see `src/midi_server/ring.rs`)
+    let (mut producer, mut consumer) = input_ring();
+
+    let worker = spawn(move || loop {
+        while let Some(raw_midi) = consumer.pop() {
+            process_input(
+                &raw_midi,
+                &midi_datas.0,
+                &midi_datas.1,
+                &midi_datas.2,
+                &midi_datas.3,
+                &midi_datas.4,
+            );
+        }
 
-                    let init_mesgs_len = init_mesgs.len();
+        let dropped = consumer.take_dropped();
+        if dropped > 0 {
+            log::warn!(
+                target: INPUT,
+                "{} input messages dropped (input ring full)",
+                dropped
+            );
+        }
 
-                    for (idx, mesg) in init_mesgs.iter().enumerate() {
-                        println!(
-                            "Sending mesg {}/{} : {:04X?}",
-                            (idx + 1),
-                            init_mesgs_len,
-                            mesg.data()
-                        );
+        if midi_datas.0.lock().unwrap().stop_thread {
+            return;
+        }
 
-                        let _ = conn_out.send(mesg.data());
-                        sleep(Duration::from_millis(10));
-                    }
-                }
-                Err(err) => {
-                    println!(
-                        "Unable to access device state for initialization, closing thread : {}",
-                        err
-                    );
-                    return;
-                }
-            }
+        park_timeout(Duration::from_millis(10));
+    });
+    let waker = worker.thread().clone();
+    let realtime = transport.is_realtime();
+
+    if let Err(err) = transport.listen(Box::new(move |stamp, message| {
+        match realtime {
+            true => producer.push(stamp, message),
+            false => producer.push_waiting(stamp, message, &waker),
+        };
+        waker.unpark();
+    })) {
+        log::error!(target: SERVER, "Unable to listen to input : {}", err);
+    }
 
-            let triggers_events: Arc<Mutex<Option<Vec<Event>>>> = Arc::new(Mutex::new(None));
+    let int_signal_arc = Arc::clone(&int_signal);
+    let device_params_lock = device_params.lock().unwrap();
+    let mut fps = *device_params_lock.get_fps();
+    drop(device_params_lock);
 
-            let recipe_lock = recipe.lock().unwrap().clone();
-            let mut opt_recipe = None;
-            let use_sys_event = int_signal.lock().unwrap().use_sys_event;
+    let mut duration: u64 = 1000 / fps;
 
-            if !recipe_lock.is_empty() {
-                opt_recipe = Some(&recipe_lock);
-            }
+    loop {
+        if int_signal_arc.lock().unwrap().stop_thread {
+            transport.close();
+            worker.thread().unpark();
+            let _ = worker.join();
+            return;
+        }
+
+        if int_signal_arc.lock().unwrap().update_fps {
+            fps = *device_params.lock().unwrap().get_fps();
+            duration = 1000 / fps;
+            log::debug!(target: TIMING, "Server loop at {} fps ({} ms)", fps, duration);
+            int_signal_arc.lock().unwrap().update_fps = false;
+        }
 
-            *triggers_events.lock().unwrap() = match craft_recipe(&use_sys_event, opt_recipe) {
-                Ok(events) => {
-                    println!("Build triggers before conn_in");
-                    events
+        let timestamp = *device_params.lock().unwrap().get_timestamp();
+        match timestamp_gen(timestamp[0], timestamp[1], timestamp[2], timestamp[3]) {
+            Ok(raw_timestamp) => {
+                for raw_midi in raw_timestamp {
+                    output.push(raw_midi.data());
                 }
-                Err(err) => panic!("Unable to create the trigger table ! {}", err),
-            };
+            }
+            Err(err) => {
+                log::warn!(target: TIMING, "Unable to generate timestamp, continue… {}", err)
+            }
+        }
 
-            drop(recipe_lock);
+        if let Some(raw_midi_mesg) = signal_handling(
+            &int_signal,
+            &triggers_events,
+            &recipe,
+            &device_params,
+            debug,
+        ) {
+            for raw_midi in raw_midi_mesg {
+                output.push(raw_midi.data());
+            }
+        }
 
-            if debug {
-                println!("Triggers Events : {:?}", triggers_events.lock().unwrap());
+        let mut sig_flag = int_signal_arc.lock().unwrap();
+        let events = triggers_events.lock().unwrap();
+        let actions = sig_flag.gestures.poll(Instant::now());
+        apply_gesture_actions(
+            actions,
+            &mut sig_flag,
+            &int_tx,
+            &tick_tx,
+            &device_params,
+            &events,
+        );
+        drop(events);
+
+        let smoothed = sig_flag.smoother.tick(Instant::now());
+        if !smoothed.is_empty() {
+            let _ = tick_tx.send(smoothed);
+        }
 
-                println!("Initialization done!");
+        // Under the lock : a reset flags the stage before it queues its messages
+        if sig_flag.output_reset {
+            output.forget();
+            sig_flag.output_reset = false;
+        }
+
+        while let Ok(rx_data) = int_rx.try_recv() {
+            for midi_data in rx_data {
+                output.push(&midi_data);
             }
+        }
+        drop(sig_flag);
 
-            let (int_tx, int_rx) = channel();
-
-            let _conn_in = params.midi_input.connect(
-                &params.midi_input_port,
-                "bl-midi-in",
-                move |stamp, message, midi_datas| {
-                    input_callback(
-                        (&stamp, message),
-                        &midi_datas.0,
-                        &midi_datas.1,
-                        &midi_datas.2,
-                        &midi_datas.3,
-                        &midi_datas.4,
-                    );
-                },
-                (
-                    int_signal.clone(),
-                    int_tx.clone(),
-                    tx,
-                    triggers_events.clone(),
-                    device_params.clone(),
-                ),
-            );
+        for mesg in output.drain(Instant::now()) {
+            monitor.record(Direction::Out, &mesg, vec![]);
+            send_or_log(&mut transport, &mesg);
+        }
 
-            let int_signal_arc = Arc::clone(&int_signal);
-            let device_params_lock = device_params.lock().unwrap();
-            let mut fps = *device_params_lock.get_fps();
-            drop(device_params_lock);
+        sleep(Duration::from_millis(duration));
+    }
+}
 
-            let mut duration: u64 = 1000 / fps;
+fn send_or_log<T: MidiTransport>(transport: &mut T, mesg: &[u8]) {
+    if let Err(err) = transport.send(mesg) {
+        log::warn!(
+            target: OUTPUT,
+            "Unable to send {:04X?} to {} : {}",
+            mesg,
+            transport.port_name(),
+            err
+        );
+    }
+}
 
-            loop {
-                if int_signal_arc.lock().unwrap().stop_thread {
-                    return;
-                }
+/// Record a message of the device with the events it triggered (see `src/midi_server/monitor.rs`).
+fn monitor_input(sig_flag: &SIGflag, mesg: &[u8], matched: &[u64], events: &Option<Vec<Event>>) {
+    if !sig_flag.monitor.is_enabled() {
+        return;
+    }
 
-                if int_signal_arc.lock().unwrap().update_fps {
-                    fps = *device_params.lock().unwrap().get_fps();
-                    duration = 1000 / fps;
-                    int_signal_arc.lock().unwrap().update_fps = false;
-                }
+    let names = matched
+        .iter()
+        .map(|index| {
+            let name = events
+                .iter()
+                .flatten()
+                .find(|event| event.get_index() == index)
+                .map(|event| event.get_name().to_string())
+                .unwrap_or_default();
+
+            (*index, name)
+        })
+        .collect();
+
+    sig_flag.monitor.record(Direction::In, mesg, names);
+}
 
-                let timestamp = *device_params.lock().unwrap().get_timestamp();
-                match timestamp_gen(timestamp[0], timestamp[1], timestamp[2], timestamp[3]) {
-                    Ok(raw_timestamp) => {
-                        for raw_midi in raw_timestamp {
-                            conn_out.send(raw_midi.data()).unwrap();
-                        }
-                    }
-                    Err(err) => println!("Unable to generate timestamp, continue… {}", err),
-                }
+/// Send the triggers of the gesture events, reset or panic.
+fn apply_gesture_actions(
+    actions: Vec<GestureAction>,
+    sig_flag: &mut SIGflag,
+    int_tx: &Sender<Vec<Vec<u8>>>,
+    ext_tx: &Sender<Vec<ExtTrigger>>,
+    device_params: &Arc<Mutex<DeviceState>>,
+    events: &Option<Vec<Event>>,
+) {
+    let mut triggers = vec![];
+
+    for action in actions {
+        match action {
+            GestureAction::Trigger(trigger) => triggers.push(trigger),
+            GestureAction::Reset => {
+                let toggles = toggle_leds(&sig_flag.toggle_states, events);
+                reset_device(sig_flag, int_tx, device_params, &toggles)
+            }
+            GestureAction::Panic => panic(sig_flag, int_tx, device_params, events),
+        }
+    }
 
-                if let Some(raw_midi_mesg) = signal_handling(
-                    &int_signal,
-                    &triggers_events,
-                    &recipe,
-                    &device_params,
-                    debug,
-                ) {
-                    for raw_midi in raw_midi_mesg {
-                        conn_out.send(raw_midi.data()).unwrap();
+    if !triggers.is_empty() {
+        let _ = ext_tx.send(triggers);
+    }
+}
+
+/// Clear the LCD, reset the device then send the current `DeviceState` and toggle LEDs.
+///
+/// Called with the `SIGflag` and events locked : the messages are only queued, the output stage
+/// paces them toward the device.
+fn reset_device(
+    sig_flag: &mut SIGflag,
+    int_tx: &Sender<Vec<Vec<u8>>>,
+    device_params: &Arc<Mutex<DeviceState>>,
+    toggles: &[(u8, bool)],
+) {
+    // The whole state is sent again, even what the surface showed before the reset
+    sig_flag.output_reset = true;
+
+    match reset_mc_device() {
+        Ok(to_send) => {
+            match gen_lcd_string(0, None) {
+                Ok(raw_midi_mesgs) => {
+                    let mut mesgs = vec![];
+                    for mesg in raw_midi_mesgs {
+                        mesgs.push(mesg.data().to_vec());
                     }
+                    let _ = int_tx.send(mesgs);
                 }
+                Err(err) => log::warn!(target: OUTPUT, "Unable to clear lcd string : {}", err),
+            };
 
-                if let Ok(rx_data) = int_rx.try_recv() {
-                    for midi_data in rx_data {
-                        conn_out.send(&midi_data).unwrap();
+            match gen_lcd_string(0, Some("Reseting device !".to_string())) {
+                Ok(raw_midi_mesgs) => {
+                    let mut mesgs = vec![];
+                    for mesg in raw_midi_mesgs {
+                        mesgs.push(mesg.data().to_vec());
                     }
+                    let _ = int_tx.send(mesgs);
+                }
+                Err(err) => {
+                    log::warn!(target: OUTPUT, "Unable to generate reset info lcd string : {}", err)
                 }
+            };
+
+            let _ = int_tx.send(to_send.iter().map(|mesg| mesg.data().to_vec()).collect());
+
+            let mut init_mesgs: Vec<RawMidi> = vec![];
 
-                sleep(Duration::from_millis(duration));
+            match device_params.lock() {
+                Ok(device_params_lock) => {
+                    init_mesgs = initialize_mc_device(&device_params_lock, toggles).unwrap()
+                }
+                Err(err) => {
+                    log::warn!(
+                        target: OUTPUT,
+                        "Unable to lock device state skip device reset : {}",
+                        err
+                    )
+                }
             }
+
+            let _ = int_tx.send(init_mesgs.iter().map(|mesg| mesg.data().to_vec()).collect());
+        }
+        Err(err) => log::error!(target: OUTPUT, "Unable to reset device : {}", err),
+    }
+}
+
+/// Silence everything, forget the toggle states, held buttons and latched modifiers,
+/// re-initialise the surface and tell Python (`SIGflag.panic_signal`).
+fn panic(
+    sig_flag: &mut SIGflag,
+    int_tx: &Sender<Vec<Vec<u8>>>,
+    device_params: &Arc<Mutex<DeviceState>>,
+    events: &Option<Vec<Event>>,
+) {
+    log::warn!(target: SERVER, "PANIC ! Re-initialising the surface");
+
+    let mut mesgs = vec![];
+
+    for (note, on) in toggle_leds(&sig_flag.toggle_states, events) {
+        if on {
+            mesgs.push(vec![0x90, note, 0x00]);
         }
-        Err(err) => println!("Unable to initialize device, continue : {}", err),
     }
+    sig_flag.toggle_states.clear();
+    sig_flag.toggles_changed = true;
+
+    mesgs.extend(sig_flag.modifiers.clear());
+
+    // All notes off and reset all controllers, for a synth behind the transport
+    // (a Mackie Control ignores them)
+    for channel in 0..16 {
+        mesgs.push(vec![0xB0 | channel, 0x7B, 0x00]);
+        mesgs.push(vec![0xB0 | channel, 0x79, 0x00]);
+    }
+
+    let _ = int_tx.send(mesgs);
+
+    sig_flag.note_on = false;
+    sig_flag.note_bang = false;
+    sig_flag.note_bang_value = 0;
+    sig_flag.note_need_toggle = false;
+    sig_flag.hires = Default::default();
+    sig_flag.gestures.clear();
+    sig_flag.smoother.clear();
+    sig_flag.panic_signal = true;
+
+    reset_device(sig_flag, int_tx, device_params, &[]);
 }
 
-fn input_callback(
-    data_in: (&u64, &[u8]),
+/// Process a message of the device, on the worker thread.
+fn process_input(
+    raw_midi: &RawMidi,
     sigflag: &Arc<Mutex<SIGflag>>,
     int_tx: &Sender<Vec<Vec<u8>>>,
     ext_tx: &Sender<Vec<ExtTrigger>>,
     triggers: &Arc<Mutex<Option<Vec<Event>>>>,
     device_params: &Arc<Mutex<DeviceState>>,
 ) {
-    let (stamp, mesg) = data_in;
-    let raw_midi = RawMidi::new(*stamp, mesg).unwrap();
+    let mesg = raw_midi.data();
     let mut sig_flag = sigflag.lock().unwrap();
 
+    // System Reset, not echoed to the device
+    if mesg[0] == 0xFF {
+        monitor_input(&sig_flag, mesg, &[], &triggers.lock().unwrap());
+        panic(
+            &mut sig_flag,
+            int_tx,
+            device_params,
+            &triggers.lock().unwrap(),
+        );
+        return;
+    }
+
     int_tx.send(vec![mesg.to_vec()]).unwrap();
 
+    // Learning : the control is captured for the server loop, not processed
+    if sig_flag.learn_armed && classify(mesg).is_some() {
+        sig_flag.learn_armed = false;
+        sig_flag.learned = Some(mesg.to_vec());
+        monitor_input(&sig_flag, mesg, &[], &triggers.lock().unwrap());
+        return;
+    }
+
+    // Tracked before processing, so the events of the active layer are triggered
+    let modifier_led = sig_flag.modifiers.on_message(mesg);
+
+    let events = triggers.lock().unwrap();
+    let actions = sig_flag.gestures.on_message(mesg, Instant::now());
+    let mut matched: Vec<u64> = actions
+        .iter()
+        .filter_map(|action| match action {
+            GestureAction::Trigger((index, _)) => Some(*index),
+            _ => None,
+        })
+        .collect();
+    apply_gesture_actions(
+        actions,
+        &mut sig_flag,
+        int_tx,
+        ext_tx,
+        device_params,
+        &events,
+    );
+    drop(events);
+
     if mesg[0] == 0x90 {
         sig_flag.note_on = true;
         sig_flag.note_bang_value = mesg[1];
@@ -201,70 +498,20 @@ fn input_callback(
         sig_flag.note_bang_value = 0;
     }
 
-    if mesg[0] == 0x80 && mesg[1] == 0x52 {
-        if !sig_flag.reset_signal {
-            sig_flag.reset_signal = true;
-        }
-    } else if sig_flag.reset_signal && mesg[0] == 0x90 && mesg[1] == 0x52 {
-        match reset_mc_device() {
-            Ok(to_send) => {
-                match gen_lcd_string(0, None) {
-                    Ok(raw_midi_mesgs) => {
-                        let mut mesgs = vec![];
-                        for mesg in raw_midi_mesgs {
-                            mesgs.push(mesg.data().to_vec());
-                        }
-                        let _ = int_tx.send(mesgs);
-                    }
-                    Err(err) => println!("Unable to clear lcd string : {}", err),
-                };
-
-                match gen_lcd_string(0, Some("Reseting device !".to_string())) {
-                    Ok(raw_midi_mesgs) => {
-                        let mut mesgs = vec![];
-                        for mesg in raw_midi_mesgs {
-                            mesgs.push(mesg.data().to_vec());
-                        }
-                        let _ = int_tx.send(mesgs);
-                    }
-                    Err(err) => println!("Unable to generate reset info lcd string : {}", err),
-                };
-
-                for mesg in to_send.iter() {
-                    let _ = int_tx.send(vec![mesg.data().to_vec()]);
-                    sleep(Duration::from_millis(10));
-                }
-
-                sleep(Duration::from_millis(100));
-                let mut init_mesgs: Vec<RawMidi> = vec![];
+    let midi_result = process_midi_mesg(raw_midi, "MC", &mut sig_flag, &triggers.lock().unwrap());
 
-                match device_params.lock() {
-                    Ok(device_params_lock) => {
-                        init_mesgs = initialize_mc_device(&device_params_lock).unwrap()
-                    }
-                    Err(err) => println!("Unable to lock device state skip device reset : {}", err),
-                }
+    match midi_result {
+        Ok(mesgs) => {
+            matched.extend(&mesgs.matched);
 
-                for mesg in init_mesgs.iter() {
-                    let _ = int_tx.send(vec![mesg.data().to_vec()]);
-                    sleep(Duration::from_millis(10));
-                }
+            if let Some(debug_mesg) = &mesgs.debug {
+                log::debug!(target: INPUT, "Midi message : {:?}", debug_mesg);
             }
-            Err(err) => println!("Unable to reset device : {}", err),
-        }
-        sig_flag.reset_signal = false;
-    } else if sig_flag.reset_signal {
-        sig_flag.reset_signal = false;
-    }
-
-    let midi_result = process_midi_mesg(&raw_midi, "MC", &mut sig_flag, &triggers.lock().unwrap());
 
-    match midi_result {
-        Ok(mesgs) => {
             match mesgs.to_send.0 {
                 Some(mesgs) => {
                     if sig_flag.debug {
-                        println!("Sending triggered data to midi port !");
+                        log::debug!(target: OUTPUT, "Sending triggered data to midi port !");
                     }
                     let mut midi_datas = vec![];
 
@@ -275,13 +522,17 @@ fn input_callback(
                     match int_tx.send(midi_datas) {
                         Ok(_) => (),
                         Err(err) => {
-                            println!("Unable to send mesg to Internal Sender : {}", err)
+                            log::error!(
+                                target: OUTPUT,
+                                "Unable to send mesg to Internal Sender : {}",
+                                err
+                            )
                         }
                     }
                 }
                 None => {
                     if sig_flag.debug {
-                        println!("Nothing to send to internal midi server !")
+                        log::debug!(target: OUTPUT, "Nothing to send to internal midi server !")
                     }
                 }
             }
@@ -289,23 +540,178 @@ fn input_callback(
             match mesgs.to_send.1 {
                 Some(mesgs) => {
                     if sig_flag.debug {
-                        println!("Preparing to send trigger to client : {:04X?}", mesgs);
+                        log::debug!(
+                            target: INPUT,
+                            "Preparing to send trigger to client : {:04X?}",
+                            mesgs
+                        );
                     }
 
                     match ext_tx.send(mesgs) {
                         Ok(_) => (),
                         Err(err) => {
-                            println!("Error when sending midi mesg to output : {}", err)
+                            log::error!(
+                                target: INPUT,
+                                "Error when sending midi mesg to output : {}",
+                                err
+                            )
                         }
                     }
                 }
                 None => {
                     if sig_flag.debug {
-                        println!("Nothing to send, skip it…")
+                        log::debug!(target: INPUT, "Nothing to send, skip it…")
                     }
                 }
             }
         }
-        Err(err) => println!("No midi mesg output : {}", err),
+        Err(err) => log::warn!(target: INPUT, "No midi mesg output : {}", err),
     };
+
+    monitor_input(&sig_flag, mesg, &matched, &triggers.lock().unwrap());
+
+    // Sent last, the LED of a latched modifier overrides the echo and the feedback of the event
+    if let Some(modifier_led) = modifier_led {
+        let _ = int_tx.send(vec![modifier_led]);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::midi_server::transport::{LoopbackHandle, LoopbackTransport};
+    use std::sync::mpsc::Receiver;
+    use std::thread::JoinHandle;
+
+    /// A server running a recipe over any transport, in its own thread.
+    pub(crate) struct TestServer {
+        pub int_signal: Arc<Mutex<SIGflag>>,
+        pub triggers: Arc<Mutex<Option<Vec<Event>>>>,
+        rx: Receiver<Vec<ExtTrigger>>,
+        thread: JoinHandle<()>,
+    }
+
+    impl TestServer {
+        pub(crate) fn start<T: MidiTransport + 'static>(transport: T, recipe: Recipe) -> Self {
+            let (tx, rx) = channel();
+            let int_signal = Arc::new(Mutex::new(SIGflag::default()));
+            let device_params = Arc::new(Mutex::new(DeviceState::default()));
+            let triggers = Arc::new(Mutex::new(None));
+
+            let thread = {
+                let (int_signal, triggers) = (Arc::clone(&int_signal), Arc::clone(&triggers));
+                let recipe = Arc::new(Mutex::new(recipe));
+                spawn(move || {
+                    init_midi_audio(transport, tx, int_signal, recipe, device_params, triggers)
+                })
+            };
+
+            TestServer {
+                int_signal,
+                triggers,
+                rx,
+                thread,
+            }
+        }
+
+        /// The index of the event at `position` in the trigger vector.
+        pub(crate) fn event_index(&self, position: usize) -> u64 {
+            *self.triggers.lock().unwrap().as_ref().unwrap()[position].get_index()
+        }
+
+        /// Wait for `count` triggers sent to Python.
+        pub(crate) fn receive(&self, count: usize) -> Vec<ExtTrigger> {
+            let mut received = vec![];
+
+            while received.len() < count {
+                match self.rx.recv_timeout(Duration::from_secs(5)) {
+                    Ok(triggers) => received.extend(triggers),
+                    Err(_) => panic!("Only {} triggers received : {:?}", received.len(), received),
+                }
+            }
+
+            received
+        }
+
+        /// Stop the server, return the triggers not received yet.
+        pub(crate) fn stop(self) -> Vec<ExtTrigger> {
+            self.int_signal.lock().unwrap().stop_thread = true;
+            self.thread.join().unwrap();
+
+            self.rx.try_iter().flatten().collect()
+        }
+    }
+
+    /// Inject `mesg` as soon as the server listens to the loopback device.
+    pub(crate) fn inject_when_listening(device: &LoopbackHandle, mesg: &[u8]) {
+        let start = Instant::now();
+
+        while !device.inject(0, mesg) {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "The server never listened to the loopback device"
+            );
+            sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn loopback_session() {
+        let (transport, device) = LoopbackTransport::new("MiBL test");
+        let server =
+            TestServer::start(transport, vec![(vec![0x90, 0x10, 0x7F], vec![], Some(0.5))]);
+
+        inject_when_listening(&device, &[0x90, 0x10, 0x7F]);
+        assert_eq!(server.receive(1), vec![(server.event_index(0), 0.5)]);
+
+        // The device is initialized, then the message is echoed by the server loop
+        let start = Instant::now();
+        let mut sent = device.take_sent();
+        while !sent.contains(&vec![0x90, 0x10, 0x7F]) {
+            assert!(
+                start.elapsed() < Duration::from_secs(2),
+                "Message not echoed"
+            );
+            sleep(Duration::from_millis(10));
+            sent.extend(device.take_sent());
+        }
+
+        assert!(server.stop().is_empty());
+        assert!(
+            !device.inject(0, &[0x90, 0x10, 0x7F]),
+            "Transport not closed"
+        );
+    }
+
+    #[test]
+    fn loopback_burst_is_not_dropped() {
+        let (transport, device) = LoopbackTransport::new("MiBL test");
+        let server =
+            TestServer::start(transport, vec![(vec![0x90, 0x10, 0x7F], vec![], Some(0.5))]);
+        let count = 3000; // more than the input ring
+
+        inject_when_listening(&device, &[0x90, 0x10, 0x7F]);
+        for _ in 1..count {
+            assert!(device.inject(0, &[0x90, 0x10, 0x7F]));
+        }
+
+        assert_eq!(server.receive(count).len(), count);
+        assert!(server.stop().is_empty());
+    }
+
+    #[test]
+    fn panic_does_not_stall_the_worker() {
+        let (transport, device) = LoopbackTransport::new("MiBL test");
+        let server =
+            TestServer::start(transport, vec![(vec![0x90, 0x10, 0x7F], vec![], Some(0.5))]);
+
+        inject_when_listening(&device, &[0xFF]);
+        let start = Instant::now();
+        assert!(device.inject(0, &[0x90, 0x10, 0x7F]));
+
+        assert_eq!(server.receive(1), vec![(server.event_index(0), 0.5)]);
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert!(server.int_signal.lock().unwrap().panic_signal);
+        server.stop();
+    }
 }
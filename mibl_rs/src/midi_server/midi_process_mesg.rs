@@ -2,12 +2,35 @@ use crate::midi_server::container::{
     Event, ExtTrigger, MidiMesg, MidiProcess, MidiResult, RawMidi, SIGflag, TriggerResult,
     MAX_MIDI_MSG_SIZE,
 };
+use crate::midi_server::group::{select, switch_off};
+use crate::midi_server::hires::HiResParam;
+use crate::midi_server::logger::INPUT;
 use crate::midi_server::midi_event::{
     get_channel, get_note_name, get_octave, process_cc, process_note, process_pitch_bend,
     process_sys,
 };
 use crate::midi_server::midi_send_mesg::make_raw_midi_mesg;
+use crate::midi_server::modifier::ModifierSet;
 use crate::node_utils::sys_event::convert_half;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Is the event in the layer of the active modifiers (see `src/midi_server/modifier.rs`) ?
+/// The events of the same input are in the same bucket of `candidates`.
+fn in_active_layer(
+    trigger: &Event,
+    triggers: &[Event],
+    candidates: &[usize],
+    active: ModifierSet,
+) -> bool {
+    match trigger.get_modifiers() {
+        Some(modifiers) => modifiers == active,
+        None => !candidates.iter().any(|position| {
+            let other = &triggers[*position];
+            other.get_modifiers() == Some(active) && other.get_mesg_in() == trigger.get_mesg_in()
+        }),
+    }
+}
 
 pub fn process_midi_mesg(
     event: &RawMidi,
@@ -30,7 +53,7 @@ pub fn process_midi_mesg(
         "HUI" => 0,
         "MC" | "Mackie Control" | "MackieControl" => 1,
         _ => {
-            log::warn!("Protocole unknown drop to MC");
+            log::warn!(target: INPUT, "Protocole unknown drop to MC");
             1
         }
     };
@@ -39,12 +62,13 @@ pub fn process_midi_mesg(
     let debug = sig_flag.debug;
 
     if debug {
-        println!(
+        log::debug!(
+            target: INPUT,
             "\n ---------\n  Midi event to process ({}:{}) : {:04X?}\n ---------\n",
             proto, protocole, display_event
         );
 
-        println!("Delta frames : {:?}", event.delta_frames);
+        log::debug!(target: INPUT, "Delta frames : {:?}", event.delta_frames);
     }
 
     let event_data = event.data();
@@ -61,12 +85,19 @@ pub fn process_midi_mesg(
     let clean_cmd = (cmd >> 4) << 4;
 
     if debug {
-        println!("CHANNEL : {}", channel);
+        log::debug!(target: INPUT, "CHANNEL : {}", channel);
 
-        println!("Raw MIDI : {:04X?} {:?}", event_data, event_data);
+        log::debug!(target: INPUT, "Raw MIDI : {:04X?} {:?}", event_data, event_data);
     }
 
     let mut is_fader: bool = false;
+    let mut matched = vec![];
+
+    // 14-bit CC, NRPN and RPN (see `src/midi_server/hires.rs`)
+    let hires = match (clean_cmd, event_data) {
+        (0xB0, [_, cc, value, ..]) => sig_flag.hires.on_cc(channel - 1, *cc, *value),
+        _ => None,
+    };
 
     let (midi_mesg_to_send, debug_midi_mesg): (TriggerResult, Option<MidiMesg>) = match triggers {
         Some(triggers) => {
@@ -74,7 +105,17 @@ pub fn process_midi_mesg(
             let mut int_trigger_result: Option<Vec<RawMidi>> = None;
             let mut midi_mesg: Option<MidiMesg> = None;
 
-            for (idx, trigger) in triggers.iter().enumerate() {
+            // Only the events indexed for this message can be triggered
+            let dispatch = Arc::clone(&sig_flag.dispatch);
+            let candidates = dispatch.candidates(event_data);
+
+            for trigger in candidates.iter().map(|position| &triggers[*position]) {
+                if trigger.get_gesture().is_some()
+                    || !in_active_layer(trigger, triggers, candidates, sig_flag.modifiers.active())
+                {
+                    continue;
+                }
+
                 let trigger_mesg_in = trigger.get_mesg_in();
                 let mut note_bang = false;
 
@@ -88,24 +129,39 @@ pub fn process_midi_mesg(
 
                 if trigger_mesg_in.len() >= 3 && clean_cmd == 0xE0 && cmd == trigger_mesg_in[0] {
                     if debug {
-                        println!("Fader detected !");
+                        log::debug!(target: INPUT, "Fader detected !");
                     }
                     is_fader = true;
                 }
 
-                if note_bang || event_data == trigger_mesg_in || is_fader {
+                let hires_value = hires
+                    .filter(|(param, _)| {
+                        HiResParam::from_ingredient(trigger_mesg_in) == Some(*param)
+                    })
+                    .map(|(_, value)| value);
+
+                if note_bang || event_data == trigger_mesg_in || is_fader || hires_value.is_some() {
+                    matched.push(*trigger.get_index());
+
                     if debug {
-                        println!(
+                        log::debug!(
+                            target: INPUT,
                             "Event triggered {} : {}",
                             trigger.get_index(),
                             trigger.get_name()
                         );
-                        println!("Note bang status : {}", note_bang);
+                        log::debug!(target: INPUT, "Note bang status : {}", note_bang);
                     }
 
                     let trigger_val_out = trigger.get_val_out();
 
-                    if trigger_val_out.is_none() {
+                    if let Some(value) = hires_value {
+                        val_out = Some(value);
+
+                        if debug {
+                            log::debug!(target: INPUT, "{} : {}", trigger.get_name(), value);
+                        }
+                    } else if trigger_val_out.is_none() {
                         match clean_cmd {
                             0x80 | 0x90 => {
                                 let mut note_value = event_data[2];
@@ -121,7 +177,7 @@ pub fn process_midi_mesg(
                                 val_out = Some(tmp_midi_mesg.value);
 
                                 if debug {
-                                    println!("{}", tmp_midi_mesg.name);
+                                    log::debug!(target: INPUT, "{}", tmp_midi_mesg.name);
                                     midi_mesg = Some(tmp_midi_mesg);
                                 }
                             }
@@ -139,73 +195,40 @@ pub fn process_midi_mesg(
                                         ),
                                         value: poly_key_value,
                                     };
-                                    println!("{} : {}", tmp_midi_mesg.name, tmp_midi_mesg.value);
+                                    log::debug!(
+                                        target: INPUT,
+                                        "{} : {}",
+                                        tmp_midi_mesg.name,
+                                        tmp_midi_mesg.value
+                                    );
                                     midi_mesg = Some(tmp_midi_mesg);
                                 }
                             }
                             0xB0 => {
-                                let mut cc_flag = sig_flag.cc_flag;
-                                match event_data[1] {
-                                    cc_num if cc_num > 0x3F && cc_num < 0x62 => {
-                                        let tmp_midi_mesg = process_cc(cc_num, event_data[2], None);
-                                        val_out = Some(tmp_midi_mesg.value);
-
-                                        if debug {
-                                            midi_mesg = Some(tmp_midi_mesg);
-                                        }
-                                    }
-                                    cc_num if cc_num <= 0x1F && !cc_flag.cc_lsb_flag => {
-                                        if channel == cc_flag.cc_channel
-                                            && cc_flag.cc_note == event_data[1] + 0x20
-                                        {
-                                            cc_flag.cc_lsb_flag = true;
-                                            cc_flag.cc_msb_value = event_data[2];
-                                            cc_flag.cc_num = cc_num;
-                                        }
-
-                                        if !cc_flag.cc_lsb_flag {
-                                            let tmp_midi_mesg =
-                                                process_cc(cc_num, event_data[2], None);
-                                            val_out = Some(tmp_midi_mesg.value);
-
-                                            if debug {
-                                                midi_mesg = Some(tmp_midi_mesg);
-                                            }
-                                        } else {
-                                            break;
-                                        }
-                                    }
-                                    cc_num if cc_num > 0x1F && cc_num < 0x40 => {
-                                        let tmp_midi_mesg: Option<MidiMesg>;
-
-                                        if !cc_flag.cc_lsb_flag {
-                                            tmp_midi_mesg =
-                                                Some(process_cc(cc_num, event_data[2], None));
-                                            val_out = Some(tmp_midi_mesg.clone().unwrap().value);
-                                        } else {
-                                            tmp_midi_mesg = Some(process_cc(
-                                                cc_flag.cc_num,
-                                                cc_flag.cc_msb_value,
-                                                Some(event_data[2]),
-                                            ));
-                                            val_out = Some(tmp_midi_mesg.clone().unwrap().value);
-                                            cc_flag.cc_lsb_flag = false;
-                                        }
-
-                                        if debug && tmp_midi_mesg.is_some() {
-                                            midi_mesg = tmp_midi_mesg;
-                                        }
-                                    }
-                                    _ => log::warn!("Unknown value found for CC !"),
+                                let tmp_midi_mesg = process_cc(event_data[1], event_data[2], None);
+                                val_out = Some(tmp_midi_mesg.value);
+
+                                if debug {
+                                    midi_mesg = Some(tmp_midi_mesg);
                                 }
                             }
-                            0xC0 => println!("Command not used in Blender Midi : {:04X?}", cmd),
+                            0xC0 => {
+                                log::debug!(
+                                    target: INPUT,
+                                    "Command not used in Blender Midi : {:04X?}",
+                                    cmd
+                                )
+                            }
                             0xD0 => {
                                 let chan_press_value = convert_half(event_data[1]);
                                 val_out = Some(chan_press_value);
 
                                 if debug {
-                                    println!("Channel Pressure Aftertouch : {}", chan_press_value);
+                                    log::debug!(
+                                        target: INPUT,
+                                        "Channel Pressure Aftertouch : {}",
+                                        chan_press_value
+                                    );
 
                                     midi_mesg = Some(MidiMesg {
                                         channel,
@@ -219,7 +242,8 @@ pub fn process_midi_mesg(
                                     process_pitch_bend((event_data[1], event_data[2]));
                                 val_out = Some(tmp_midi_mesg.value);
 
-                                println!(
+                                log::trace!(
+                                    target: INPUT,
                                     "Fader ({:?}) value : {:?}",
                                     event_data[0] ^ 0xE0,
                                     val_out
@@ -230,7 +254,7 @@ pub fn process_midi_mesg(
                                 }
                             }
                             0xF0 => process_sys(event_data),
-                            _ => log::warn!("Unkown event : {:04X?}", event_data),
+                            _ => log::warn!(target: INPUT, "Unkown event : {:04X?}", event_data),
                         }
                     }
 
@@ -243,35 +267,53 @@ pub fn process_midi_mesg(
                         int_trigger_result = Some(int_midi_mesg);
                     }
 
+                    // A device value goes through the transfer of the event
                     let val_out = match (trigger_val_out, val_out) {
-                        (None, Some(val)) => Some(val),
+                        (None, Some(val)) => Some(
+                            trigger
+                                .get_transfer()
+                                .map_or(val, |transfer| transfer.apply(val)),
+                        ),
                         (Some(val), None) => Some(val),
                         (Some(val), Some(_)) => Some(val),
                         (None, None) => None,
                     };
 
-                    if val_out.is_some() {
-                        let mut ext_midi_mesg = Vec::<ExtTrigger>::with_capacity(MAX_MIDI_MSG_SIZE);
-                        ext_midi_mesg.push((*trigger.get_index(), val_out.unwrap()));
-                        ext_trigger_result = Some(ext_midi_mesg);
+                    // A smoothed device value is sent by the server loop
+                    let smoothing = trigger
+                        .get_smoothing()
+                        .filter(|_| trigger_val_out.is_none());
+
+                    match (val_out, smoothing) {
+                        (Some(val), Some(smoothing)) => sig_flag.smoother.set_target(
+                            *trigger.get_index(),
+                            val,
+                            smoothing,
+                            Instant::now(),
+                        ),
+                        (Some(val), None) => {
+                            let mut ext_midi_mesg =
+                                Vec::<ExtTrigger>::with_capacity(MAX_MIDI_MSG_SIZE);
+                            ext_midi_mesg.push((*trigger.get_index(), val));
+                            ext_trigger_result = Some(ext_midi_mesg);
+                        }
+                        (None, _) => (),
                     }
 
                     if note_bang {
                         if trigger.get_toggable() {
                             let note_value = trigger.get_mesg_in()[1];
-                            let mut mesg = [0x90, note_value, 0x7F];
-
-                            if sig_flag.note_led_on.contains(&note_value) {
-                                mesg[2] = 0x00;
-                                let note_idx = sig_flag
-                                    .note_led_on
-                                    .iter()
-                                    .position(|&x| x == note_value)
-                                    .expect("slice should contain elem");
-                                sig_flag.note_led_on.remove(note_idx);
-                            } else {
-                                sig_flag.note_led_on.push(note_value);
-                            }
+                            let toggle_on = !sig_flag
+                                .toggle_states
+                                .get(trigger.get_index())
+                                .copied()
+                                .unwrap_or_default();
+                            let mesg = [0x90, note_value, if toggle_on { 0x7F } else { 0x00 }];
+
+                            sig_flag
+                                .toggle_states
+                                .insert(*trigger.get_index(), toggle_on);
+                            sig_flag.toggles_changed = true;
 
                             let raw_midi_mesg =
                                 make_raw_midi_mesg(event.delta_frames(), &mesg).unwrap();
@@ -290,6 +332,30 @@ pub fn process_midi_mesg(
                         sig_flag.note_bang = false;
                         sig_flag.note_bang_value = 0;
                     }
+
+                    if let Some((leds, group_trigger)) =
+                        select(trigger, triggers, &mut sig_flag.toggle_states)
+                    {
+                        sig_flag.toggles_changed = true;
+
+                        let int_trigger = int_trigger_result.get_or_insert_with(Vec::new);
+                        for led in leds {
+                            int_trigger
+                                .push(make_raw_midi_mesg(event.delta_frames(), &led).unwrap());
+                        }
+
+                        ext_trigger_result
+                            .get_or_insert_with(Vec::new)
+                            .push(group_trigger);
+                    } else if let Some(led) =
+                        switch_off(trigger, triggers, &mut sig_flag.toggle_states)
+                    {
+                        sig_flag.toggles_changed = true;
+
+                        int_trigger_result
+                            .get_or_insert_with(Vec::new)
+                            .push(make_raw_midi_mesg(event.delta_frames(), &led).unwrap());
+                    }
                 }
             }
             ((int_trigger_result, ext_trigger_result), midi_mesg)
@@ -300,6 +366,7 @@ pub fn process_midi_mesg(
     let midi_to_send: MidiProcess = MidiProcess {
         debug: debug_midi_mesg,
         to_send: midi_mesg_to_send,
+        matched,
     };
 
     Ok(midi_to_send)
@@ -1,7 +1,11 @@
 use crate::midi_server::container::{
-    DeviceState, Event, RawMidi, Recipe, SIGflag, MAX_MIDI_MSG_SIZE,
+    DeviceState, Event, RawMidi, Recipe, SIGflag, ToggleStates, MAX_MIDI_MSG_SIZE,
 };
-use crate::midi_server::math_utils::split_digits;
+use crate::midi_server::curve::fader_position;
+use crate::midi_server::dispatch::DispatchTable;
+use crate::midi_server::group::select;
+use crate::midi_server::logger::{OUTPUT, RECIPE, TIMING};
+use crate::midi_server::math_utils::{encode_14bit, split_digits};
 use crate::midi_server::midi_event::craft_recipe;
 use crate::midi_server::sys_event::SYS_EVENT_ARRAY;
 use std::sync::{Arc, Mutex};
@@ -19,24 +23,9 @@ use std::sync::{Arc, Mutex};
 // 0xE0      Pitch bend  2              lsb (7 bits) msb (7 bits)
 // 0xF0      (non-musical commands)
 
+/// Fader value (0.0 to 1.0) => pitch bend (lsb, msb), read back by `process_pitch_bend`.
 pub fn convert_value_to_lsb_msb(value: f32) -> (u8, u8) {
-    match value {
-        value if value == 0.0 => (0, 0),
-        value if value == 1.0 => (0x7C, 0x7F),
-        _ => {
-            let u16_value = (value * 16384.0).round() as u16;
-            let lsb_value = (u16_value & 0xff) as u8;
-            let msb_value = (u16_value >> 7) as u8;
-
-            let u16_reverse_value = (msb_value as u16) << 7 | lsb_value as u16;
-            println!(
-                "Calculate CC value : {}",
-                (u16_reverse_value as f32) / 16384.0
-            );
-
-            (lsb_value, msb_value)
-        }
-    }
+    encode_14bit(value)
 }
 
 pub fn make_raw_midi_mesg(stamp: &u64, mesg: &[u8]) -> Result<RawMidi, String> {
@@ -117,40 +106,23 @@ pub fn make_lcd_mesg(
 pub fn gen_lcd_string(stamp: u64, mesg: Option<String>) -> Result<Vec<RawMidi>, String> {
     let mut raw_midi_mesg: Vec<RawMidi> = Vec::new();
 
+    // A line of the LCD (56 characters) is written by a single SysEx
     fn line_append(
         vec: &mut Vec<RawMidi>,
         stamp: &u64,
         line: &[u8],
         line_pos: u8,
     ) -> Result<(), String> {
-        let chunk_size = 7;
-        let line_len = if line.len() <= 110 { line.len() } else { 110 };
-
-        let loop_iter = if line_len / chunk_size <= 8 {
-            line_len / chunk_size
-        } else {
-            8
-        };
-
-        let loop_rem = line_len % chunk_size;
+        let line = &line[..line.len().min(56)];
 
-        for idx in 0..=loop_iter {
-            let min = idx * chunk_size;
-            let mut max = min + loop_rem;
-
-            if idx != loop_iter {
-                max = min + chunk_size;
-            }
-
-            match String::from_utf8(line[min..max].to_vec()) {
-                Ok(str) => {
-                    match make_lcd_mesg(*stamp, (idx as u8) + 1, line_pos, str) {
-                        Ok(raw_midi) => vec.push(raw_midi),
-                        Err(err) => return Err(err),
-                    };
-                }
-                Err(err) => return Err(err.to_string()),
+        match String::from_utf8(line.to_vec()) {
+            Ok(str) => {
+                match make_lcd_mesg(*stamp, 1, line_pos, str) {
+                    Ok(raw_midi) => vec.push(raw_midi),
+                    Err(err) => return Err(err),
+                };
             }
+            Err(err) => return Err(err.to_string()),
         }
         Ok(())
     }
@@ -231,7 +203,9 @@ pub fn timestamp_gen(
         let digit = idx + 0x40;
         match make_raw_midi_mesg(&stamp, &[0xB0, digit as u8, (*value + 0x30)]) {
             Ok(raw_midi) => raw_midi_timestamp.push(raw_midi),
-            Err(err) => println!("Unable to create message for timestamp ! {}", err),
+            Err(err) => {
+                log::warn!(target: TIMING, "Unable to create message for timestamp ! {}", err)
+            }
         }
     }
 
@@ -358,7 +332,30 @@ pub fn send_note_bang(note: u8, led_value: u8) -> Result<Vec<RawMidi>, String> {
     Err(format!("Bad led mode {} : 0 or any even value to LED Off, 1 or any odd value to LED Blink, 127 to LED On", led_value))
 }
 
-pub fn initialize_mc_device(init_values: &DeviceState) -> Result<Vec<RawMidi>, String> {
+/// (note, on) of the LEDs of the toggable events and group members with a toggle state.
+pub fn toggle_leds(toggle_states: &ToggleStates, events: &Option<Vec<Event>>) -> Vec<(u8, bool)> {
+    events
+        .iter()
+        .flatten()
+        .filter(|event| {
+            (event.get_toggable() || event.get_group().is_some()) && event.get_mesg_in().len() >= 2
+        })
+        .filter_map(|event| {
+            toggle_states
+                .get(event.get_index())
+                .map(|on| (event.get_mesg_in()[1], *on))
+        })
+        .collect()
+}
+
+pub fn toggle_led_mesg(note: u8, on: bool) -> Result<RawMidi, String> {
+    make_raw_midi_mesg(&0, &[0x90, note, if on { 0x7F } else { 0x00 }])
+}
+
+pub fn initialize_mc_device(
+    init_values: &DeviceState,
+    toggles: &[(u8, bool)],
+) -> Result<Vec<RawMidi>, String> {
     let mut raw_midi_mesg: Vec<RawMidi> = Vec::new();
 
     let pitch_bend_prefix = 0xE0;
@@ -375,7 +372,7 @@ pub fn initialize_mc_device(init_values: &DeviceState) -> Result<Vec<RawMidi>, S
                 raw_midi_mesg.push(midi_mesg);
             }
         }
-        Err(err) => println!("Unable to generate timestamp : {}", err),
+        Err(err) => log::warn!(target: TIMING, "Unable to generate timestamp : {}", err),
     }
 
     match assign_gen(0) {
@@ -384,7 +381,7 @@ pub fn initialize_mc_device(init_values: &DeviceState) -> Result<Vec<RawMidi>, S
                 raw_midi_mesg.push(raw_midi);
             }
         }
-        Err(err) => println!("Unable to generate assign digit : {}", err),
+        Err(err) => log::warn!(target: OUTPUT, "Unable to generate assign digit : {}", err),
     }
 
     if let Some(lcd_vec) = init_values.get_lcd_vec() {
@@ -393,7 +390,7 @@ pub fn initialize_mc_device(init_values: &DeviceState) -> Result<Vec<RawMidi>, S
                 Ok(raw_midi) => {
                     raw_midi_mesg.push(raw_midi);
                 }
-                Err(err) => println!("Unable to generate lcd mesg : {}", err),
+                Err(err) => log::warn!(target: OUTPUT, "Unable to generate lcd mesg : {}", err),
             }
         }
     } else {
@@ -408,14 +405,16 @@ pub fn initialize_mc_device(init_values: &DeviceState) -> Result<Vec<RawMidi>, S
                     raw_midi_mesg.push(raw_midi);
                 }
             }
-            Err(err) => println!("Unable to generate lcd string : {}", err),
+            Err(err) => log::warn!(target: OUTPUT, "Unable to generate lcd string : {}", err),
         }
     }
 
     for vpot in init_values.get_vpots() {
         match pan_knob_gen(vpot[1], vpot[0], vpot[2]) {
             Ok(raw_midi) => raw_midi_mesg.push(raw_midi),
-            Err(err) => println!("Unable to create Pan Knob midi message : {}", err),
+            Err(err) => {
+                log::warn!(target: OUTPUT, "Unable to create Pan Knob midi message : {}", err)
+            }
         }
     }
 
@@ -432,11 +431,11 @@ pub fn initialize_mc_device(init_values: &DeviceState) -> Result<Vec<RawMidi>, S
 
         match make_raw_midi_mesg_fast(&time, &midi_mesg) {
             Ok(raw_midi) => {
-                log::info!("Initializing slider #{}", pb_idx);
+                log::debug!(target: OUTPUT, "Initializing slider #{}", pb_idx);
                 raw_midi_mesg.push(raw_midi);
             }
             Err(..) => {
-                log::error!("Unable to trigger event abort");
+                log::error!(target: OUTPUT, "Unable to trigger event abort");
             }
         };
 
@@ -460,17 +459,30 @@ pub fn initialize_mc_device(init_values: &DeviceState) -> Result<Vec<RawMidi>, S
                         raw_midi_mesg.push(raw_midi)
                     }
                 }
-                Err(err) => println!("Unable to generate note channel bang mesg : {}", err),
+                Err(err) => {
+                    log::warn!(
+                        target: OUTPUT,
+                        "Unable to generate note channel bang mesg : {}",
+                        err
+                    )
+                }
             }
         } else {
-            println!("Channel button not in range (0x00..=0x1F) : {:X}", note);
+            log::warn!(target: OUTPUT, "Channel button not in range (0x00..=0x1F) : {:X}", note);
+        }
+    }
+
+    for (note, on) in toggles {
+        match toggle_led_mesg(*note, *on) {
+            Ok(raw_midi) => raw_midi_mesg.push(raw_midi),
+            Err(err) => log::warn!(target: OUTPUT, "Unable to generate toggle LED mesg : {}", err),
         }
     }
 
     for meter in 0..=7 {
         match meter_led(meter, 0, false) {
             Ok(raw_midi) => raw_midi_mesg.push(raw_midi),
-            Err(err) => println!("Unable to generate meter midi mesg : {}", err),
+            Err(err) => log::warn!(target: OUTPUT, "Unable to generate meter midi mesg : {}", err),
         }
     }
 
@@ -487,7 +499,7 @@ pub fn reset_mc_device() -> Result<Vec<RawMidi>, String> {
         if *value != 0x3C {
             match send_note_bang(*value, 0x00) {
                 Ok(bang) => {
-                    println!("Resetting System LED #{} : {}", idx + 1, name);
+                    log::debug!(target: OUTPUT, "Resetting System LED #{} : {}", idx + 1, name);
                     raw_midi_mesg.extend(bang);
                 }
                 Err(err) => return Err(err),
@@ -506,11 +518,11 @@ pub fn reset_mc_device() -> Result<Vec<RawMidi>, String> {
 
         match make_raw_midi_mesg_fast(&time, &midi_mesg) {
             Ok(raw_midi) => {
-                log::info!("Resetting slider #{}", pb_idx);
+                log::debug!(target: OUTPUT, "Resetting slider #{}", pb_idx);
                 raw_midi_mesg.push(raw_midi);
             }
             Err(..) => {
-                log::error!("Unable to trigger event abort");
+                log::error!(target: OUTPUT, "Unable to trigger event abort");
             }
         };
 
@@ -523,7 +535,7 @@ pub fn reset_mc_device() -> Result<Vec<RawMidi>, String> {
                 raw_midi_mesg.push(midi_mesg);
             }
         }
-        Err(err) => println!("Unable to generate timestamp : {}", err),
+        Err(err) => log::warn!(target: TIMING, "Unable to generate timestamp : {}", err),
     }
 
     match assign_gen(0) {
@@ -532,7 +544,7 @@ pub fn reset_mc_device() -> Result<Vec<RawMidi>, String> {
                 raw_midi_mesg.push(raw_midi);
             }
         }
-        Err(err) => println!("Unable to generate assign digit : {}", err),
+        Err(err) => log::warn!(target: OUTPUT, "Unable to generate assign digit : {}", err),
     }
 
     match gen_lcd_string(0, None) {
@@ -541,13 +553,15 @@ pub fn reset_mc_device() -> Result<Vec<RawMidi>, String> {
                 raw_midi_mesg.push(midi_mesg);
             }
         }
-        Err(err) => println!("Unable to generate flush lcd mesg : {}", err),
+        Err(err) => log::warn!(target: OUTPUT, "Unable to generate flush lcd mesg : {}", err),
     }
 
     for pan_idx in 1..9 {
         match pan_knob_gen(0, pan_idx, 0x01) {
             Ok(raw_midi) => raw_midi_mesg.push(raw_midi),
-            Err(err) => println!("Unable to create Pan Knob midi message : {}", err),
+            Err(err) => {
+                log::warn!(target: OUTPUT, "Unable to create Pan Knob midi message : {}", err)
+            }
         }
     }
 
@@ -570,25 +584,68 @@ pub fn signal_handling(
 
     if int_signal_arc.update_recipe {
         if debug {
-            println!("Updating recipe in loop");
+            log::debug!(target: RECIPE, "Updating recipe in loop");
         }
         let recipe = recipe_arc.clone();
         let mut opt_recipe = None;
         let use_sys_event = int_signal_arc.use_sys_event;
+        let recipe_options = int_signal_arc.recipe_options.clone();
 
         if !recipe.is_empty() {
             opt_recipe = Some(&recipe);
         }
 
-        *triggers_events_arc = match craft_recipe(&use_sys_event, opt_recipe) {
+        *triggers_events_arc = match craft_recipe(&use_sys_event, opt_recipe, &recipe_options) {
             Ok(events) => {
-                println!("Triggers build in loop");
+                log::debug!(target: RECIPE, "Triggers build in loop");
                 events
             }
             Err(err) => panic!("Unable to create the trigger table ! {}", err),
         };
+        int_signal_arc.dispatch = Arc::new(DispatchTable::new(&triggers_events_arc));
+        int_signal_arc.gestures.set_events(&triggers_events_arc);
 
         int_signal_arc.update_recipe = false;
+
+        // The toggle states are kept, their LEDs follow the new events
+        for (note, on) in toggle_leds(&int_signal_arc.toggle_states, &triggers_events_arc) {
+            if let Ok(raw_midi) = toggle_led_mesg(note, on) {
+                raw_midi_mesg.push(raw_midi);
+            }
+        }
+    }
+
+    if !int_signal_arc.toggle_updates.is_empty() {
+        let toggle_updates = std::mem::take(&mut int_signal_arc.toggle_updates);
+
+        for (index, on) in toggle_updates {
+            let member = triggers_events_arc
+                .iter()
+                .flatten()
+                .find(|event| *event.get_index() == index && event.get_group().is_some());
+
+            // Selecting a group member turns off the others
+            match member {
+                Some(member) if on => {
+                    select(
+                        member,
+                        triggers_events_arc.as_deref().unwrap_or_default(),
+                        &mut int_signal_arc.toggle_states,
+                    );
+                }
+                _ => {
+                    int_signal_arc.toggle_states.insert(index, on);
+                }
+            }
+        }
+
+        for (note, on) in toggle_leds(&int_signal_arc.toggle_states, &triggers_events_arc) {
+            if let Ok(raw_midi) = toggle_led_mesg(note, on) {
+                raw_midi_mesg.push(raw_midi);
+            }
+        }
+
+        int_signal_arc.toggles_changed = true;
     }
 
     if int_signal_arc.update_lcd_vec {
@@ -601,7 +658,14 @@ pub fn signal_handling(
                 let lcd_mesg = lcd_def.2.clone();
                 match make_lcd_mesg(0, lcd_num, line_num, lcd_mesg) {
                     Ok(raw_midi) => raw_midi_mesg.push(raw_midi),
-                    Err(err) => println!("Unable to generate LCD message {:?} : {}", lcd_def, err),
+                    Err(err) => {
+                        log::warn!(
+                            target: OUTPUT,
+                            "Unable to generate LCD message {:?} : {}",
+                            lcd_def,
+                            err
+                        )
+                    }
                 };
             }
         }
@@ -616,7 +680,8 @@ pub fn signal_handling(
             Ok(lcd_raw_midi) => {
                 raw_midi_mesg.extend(lcd_raw_midi);
             }
-            Err(err) => println!(
+            Err(err) => log::warn!(
+                target: OUTPUT,
                 "Unable to generate LCD message from string {:?} : {}",
                 lcd_string, err
             ),
@@ -631,7 +696,7 @@ pub fn signal_handling(
             match pan_knob_gen(vpot[1], vpot[0], vpot[2]) {
                 Ok(raw_midi) => raw_midi_mesg.push(raw_midi),
                 Err(err) => {
-                    println!("Unable to create Pan Knob midi message : {}", err)
+                    log::warn!(target: OUTPUT, "Unable to create Pan Knob midi message : {}", err)
                 }
             }
         }
@@ -646,7 +711,8 @@ pub fn signal_handling(
             let pb_value = fader.1;
             let pb_num = 0xE0 + pb_idx;
 
-            let (lsb, msb) = convert_value_to_lsb_msb(pb_value);
+            let (lsb, msb) =
+                convert_value_to_lsb_msb(fader_position(&triggers_events_arc, pb_idx, pb_value));
 
             let midi_mesg = vec![pb_num, lsb, msb];
 
@@ -655,7 +721,7 @@ pub fn signal_handling(
                     raw_midi_mesg.push(raw_midi);
                 }
                 Err(err) => {
-                    println!("Unable to generate fader mesg : {}", err);
+                    log::warn!(target: OUTPUT, "Unable to generate fader mesg : {}", err);
                 }
             };
         }
@@ -682,11 +748,19 @@ pub fn signal_handling(
                         raw_midi_mesg.extend(bang_raw_midi);
                     }
                     Err(err) => {
-                        println!("Unable to generate note channel bang mesg : {}", err)
+                        log::warn!(
+                            target: OUTPUT,
+                            "Unable to generate note channel bang mesg : {}",
+                            err
+                        )
                     }
                 }
             } else {
-                println!("Channel button not in range (0x00..=0x1F) : {:X}", note);
+                log::warn!(
+                    target: OUTPUT,
+                    "Channel button not in range (0x00..=0x1F) : {:X}",
+                    note
+                );
             }
         }
 
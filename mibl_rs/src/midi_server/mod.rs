@@ -1,8 +1,30 @@
 pub mod container;
+pub mod control;
+pub mod curve;
+pub mod dispatch;
+pub mod dmx;
+pub mod gesture;
+pub mod group;
+pub mod hires;
+pub mod learn;
+pub mod logger;
 pub mod math_utils;
 pub mod midi_event;
 pub mod midi_main;
 pub mod midi_process_mesg;
 pub mod midi_send_mesg;
+pub mod modifier;
+pub mod monitor;
+pub mod osc;
+pub mod output;
+pub mod recorder;
+pub mod ring;
+pub mod rtp_midi;
 pub mod setup_client_params;
+pub mod smf;
+pub mod smoothing;
 pub mod sys_event;
+pub mod sysex;
+pub mod transport;
+#[cfg(feature = "websocket")]
+pub mod web;
@@ -0,0 +1,112 @@
+// MODIFIER LAYERS
+// MOD_Shift (0x46), MOD_Option (0x47), MOD_Ctrl (0x48), MOD_Alt (0x49)
+// Momentary : the modifier is active while held. Latched : each press toggles it, its LED shows it.
+// An event declares its layer in the recipe options, {"modifiers": "shift+alt"} or {"modifiers": "none"}.
+// It is triggered only when exactly these modifiers are active. An event without layer is triggered
+// when no event of the same input is declared for the active modifiers (so fader and Shift+fader can
+// be two parameters, and a recipe without layers works as before).
+
+const MODIFIERS: [(&str, u8); 4] = [
+    ("shift", 0x46),
+    ("option", 0x47),
+    ("ctrl", 0x48),
+    ("alt", 0x49),
+];
+
+/// A set of modifiers, a bit by modifier in the `MODIFIERS` order.
+pub type ModifierSet = u8;
+
+fn modifier_bit(note: u8) -> Option<ModifierSet> {
+    MODIFIERS
+        .iter()
+        .position(|(_, value)| *value == note)
+        .map(|position| 1 << position)
+}
+
+/// Parse "shift+alt" (or "none") into a `ModifierSet`.
+pub fn parse_modifiers(names: &str) -> Result<ModifierSet, String> {
+    if names.trim().eq_ignore_ascii_case("none") {
+        return Ok(0);
+    }
+
+    names.split('+').try_fold(0, |set, name| {
+        let name = name.trim().to_lowercase();
+
+        MODIFIERS
+            .iter()
+            .position(|(modifier, _)| *modifier == name)
+            .map(|position| set | 1 << position)
+            .ok_or(format!("Unknown modifier : {}", name))
+    })
+}
+
+pub fn modifier_names(set: ModifierSet) -> Vec<&'static str> {
+    MODIFIERS
+        .iter()
+        .enumerate()
+        .filter(|(position, _)| set & 1 << position != 0)
+        .map(|(_, (name, _))| *name)
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ModifierState {
+    held: ModifierSet,
+    latched: ModifierSet,
+    latch_mode: ModifierSet, // modifiers in latched mode, the others are momentary
+}
+
+impl ModifierState {
+    pub fn new(latch_mode: ModifierSet) -> Self {
+        Self {
+            latch_mode,
+            ..Default::default()
+        }
+    }
+
+    /// The modifiers currently active.
+    pub fn active(&self) -> ModifierSet {
+        (self.held & !self.latch_mode) | self.latched
+    }
+
+    /// Track a modifier button, return the LED message of a latched modifier
+    /// (sent after the echo of the input, so on release too).
+    pub fn on_message(&mut self, mesg: &[u8]) -> Option<Vec<u8>> {
+        if mesg.len() < 3 || !matches!(mesg[0], 0x80 | 0x90) {
+            return None;
+        }
+
+        let bit = modifier_bit(mesg[1])?;
+
+        if mesg[0] == 0x90 && mesg[2] > 0 {
+            self.held |= bit;
+
+            if self.latch_mode & bit != 0 {
+                self.latched ^= bit;
+            }
+        } else {
+            self.held &= !bit;
+        }
+
+        if self.latch_mode & bit == 0 {
+            return None;
+        }
+
+        let led = if self.latched & bit != 0 { 0x7F } else { 0x00 };
+        Some(vec![0x90, mesg[1], led])
+    }
+
+    /// Release all the modifiers, return the LED messages of the latched ones.
+    pub fn clear(&mut self) -> Vec<Vec<u8>> {
+        let leds = MODIFIERS
+            .iter()
+            .enumerate()
+            .filter(|(position, _)| self.latched & 1 << position != 0)
+            .map(|(_, (_, note))| vec![0x90, *note, 0x00])
+            .collect();
+
+        self.held = 0;
+        self.latched = 0;
+        leds
+    }
+}
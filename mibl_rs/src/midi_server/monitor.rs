@@ -0,0 +1,208 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::midi_server::control::Control;
+use crate::midi_server::math_utils::decode_14bit;
+use crate::midi_server::recorder::Direction;
+
+// MIDI MONITOR
+// When enabled, the server records the messages of the device (after processing, with the events
+// triggered) and the messages sent to the device (when they leave the output stage). At most
+// `MONITOR_SIZE` entries are kept, the oldest are dropped. Each entry has a sequence number, so
+// Python can ask for the entries after the last one it has shown.
+
+pub const MONITOR_SIZE: usize = 2048;
+
+#[derive(Debug, Clone)]
+pub struct MonitorEntry {
+    pub seq: u64,
+    pub time: f64, // seconds since the monitor was created or cleared
+    pub direction: Direction,
+    pub mesg: Vec<u8>,
+    pub decoded: String,
+    pub events: Vec<(u64, String)>, // (index, name) of the events triggered, empty if unmatched
+}
+
+/// The entries returned by `MonitorHandle::query`, every filter set must match.
+#[derive(Debug, Clone, Default)]
+pub struct MonitorFilter {
+    pub direction: Option<Direction>,
+    pub matched: Option<bool>,
+    pub contains: Option<String>, // in the decoded message or an event name, case insensitive
+    pub since: Option<u64>,       // entries after this sequence number
+    pub limit: Option<usize>,     // the latest entries only
+}
+
+impl MonitorFilter {
+    fn accepts(&self, entry: &MonitorEntry) -> bool {
+        self.direction
+            .is_none_or(|direction| direction == entry.direction)
+            && self
+                .matched
+                .is_none_or(|matched| matched != entry.events.is_empty())
+            && self.since.is_none_or(|since| entry.seq > since)
+            && self.contains.as_ref().is_none_or(|text| {
+                let text = text.to_lowercase();
+
+                entry.decoded.to_lowercase().contains(&text)
+                    || entry
+                        .events
+                        .iter()
+                        .any(|(_, name)| name.to_lowercase().contains(&text))
+            })
+    }
+}
+
+#[derive(Debug)]
+struct MonitorShared {
+    enabled: bool,
+    entries: VecDeque<MonitorEntry>,
+    next_seq: u64,
+    start: Instant,
+}
+
+/// Shared by `MiBlRustProcess` and the server (`SIGflag`), kept across server restarts.
+#[derive(Debug, Clone)]
+pub struct MonitorHandle {
+    shared: Arc<Mutex<MonitorShared>>,
+}
+
+impl Default for MonitorHandle {
+    fn default() -> Self {
+        MonitorHandle {
+            shared: Arc::new(Mutex::new(MonitorShared {
+                enabled: false,
+                entries: VecDeque::new(),
+                next_seq: 0,
+                start: Instant::now(),
+            })),
+        }
+    }
+}
+
+impl MonitorHandle {
+    pub fn is_enabled(&self) -> bool {
+        self.shared.lock().unwrap().enabled
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.shared.lock().unwrap().enabled = enabled;
+    }
+
+    /// Record a message if the monitor is enabled.
+    pub fn record(&self, direction: Direction, mesg: &[u8], events: Vec<(u64, String)>) {
+        let mut shared = self.shared.lock().unwrap();
+
+        if !shared.enabled {
+            return;
+        }
+
+        if shared.entries.len() >= MONITOR_SIZE {
+            shared.entries.pop_front();
+        }
+
+        let entry = MonitorEntry {
+            seq: shared.next_seq,
+            time: shared.start.elapsed().as_secs_f64(),
+            direction,
+            mesg: mesg.to_vec(),
+            decoded: decode(direction, mesg),
+            events,
+        };
+
+        shared.next_seq += 1;
+        shared.entries.push_back(entry);
+    }
+
+    /// The entries accepted by `filter`, oldest first.
+    pub fn query(&self, filter: &MonitorFilter) -> Vec<MonitorEntry> {
+        let shared = self.shared.lock().unwrap();
+        let mut entries: Vec<MonitorEntry> = shared
+            .entries
+            .iter()
+            .filter(|entry| filter.accepts(entry))
+            .cloned()
+            .collect();
+
+        if let Some(limit) = filter.limit {
+            entries.drain(..entries.len().saturating_sub(limit));
+        }
+
+        entries
+    }
+
+    /// Forget the entries and restart the clock, the sequence numbers go on.
+    pub fn clear(&self) {
+        let mut shared = self.shared.lock().unwrap();
+
+        shared.entries.clear();
+        shared.start = Instant::now();
+    }
+}
+
+/// Relative value of a V-Pot or the jog wheel : bit 6 is the sign.
+fn relative(value: u8) -> i8 {
+    match value & 0x40 {
+        0 => (value & 0x3F) as i8,
+        _ => -((value & 0x3F) as i8),
+    }
+}
+
+/// A human readable message, *e.g* "strip3.fader = 0.42", "transport.play on".
+pub fn decode(direction: Direction, mesg: &[u8]) -> String {
+    let Some(status) = mesg.first().copied() else {
+        return "Empty message".to_string();
+    };
+    let channel = (status & 0x0F) + 1;
+    let control = Control::from_midi(mesg);
+
+    match (status & 0xF0, mesg) {
+        (0x80 | 0x90, [_, note, velocity, ..]) => {
+            let on = status & 0xF0 == 0x90 && *velocity > 0;
+            let state = match (direction, on, *velocity) {
+                (Direction::Out, true, 0x01) => "blink",
+                (_, true, _) => "on",
+                (_, false, _) => "off",
+            };
+
+            match control {
+                Some(control) => format!("{} {}", control, state),
+                None => format!("Note {} {} (vel {}, ch {})", note, state, velocity, channel),
+            }
+        }
+        (0xE0, [_, lsb, msb, ..]) => {
+            let value = decode_14bit(*lsb, *msb);
+
+            match control {
+                Some(control) => format!("{} = {:.2}", control, value),
+                None => format!("Pitch bend = {:.2} (ch {})", value, channel),
+            }
+        }
+        (0xB0, [_, cc, value, ..]) => match (direction, control) {
+            (Direction::In, Some(control)) => format!("{} {:+}", control, relative(*value)),
+            (Direction::Out, _) if (0x30..=0x37).contains(cc) => {
+                format!("strip{}.vpot ring = {:02X}", cc - 0x2F, value)
+            }
+            (Direction::Out, _) if (0x40..=0x4B).contains(cc) => {
+                format!("Digit {} = {:02X}", cc - 0x40, value)
+            }
+            _ => format!("CC {} = {} (ch {})", cc, value, channel),
+        },
+        (0xD0, [_, value, ..]) => match direction {
+            Direction::In => format!("Channel pressure = {} (ch {})", value, channel),
+            Direction::Out => format!("strip{}.meter = {}", (value >> 4) + 1, value & 0x0F),
+        },
+        (0xF0, _) => match (status, mesg) {
+            (0xF0, [0xF0, 0x00, 0x00, 0x66, _, 0x12, position, text @ ..]) => format!(
+                "LCD @{} \"{}\"",
+                position,
+                String::from_utf8_lossy(text.strip_suffix(&[0xF7]).unwrap_or(text))
+            ),
+            (0xF0, _) => format!("SysEx ({} bytes)", mesg.len()),
+            (0xFF, _) => "System reset".to_string(),
+            _ => format!("System {:02X} ({} bytes)", status, mesg.len()),
+        },
+        _ => format!("{:02X?}", mesg),
+    }
+}
@@ -0,0 +1,391 @@
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+use crate::midi_server::container::{Event, ExtTrigger};
+use crate::midi_server::logger::{INPUT, OUTPUT, SERVER};
+
+// OPEN SOUND CONTROL 1.0
+// Message = address (OSC-string) + type tags (OSC-string, starts with ',') + arguments
+// OSC-string = ASCII chars + 1 to 4 '\0' (total length multiple of 4)
+// Arguments : i = int32 BE, f = float32 BE, s = OSC-string
+// Bundle = "#bundle\0" + time tag (8 bytes) + [size (int32) + element]…
+
+const OSC_MAX_PACKET: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscArg {
+    Int(i32),
+    Float(f32),
+    Str(String),
+}
+
+impl OscArg {
+    pub fn as_f32(&self) -> Option<f32> {
+        match self {
+            OscArg::Int(value) => Some(*value as f32),
+            OscArg::Float(value) => Some(*value),
+            OscArg::Str(_) => None,
+        }
+    }
+
+    pub fn as_i32(&self) -> Option<i32> {
+        match self {
+            OscArg::Int(value) => Some(*value),
+            OscArg::Float(value) => Some(value.round() as i32),
+            OscArg::Str(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<OscArg>,
+}
+
+impl OscMessage {
+    pub fn new(address: &str, args: Vec<OscArg>) -> Self {
+        Self {
+            address: address.to_string(),
+            args,
+        }
+    }
+}
+
+/// A command received by the bridge, to apply on `DeviceState` like the Python setters.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscCommand {
+    Fader(u8, f32),       // fader_num, value
+    Lcd(String),          // lcd string
+    Vpot(u8, u8, u8),     // vpot_idx, mode, value
+    Timecode([usize; 4]), // hours, minutes, seconds, frames
+}
+
+fn push_osc_string(buffer: &mut Vec<u8>, string: &str) {
+    buffer.extend(string.as_bytes());
+    buffer.push(0);
+    buffer.resize(buffer.len().next_multiple_of(4), 0);
+}
+
+fn read_osc_string(data: &[u8], pos: &mut usize) -> Result<String, String> {
+    let start = *pos;
+    let end = data[start..]
+        .iter()
+        .position(|&byte| byte == 0)
+        .map(|len| start + len)
+        .ok_or("OSC string without terminator")?;
+
+    let string = String::from_utf8(data[start..end].to_vec()).map_err(|err| err.to_string())?;
+    *pos = (end + 4) & !0x03;
+
+    Ok(string)
+}
+
+fn read_be_bytes(data: &[u8], pos: &mut usize) -> Result<[u8; 4], String> {
+    let bytes = data.get(*pos..*pos + 4).ok_or("Truncated OSC argument")?;
+    *pos += 4;
+
+    Ok([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+pub fn encode_osc(mesg: &OscMessage) -> Vec<u8> {
+    let mut buffer = vec![];
+    let mut type_tags = ",".to_string();
+
+    for arg in &mesg.args {
+        type_tags.push(match arg {
+            OscArg::Int(_) => 'i',
+            OscArg::Float(_) => 'f',
+            OscArg::Str(_) => 's',
+        });
+    }
+
+    push_osc_string(&mut buffer, &mesg.address);
+    push_osc_string(&mut buffer, &type_tags);
+
+    for arg in &mesg.args {
+        match arg {
+            OscArg::Int(value) => buffer.extend(value.to_be_bytes()),
+            OscArg::Float(value) => buffer.extend(value.to_be_bytes()),
+            OscArg::Str(value) => push_osc_string(&mut buffer, value),
+        }
+    }
+
+    buffer
+}
+
+/// Decode an OSC packet, a message or a bundle (nested bundles are flattened).
+pub fn decode_osc(data: &[u8]) -> Result<Vec<OscMessage>, String> {
+    if data.starts_with(b"#bundle\0") {
+        let mut messages = vec![];
+        let mut pos = 16; // "#bundle\0" + time tag
+
+        while pos + 4 <= data.len() {
+            let size = i32::from_be_bytes(read_be_bytes(data, &mut pos)?) as usize;
+            let element = data.get(pos..pos + size).ok_or("Truncated OSC bundle")?;
+            messages.extend(decode_osc(element)?);
+            pos += size;
+        }
+
+        return Ok(messages);
+    }
+
+    let mut pos = 0;
+    let address = read_osc_string(data, &mut pos)?;
+
+    if !address.starts_with('/') {
+        return Err(format!("Bad OSC address : {}", address));
+    }
+
+    let mut args = vec![];
+
+    if pos < data.len() {
+        let type_tags = read_osc_string(data, &mut pos)?;
+
+        for tag in type_tags.chars().skip(1) {
+            match tag {
+                'i' => args.push(OscArg::Int(i32::from_be_bytes(read_be_bytes(
+                    data, &mut pos,
+                )?))),
+                'f' => args.push(OscArg::Float(f32::from_be_bytes(read_be_bytes(
+                    data, &mut pos,
+                )?))),
+                's' => args.push(OscArg::Str(read_osc_string(data, &mut pos)?)),
+                _ => return Err(format!("OSC type tag not supported : {}", tag)),
+            }
+        }
+    }
+
+    Ok(vec![OscMessage { address, args }])
+}
+
+/// Convert an `Event` name to an OSC address part (*e.g* "Mute track button #3" => "Mute_track_button_3")
+pub fn osc_name(name: &str) -> String {
+    let mut osc_name = String::with_capacity(name.len());
+
+    for char in name.chars() {
+        match char {
+            ' ' | '/' => {
+                if !osc_name.ends_with('_') {
+                    osc_name.push('_');
+                }
+            }
+            '#' | '*' | ',' | '?' | '[' | ']' | '{' | '}' => (),
+            _ => osc_name.push(char),
+        }
+    }
+
+    osc_name.trim_matches('_').to_string()
+}
+
+/// Convert an incoming OSC message into a `DeviceState` command.
+pub fn parse_command(mesg: &OscMessage) -> Result<OscCommand, String> {
+    let parts: Vec<&str> = mesg.address.trim_start_matches('/').split('/').collect();
+    let index = |idx: usize| -> Result<u8, String> {
+        parts
+            .get(idx)
+            .ok_or(format!("Missing index in {}", mesg.address))?
+            .parse::<u8>()
+            .map_err(|err| format!("Bad index in {} : {}", mesg.address, err))
+    };
+    let int_arg = |idx: usize| -> Result<i32, String> {
+        mesg.args
+            .get(idx)
+            .and_then(OscArg::as_i32)
+            .ok_or(format!("{} : missing int argument #{}", mesg.address, idx))
+    };
+
+    match parts.as_slice() {
+        ["mibl", "fader", _] => {
+            let value = mesg
+                .args
+                .first()
+                .and_then(OscArg::as_f32)
+                .ok_or(format!("{} : missing float argument", mesg.address))?;
+
+            Ok(OscCommand::Fader(index(2)?, value.clamp(0.0, 1.0)))
+        }
+        ["mibl", "lcd"] => match mesg.args.first() {
+            Some(OscArg::Str(lcd_string)) => Ok(OscCommand::Lcd(lcd_string.clone())),
+            _ => Err(format!("{} : missing string argument", mesg.address)),
+        },
+        ["mibl", "vpot", _] => {
+            // One argument : value (mode 0), two arguments : mode, value
+            let (mode, value) = match mesg.args.len() {
+                1 => (0, int_arg(0)?),
+                _ => (int_arg(0)?, int_arg(1)?),
+            };
+
+            Ok(OscCommand::Vpot(
+                index(2)?,
+                mode.clamp(0, 0x03) as u8,
+                value.clamp(0, 0x0B) as u8,
+            ))
+        }
+        ["mibl", "timecode"] => Ok(OscCommand::Timecode([
+            int_arg(0)?.max(0) as usize,
+            int_arg(1)?.max(0) as usize,
+            int_arg(2)?.max(0) as usize,
+            int_arg(3)?.max(0) as usize,
+        ])),
+        _ => Err(format!("Unknown OSC address : {}", mesg.address)),
+    }
+}
+
+/// UDP OSC endpoint of the server.
+///
+/// Publishes every `ExtTrigger` to `target` as `/mibl/trigger/<index> f` and `/mibl/trigger/<name> f`,
+/// and receives `/mibl/fader/<n> f`, `/mibl/lcd s`, `/mibl/vpot/<n> [i] i`, `/mibl/timecode i i i i`.
+pub struct OscBridge {
+    socket: UdpSocket,
+    target: Option<SocketAddr>,
+}
+
+impl OscBridge {
+    pub fn bind(listen: &str, target: Option<&str>) -> Result<Self, String> {
+        let socket = UdpSocket::bind(listen)
+            .map_err(|err| format!("Unable to bind {} : {}", listen, err))?;
+
+        socket
+            .set_nonblocking(true)
+            .map_err(|err| format!("Unable to set OSC socket non blocking : {}", err))?;
+
+        let target = match target {
+            Some(target) => Some(
+                target
+                    .to_socket_addrs()
+                    .map_err(|err| format!("Bad OSC target {} : {}", target, err))?
+                    .next()
+                    .ok_or(format!("OSC target {} not found", target))?,
+            ),
+            None => None,
+        };
+
+        Ok(Self { socket, target })
+    }
+
+    /// The address the bridge listens on (*e.g* to bind on port 0).
+    pub fn local_addr(&self) -> Result<SocketAddr, String> {
+        self.socket
+            .local_addr()
+            .map_err(|err| format!("Unable to read OSC socket address : {}", err))
+    }
+
+    pub fn send(&self, mesg: &OscMessage) -> Result<(), String> {
+        match self.target {
+            Some(target) => self
+                .socket
+                .send_to(&encode_osc(mesg), target)
+                .map(|_| ())
+                .map_err(|err| format!("Unable to send OSC message : {}", err)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn publish_triggers(&self, triggers: &[ExtTrigger], events: &Option<Vec<Event>>) {
+        for (index, value) in triggers {
+            let mut addresses = vec![format!("/mibl/trigger/{}", index)];
+
+            if let Some(events) = events {
+                if let Some(event) = events.iter().find(|event| event.get_index() == index) {
+                    addresses.push(format!("/mibl/trigger/{}", osc_name(event.get_name())));
+                }
+            }
+
+            for address in addresses {
+                if let Err(err) = self.send(&OscMessage::new(&address, vec![OscArg::Float(*value)]))
+                {
+                    log::warn!(target: OUTPUT, "{}", err);
+                }
+            }
+        }
+    }
+
+    /// Read all pending packets and return the commands to apply.
+    pub fn poll(&self) -> Vec<OscCommand> {
+        let mut commands = vec![];
+        let mut buffer = [0u8; OSC_MAX_PACKET];
+
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((len, _)) => match decode_osc(&buffer[..len]) {
+                    Ok(messages) => {
+                        for mesg in messages {
+                            match parse_command(&mesg) {
+                                Ok(command) => commands.push(command),
+                                Err(err) => {
+                                    log::warn!(target: INPUT, "OSC message ignored : {}", err)
+                                }
+                            }
+                        }
+                    }
+                    Err(err) => log::warn!(target: INPUT, "Unable to decode OSC packet : {}", err),
+                },
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    log::error!(target: SERVER, "Unable to read OSC socket : {}", err);
+                    break;
+                }
+            }
+        }
+
+        commands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn bridge_over_local_udp() {
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let client_addr = client.local_addr().unwrap().to_string();
+        let bridge = OscBridge::bind("127.0.0.1:0", Some(&client_addr)).unwrap();
+
+        // Command received by the bridge
+        let fader = OscMessage::new("/mibl/fader/3", vec![OscArg::Float(0.42)]);
+        client
+            .send_to(&encode_osc(&fader), bridge.local_addr().unwrap())
+            .unwrap();
+
+        let start = Instant::now();
+        let mut commands = bridge.poll();
+        while commands.is_empty() && start.elapsed() < Duration::from_secs(1) {
+            sleep(Duration::from_millis(5));
+            commands = bridge.poll();
+        }
+        assert_eq!(commands, vec![OscCommand::Fader(3, 0.42)]);
+
+        // Trigger published by the bridge, by index and by name
+        let event = Event::new(
+            5,
+            "Mute track #3".to_string(),
+            vec![0x90, 0x10, 0x7F],
+            None,
+            Some(0.5),
+            0,
+            None,
+            false,
+            false,
+        )
+        .unwrap();
+        bridge.publish_triggers(&[(5, 0.5)], &Some(vec![event]));
+
+        let mut buffer = [0u8; OSC_MAX_PACKET];
+        for address in ["/mibl/trigger/5", "/mibl/trigger/Mute_track_3"] {
+            let (len, from) = client.recv_from(&mut buffer).unwrap();
+
+            assert_eq!(from, bridge.local_addr().unwrap());
+            assert_eq!(
+                decode_osc(&buffer[..len]).unwrap(),
+                vec![OscMessage::new(address, vec![OscArg::Float(0.5)])]
+            );
+        }
+    }
+}
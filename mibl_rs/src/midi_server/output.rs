@@ -0,0 +1,285 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
+
+use crate::midi_server::logger::OUTPUT;
+
+// OUTPUT STAGE
+// All the messages sent to the device by the server loop go through this stage :
+//   - It remembers what the surface shows (button LEDs, faders, V-Pot rings, timecode and
+//     assignment digits) and drops a message that would not change it.
+//   - A burst on one of these controls is coalesced to its latest value, queued after the
+//     messages pushed before it (the previous value is skipped).
+//   - The output is capped in bytes by second (token bucket), the pending messages wait for
+//     the next ticks. 3125 bytes by second is the MIDI DIN speed.
+// A note off (0x80) of a button is sent as a note on with a null velocity, like the LEDs.
+// Other messages (meters, SysEx, channel mode…) are never coalesced, they are only dropped when
+// `PENDING_SIZE` messages already wait. After a device reset the stage forgets what the surface
+// shows (`forget`), so the whole state is sent again.
+
+pub const OUTPUT_RATE: u32 = 3125;
+pub const PENDING_SIZE: usize = 4096;
+
+type Key = (u8, u8);
+
+/// A note off of a button, as its LED message.
+fn normalize(mesg: &[u8]) -> Vec<u8> {
+    match *mesg {
+        [0x80, note, _] => vec![0x90, note, 0x00],
+        _ => mesg.to_vec(),
+    }
+}
+
+/// The control shown by the message, `None` if the message is not diffed.
+fn key(mesg: &[u8]) -> Option<Key> {
+    match *mesg {
+        [0x90, note, _] => Some((0x90, note)),
+        [status @ 0xE0..=0xE8, _, _] => Some((status, 0)),
+        [0xB0, cc @ (0x30..=0x37 | 0x40..=0x4B), _] => Some((0xB0, cc)), // V-Pot rings, digits
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OutputStage {
+    shown: HashMap<Key, Vec<u8>>,
+    pending: VecDeque<(Option<Key>, u64, Vec<u8>)>, // (key, sequence number, message)
+    pending_keys: HashMap<Key, u64>, // key => sequence number of its latest pending message
+    next_seq: u64,
+    stale: usize,   // pending messages replaced by a later value
+    dropped: usize, // since the last drain, the queue was full
+    rate: f32,      // bytes by second, 0 for no cap
+    budget: f32,
+    last_drain: Instant,
+}
+
+impl OutputStage {
+    pub fn new(rate: u32) -> Self {
+        let rate = rate as f32;
+
+        OutputStage {
+            shown: HashMap::new(),
+            pending: VecDeque::new(),
+            pending_keys: HashMap::new(),
+            next_seq: 0,
+            stale: 0,
+            dropped: 0,
+            rate,
+            budget: rate / 10.0,
+            last_drain: Instant::now(),
+        }
+    }
+
+    /// At most 100 ms of output in a burst.
+    fn capacity(&self) -> f32 {
+        self.rate / 10.0
+    }
+
+    /// Record a message sent outside the stage, *e.g* the device initialization.
+    pub fn mark_shown(&mut self, mesg: &[u8]) {
+        let mesg = normalize(mesg);
+
+        if let Some(key) = key(&mesg) {
+            self.shown.insert(key, mesg);
+        }
+    }
+
+    /// Forget what the surface shows, *e.g* after a device reset.
+    pub fn forget(&mut self) {
+        self.shown.clear();
+    }
+
+    /// Queue a message for the device.
+    pub fn push(&mut self, mesg: &[u8]) {
+        let mesg = normalize(mesg);
+
+        let seq = self.next_seq;
+
+        let Some(key) = key(&mesg) else {
+            if self.pending.len() - self.stale >= PENDING_SIZE {
+                self.dropped += 1;
+            } else {
+                self.pending.push_back((None, seq, mesg));
+                self.next_seq += 1;
+            }
+            return;
+        };
+
+        // The latest value goes at the back, the previous one is skipped by `drain`
+        let replaced = self.pending_keys.contains_key(&key);
+
+        if replaced || self.shown.get(&key) != Some(&mesg) {
+            if replaced {
+                self.stale += 1;
+            }
+
+            self.pending_keys.insert(key, seq);
+            self.pending.push_back((Some(key), seq, mesg));
+            self.next_seq += 1;
+            self.compact();
+        }
+    }
+
+    /// Remove the replaced messages once they are half of the queue.
+    fn compact(&mut self) {
+        if self.stale * 2 <= self.pending.len() {
+            return;
+        }
+
+        let keys = &self.pending_keys;
+        self.pending
+            .retain(|(key, seq, _)| key.is_none_or(|key| keys.get(&key) == Some(seq)));
+        self.stale = 0;
+    }
+
+    /// The messages to send now, in the order they were queued.
+    pub fn drain(&mut self, now: Instant) -> Vec<Vec<u8>> {
+        let elapsed = now.duration_since(self.last_drain).as_secs_f32();
+        self.last_drain = now;
+        self.budget = (self.budget + self.rate * elapsed).min(self.capacity());
+
+        if self.dropped > 0 {
+            log::warn!(
+                target: OUTPUT,
+                "{} messages dropped (output queue full)",
+                std::mem::take(&mut self.dropped)
+            );
+        }
+
+        let mut to_send = vec![];
+
+        while let Some((key, seq, mesg)) = self.pending.front() {
+            if let Some(key) = key {
+                // Replaced by a later value
+                if self.pending_keys.get(key) != Some(seq) {
+                    self.pending.pop_front();
+                    self.stale -= 1;
+                    continue;
+                }
+
+                // Coalesced back to what the surface already shows
+                if self.shown.get(key) == Some(mesg) {
+                    self.pending_keys.remove(key);
+                    self.pending.pop_front();
+                    continue;
+                }
+            }
+
+            if self.rate > 0.0 {
+                let len = mesg.len() as f32;
+
+                // A message longer than the burst waits for a full bucket
+                if self.budget < len && self.budget < self.capacity() {
+                    break;
+                }
+
+                self.budget -= len;
+            }
+
+            if let Some((key, _, mesg)) = self.pending.pop_front() {
+                if let Some(key) = key {
+                    self.pending_keys.remove(&key);
+                    self.shown.insert(key, mesg.clone());
+                }
+
+                to_send.push(mesg);
+            }
+        }
+
+        to_send
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Push `mesgs` into a stage without cap, return what is sent.
+    fn send(output: &mut OutputStage, mesgs: &[&[u8]]) -> Vec<Vec<u8>> {
+        for mesg in mesgs {
+            output.push(mesg);
+        }
+
+        output.drain(Instant::now())
+    }
+
+    #[test]
+    fn note_off_updates_the_led() {
+        let mut output = OutputStage::new(0);
+
+        assert_eq!(
+            send(&mut output, &[&[0x90, 0x10, 0x7F]]),
+            vec![vec![0x90, 0x10, 0x7F]]
+        );
+        assert_eq!(
+            send(&mut output, &[&[0x80, 0x10, 0x40]]),
+            vec![vec![0x90, 0x10, 0x00]]
+        );
+        assert_eq!(
+            send(&mut output, &[&[0x90, 0x10, 0x7F]]),
+            vec![vec![0x90, 0x10, 0x7F]]
+        );
+        assert!(send(&mut output, &[&[0x90, 0x10, 0x7F]]).is_empty());
+    }
+
+    #[test]
+    fn forget_sends_the_state_again() {
+        let mut output = OutputStage::new(0);
+        let state: [&[u8]; 3] = [
+            &[0x90, 0x10, 0x7F],
+            &[0xE0, 0x00, 0x40],
+            &[0xB0, 0x30, 0x21],
+        ];
+
+        assert_eq!(send(&mut output, &state).len(), 3);
+        assert!(send(&mut output, &state).is_empty());
+
+        output.forget();
+        assert_eq!(send(&mut output, &state).len(), 3);
+    }
+
+    #[test]
+    fn pending_messages_are_capped() {
+        let mut output = OutputStage::new(1);
+
+        for _ in 0..PENDING_SIZE + 10 {
+            output.push(&[0xD0, 0x05]);
+        }
+        // The controls are bounded by their keys, still queued
+        output.push(&[0xE0, 0x00, 0x40]);
+
+        assert_eq!(output.pending.len(), PENDING_SIZE + 1);
+        assert_eq!(output.dropped, 10);
+    }
+
+    #[test]
+    fn latest_value_keeps_the_queue_order() {
+        let mut output = OutputStage::new(0);
+
+        let sent = send(
+            &mut output,
+            &[
+                &[0xE0, 0x00, 0x20],
+                &[0xD0, 0x05],
+                &[0xE0, 0x00, 0x40],
+                &[0xD0, 0x06],
+            ],
+        );
+
+        assert_eq!(
+            sent,
+            vec![vec![0xD0, 0x05], vec![0xE0, 0x00, 0x40], vec![0xD0, 0x06]]
+        );
+    }
+
+    #[test]
+    fn replaced_values_do_not_pile_up() {
+        let mut output = OutputStage::new(1);
+
+        for value in 0..PENDING_SIZE * 4 {
+            output.push(&[0xE0, 0x00, (value % 0x80) as u8]);
+        }
+
+        assert!(output.pending.len() <= 2);
+        assert_eq!(output.dropped, 0);
+    }
+}
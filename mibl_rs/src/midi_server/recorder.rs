@@ -0,0 +1,419 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, LineWriter, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::midi_server::logger::SERVER;
+use crate::midi_server::ring::relay_input;
+use crate::midi_server::smf::{import_smf, is_smf};
+use crate::midi_server::transport::{InputCallback, MidiTransport, TransportError};
+
+const SESSION_HEADER: &str = "# MiBL session v1";
+
+/// The way of a message : from the device (`In`) or to the device (`Out`), shared with the monitor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    In,
+    Out,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::In => "in",
+            Direction::Out => "out",
+        }
+    }
+}
+
+/// A recorded MIDI message, `delta` is the time (in microseconds) since the previous message of the session.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedMidi {
+    pub delta: u64,
+    pub direction: Direction,
+    pub data: Vec<u8>,
+}
+
+/// Parse a session line : `<in|out> <delta_us> <hex bytes…>`
+fn parse_line(line: &str) -> Result<Option<RecordedMidi>, String> {
+    let line = line.trim();
+
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let mut fields = line.split_whitespace();
+
+    let direction = match fields.next() {
+        Some("in") => Direction::In,
+        Some("out") => Direction::Out,
+        other => return Err(format!("Unknown direction : {:?}", other)),
+    };
+
+    let delta = match fields.next().map(str::parse::<u64>) {
+        Some(Ok(delta)) => delta,
+        _ => return Err("Missing or bad delta timestamp".to_string()),
+    };
+
+    let mut data = vec![];
+    for byte in fields {
+        match u8::from_str_radix(byte, 16) {
+            Ok(byte) => data.push(byte),
+            Err(err) => return Err(format!("Bad byte {} : {}", byte, err)),
+        }
+    }
+
+    if data.is_empty() {
+        return Err("Empty MIDI message".to_string());
+    }
+
+    Ok(Some(RecordedMidi {
+        delta,
+        direction,
+        data,
+    }))
+}
+
+/// Load a session file written by `Recorder`.
+pub fn load_session(path: &str) -> Result<Vec<RecordedMidi>, String> {
+    let file = File::open(path).map_err(|err| format!("Unable to open {} : {}", path, err))?;
+    let mut session = vec![];
+
+    for (idx, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|err| err.to_string())?;
+
+        match parse_line(&line) {
+            Ok(Some(recorded)) => session.push(recorded),
+            Ok(None) => (),
+            Err(err) => return Err(format!("{} line {} : {}", path, idx + 1, err)),
+        }
+    }
+
+    Ok(session)
+}
+
+/// Write every message given to `record` in a session file, with its delta timestamp.
+///
+/// Each line is flushed, so the file is usable even if Blender crashes.
+pub struct Recorder {
+    file: LineWriter<File>,
+    last: Option<Instant>,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> Result<Self, String> {
+        let file =
+            File::create(path).map_err(|err| format!("Unable to create {} : {}", path, err))?;
+        let mut file = LineWriter::new(file);
+
+        writeln!(file, "{}", SESSION_HEADER).map_err(|err| err.to_string())?;
+
+        Ok(Self { file, last: None })
+    }
+
+    pub fn record(&mut self, direction: Direction, data: &[u8]) {
+        let now = Instant::now();
+        let delta = match self.last {
+            Some(last) => now.duration_since(last).as_micros() as u64,
+            None => 0,
+        };
+        self.last = Some(now);
+
+        let mut line = format!("{} {}", direction.as_str(), delta);
+        for byte in data {
+            line.push_str(&format!(" {:02X}", byte));
+        }
+
+        if let Err(err) = writeln!(self.file, "{}", line) {
+            log::warn!(target: SERVER, "Unable to record MIDI message : {}", err);
+        }
+    }
+}
+
+/// Wrap any transport and record its traffic (incoming, and optionally outgoing) into a session file.
+pub struct RecordingTransport<T: MidiTransport> {
+    inner: T,
+    recorder: Arc<Mutex<Recorder>>,
+    record_output: bool,
+}
+
+impl<T: MidiTransport> RecordingTransport<T> {
+    pub fn new(inner: T, path: &str, record_output: bool) -> Result<Self, String> {
+        Ok(Self {
+            inner,
+            recorder: Arc::new(Mutex::new(Recorder::create(path)?)),
+            record_output,
+        })
+    }
+}
+
+impl<T: MidiTransport> MidiTransport for RecordingTransport<T> {
+    fn port_name(&self) -> &str {
+        self.inner.port_name()
+    }
+
+    fn ports(&self) -> Vec<String> {
+        self.inner.ports()
+    }
+
+    fn send(&mut self, mesg: &[u8]) -> Result<(), TransportError> {
+        if self.record_output {
+            self.recorder.lock().unwrap().record(Direction::Out, mesg);
+        }
+
+        self.inner.send(mesg)
+    }
+
+    fn listen(&mut self, mut callback: InputCallback) -> Result<(), TransportError> {
+        let recorder = Arc::clone(&self.recorder);
+
+        // Recorded on the relay thread, the file is not written from the transport callback
+        let relay = Box::new(move |stamp, mesg: &[u8]| {
+            recorder.lock().unwrap().record(Direction::In, mesg);
+            callback(stamp, mesg);
+        });
+        self.inner
+            .listen(relay_input(relay, self.inner.is_realtime()))
+    }
+
+    fn close(&mut self) {
+        self.inner.close();
+    }
+
+    fn is_realtime(&self) -> bool {
+        self.inner.is_realtime()
+    }
+}
+
+/// Feed a recorded session to the server, like the device did, without hardware.
+///
+/// Only incoming messages are replayed, outgoing messages are dropped.
+/// `speed` is a time factor : 1.0 original speed, 2.0 twice faster, 0.0 (or less) as fast as possible.
+pub struct ReplayTransport {
+    name: String,
+    session: Option<Vec<RecordedMidi>>,
+    speed: f32,
+    closed: Arc<AtomicBool>,
+    replay_thread: Option<JoinHandle<()>>,
+}
+
+impl ReplayTransport {
+    pub fn new(name: &str, session: Vec<RecordedMidi>, speed: f32) -> Self {
+        Self {
+            name: name.to_string(),
+            session: Some(session),
+            speed,
+            closed: Arc::new(AtomicBool::new(false)),
+            replay_thread: None,
+        }
+    }
+
+    /// Open a session file, or a Standard MIDI File (c.f `smf.rs`).
+    pub fn open(path: &str, speed: f32) -> Result<Self, String> {
+        let session = match is_smf(path) {
+            true => import_smf(path)?,
+            false => load_session(path)?,
+        };
+
+        Ok(Self::new(path, session, speed))
+    }
+}
+
+/// Sleep `wait` by small steps, returns `false` as soon as the transport is closed.
+fn wait_unless_closed(wait: Duration, closed: &AtomicBool) -> bool {
+    let step = Duration::from_millis(10);
+    let start = Instant::now();
+
+    loop {
+        if closed.load(Ordering::Relaxed) {
+            return false;
+        }
+
+        let elapsed = start.elapsed();
+        if elapsed >= wait {
+            return true;
+        }
+
+        sleep(step.min(wait - elapsed));
+    }
+}
+
+impl MidiTransport for ReplayTransport {
+    fn port_name(&self) -> &str {
+        &self.name
+    }
+
+    fn ports(&self) -> Vec<String> {
+        vec![self.name.clone()]
+    }
+
+    fn send(&mut self, _mesg: &[u8]) -> Result<(), TransportError> {
+        if self.closed.load(Ordering::Relaxed) {
+            return Err(TransportError::Closed);
+        }
+
+        Ok(())
+    }
+
+    fn listen(&mut self, mut callback: InputCallback) -> Result<(), TransportError> {
+        if self.closed.load(Ordering::Relaxed) {
+            return Err(TransportError::Closed);
+        }
+
+        let session = match self.session.take() {
+            Some(session) => session,
+            None => return Err(TransportError::AlreadyListening),
+        };
+
+        let speed = self.speed;
+        let closed = Arc::clone(&self.closed);
+
+        self.replay_thread = Some(spawn(move || {
+            let mut stamp: u64 = 0;
+            let mut delta: u64 = 0; // since the last message replayed
+
+            for recorded in session {
+                // The outgoing messages are not replayed, but their time is
+                delta += recorded.delta;
+
+                if recorded.direction != Direction::In {
+                    continue;
+                }
+
+                if speed > 0.0 && delta > 0 {
+                    let wait = Duration::from_micros((delta as f64 / speed as f64) as u64);
+
+                    if !wait_unless_closed(wait, &closed) {
+                        return;
+                    }
+                } else if closed.load(Ordering::Relaxed) {
+                    return;
+                }
+
+                stamp += delta;
+                delta = 0;
+                callback(stamp, &recorded.data);
+            }
+
+            log::info!(target: SERVER, "Replay done.");
+        }));
+
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+
+        if let Some(replay_thread) = self.replay_thread.take() {
+            let _ = replay_thread.join();
+        }
+    }
+
+    fn is_realtime(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi_server::midi_main::tests::{inject_when_listening, TestServer};
+    use crate::midi_server::transport::LoopbackTransport;
+    use std::sync::mpsc::channel;
+
+    fn session_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("mibl_{}_{}.txt", name, std::process::id()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn replay_keeps_the_time_of_outgoing_messages() {
+        let recorded = |delta, direction, data: &[u8]| RecordedMidi {
+            delta,
+            direction,
+            data: data.to_vec(),
+        };
+        let session = vec![
+            recorded(0, Direction::In, &[0x90, 0x10, 0x7F]),
+            recorded(100_000, Direction::Out, &[0x90, 0x10, 0x7F]),
+            recorded(100_000, Direction::In, &[0x80, 0x10, 0x00]),
+        ];
+        let mut transport = ReplayTransport::new("MiBL test", session, 1.0);
+        let (tx, rx) = channel();
+
+        transport
+            .listen(Box::new(move |stamp, mesg| {
+                let _ = tx.send((stamp, Instant::now(), mesg.to_vec()));
+            }))
+            .unwrap();
+
+        let (first_stamp, first, _) = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        let (second_stamp, second, mesg) = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        transport.close();
+
+        assert_eq!(mesg, vec![0x80, 0x10, 0x00]);
+        assert_eq!(second_stamp - first_stamp, 200_000);
+        assert!(second - first >= Duration::from_millis(190));
+    }
+
+    #[test]
+    fn fast_replay_is_not_dropped() {
+        let count = 3000; // more than the input ring
+        let session = (0..count)
+            .map(|_| RecordedMidi {
+                delta: 0,
+                direction: Direction::In,
+                data: vec![0x90, 0x10, 0x7F],
+            })
+            .collect();
+        let server = TestServer::start(
+            ReplayTransport::new("MiBL test", session, 0.0),
+            vec![(vec![0x90, 0x10, 0x7F], vec![], Some(1.0))],
+        );
+
+        assert_eq!(server.receive(count).len(), count);
+        assert!(server.stop().is_empty());
+    }
+
+    #[test]
+    fn replayed_session_gives_the_same_triggers() {
+        let recipe = vec![
+            (vec![0x90, 0x10, 0x7F], vec![], Some(1.0)),
+            (vec![0x90, 0x08, 0x7F], vec![], Some(0.25)),
+            (vec![0xE0, 0x00, 0x00], vec![], None),
+        ];
+        let inputs: [&[u8]; 5] = [
+            &[0x90, 0x10, 0x7F],
+            &[0x90, 0x08, 0x7F],
+            &[0xE0, 0x00, 0x40],
+            &[0xE0, 0x7F, 0x7F],
+            &[0x90, 0x10, 0x7F],
+        ];
+        let path = session_path("replay");
+
+        // Live, through the loopback device, outgoing messages recorded too
+        let (loopback, device) = LoopbackTransport::new("MiBL test");
+        let transport = RecordingTransport::new(loopback, &path, true).unwrap();
+        let server = TestServer::start(transport, recipe.clone());
+
+        inject_when_listening(&device, inputs[0]);
+        for mesg in &inputs[1..] {
+            assert!(device.inject(0, mesg));
+        }
+        let mut live = server.receive(inputs.len());
+        live.extend(server.stop());
+
+        // Replayed as fast as possible
+        let server = TestServer::start(ReplayTransport::open(&path, 0.0).unwrap(), recipe);
+        let replayed = server.receive(live.len());
+        let extra = server.stop();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(replayed, live);
+        assert!(extra.is_empty(), "Extra triggers : {:?}", extra);
+    }
+}
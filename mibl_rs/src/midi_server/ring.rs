@@ -0,0 +1,354 @@
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{park_timeout, spawn, yield_now, Thread};
+use std::time::Duration;
+
+use crate::midi_server::container::{RawMidi, MAX_MIDI_MSG_SIZE};
+use crate::midi_server::logger::INPUT;
+use crate::midi_server::sysex::SysExAssembler;
+use crate::midi_server::transport::InputCallback;
+
+// REAL-TIME INPUT RING
+// The transport callback runs on the midir / JACK thread : it must never allocate nor block.
+// It only copies the message in a preallocated slot of a single producer, single consumer ring
+// and unparks the worker thread, which runs all the processing (`SIGflag`, triggers, channels).
+// A SysEx longer than `MAX_MIDI_MSG_SIZE` takes several slots, the consumer joins them
+// (c.f `src/midi_server/sysex.rs`). When the ring is full, the message of a real-time transport
+// is dropped and counted, the worker reports it. A transport which is not real-time (replay,
+// loopback) waits for a free slot instead, a burst faster than the worker is not lost.
+// A transport wrapper needing to lock or allocate for each message (recording, injection) does it
+// behind a ring too, on its relay thread (`relay_input`).
+
+pub const RING_SIZE: usize = 1024; // power of 2
+
+struct Ring {
+    slots: Box<[UnsafeCell<RawMidi>]>,
+    head: AtomicUsize, // next slot read by the consumer
+    tail: AtomicUsize, // next slot written by the producer
+    dropped: AtomicUsize,
+}
+
+// A slot is only written by the producer before `tail` is released, and only read by the
+// consumer after `tail` is acquired and before `head` is released.
+unsafe impl Sync for Ring {}
+
+impl Ring {
+    fn slot(&self, position: usize) -> *mut RawMidi {
+        self.slots[position & (self.slots.len() - 1)].get()
+    }
+}
+
+/// The writing end, owned by the transport callback.
+pub struct RingProducer {
+    ring: Arc<Ring>,
+}
+
+/// The reading end, owned by the worker thread.
+pub struct RingConsumer {
+    ring: Arc<Ring>,
+    sysex: SysExAssembler,
+}
+
+/// A ring of `RING_SIZE` messages.
+pub fn input_ring() -> (RingProducer, RingConsumer) {
+    let ring = Arc::new(Ring {
+        slots: (0..RING_SIZE)
+            .map(|_| UnsafeCell::new(RawMidi::default()))
+            .collect(),
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+        dropped: AtomicUsize::new(0),
+    });
+
+    (
+        RingProducer {
+            ring: Arc::clone(&ring),
+        },
+        RingConsumer {
+            ring,
+            sysex: SysExAssembler::default(),
+        },
+    )
+}
+
+impl RingProducer {
+    /// Queue a message without allocation nor lock, return `false` if the message is dropped.
+    pub fn push(&mut self, stamp: u64, mesg: &[u8]) -> bool {
+        let pushed = self.try_push(stamp, mesg);
+
+        if !pushed {
+            self.ring.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+
+        pushed
+    }
+
+    /// Queue a message, waiting (unparking `consumer`) while the ring is full. Return `false` if
+    /// the message is dropped : the consumer is gone or the message would never fit.
+    pub fn push_waiting(&mut self, stamp: u64, mesg: &[u8], consumer: &Thread) -> bool {
+        let fragments = mesg.len().div_ceil(MAX_MIDI_MSG_SIZE);
+
+        if fragments == 0 || fragments > self.ring.slots.len() {
+            self.ring.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+
+        while !self.try_push(stamp, mesg) {
+            if Arc::strong_count(&self.ring) == 1 {
+                return false;
+            }
+
+            consumer.unpark();
+            yield_now();
+        }
+
+        true
+    }
+
+    fn try_push(&mut self, stamp: u64, mesg: &[u8]) -> bool {
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+        let free =
+            self.ring.slots.len() - tail.wrapping_sub(self.ring.head.load(Ordering::Acquire));
+        let fragments = mesg.len().div_ceil(MAX_MIDI_MSG_SIZE);
+
+        if fragments == 0 || fragments > free {
+            return false;
+        }
+
+        for (position, fragment) in mesg.chunks(MAX_MIDI_MSG_SIZE).enumerate() {
+            if let Ok(raw_midi) = RawMidi::new(stamp, fragment) {
+                unsafe { *self.ring.slot(tail.wrapping_add(position)) = raw_midi };
+            }
+        }
+        self.ring
+            .tail
+            .store(tail.wrapping_add(fragments), Ordering::Release);
+
+        true
+    }
+}
+
+impl RingConsumer {
+    /// The oldest message queued, if any, the fragments of a SysEx are joined.
+    pub fn pop(&mut self) -> Option<RawMidi> {
+        loop {
+            if let Some(raw_midi) = self.sysex.take_ready() {
+                return Some(raw_midi);
+            }
+
+            let head = self.ring.head.load(Ordering::Relaxed);
+
+            if head == self.ring.tail.load(Ordering::Acquire) {
+                return None;
+            }
+
+            // Left empty, the producer never drops a SysEx stored on the heap
+            let fragment = unsafe { std::mem::take(&mut *self.ring.slot(head)) };
+            self.ring
+                .head
+                .store(head.wrapping_add(1), Ordering::Release);
+
+            self.sysex.feed(fragment);
+        }
+    }
+
+    /// The number of messages dropped since the last call.
+    pub fn take_dropped(&self) -> usize {
+        self.ring.dropped.swap(0, Ordering::Relaxed)
+    }
+
+    /// `true` once the producer is dropped (the transport is closed).
+    fn is_orphan(&self) -> bool {
+        Arc::strong_count(&self.ring) == 1
+    }
+}
+
+/// Hand the messages of the returned callback to `relay` on a thread of its own, the callback
+/// only queues them. The thread ends with the callback (`realtime` : c.f `MidiTransport`).
+pub fn relay_input(mut relay: InputCallback, realtime: bool) -> InputCallback {
+    let (mut producer, mut consumer) = input_ring();
+
+    let relay_thread = spawn(move || loop {
+        let orphan = consumer.is_orphan();
+
+        while let Some(raw_midi) = consumer.pop() {
+            relay(raw_midi.delta_frames, raw_midi.data());
+        }
+
+        let dropped = consumer.take_dropped();
+        if dropped > 0 {
+            log::warn!(target: INPUT, "{} input messages dropped (relay ring full)", dropped);
+        }
+
+        if orphan {
+            return;
+        }
+
+        park_timeout(Duration::from_millis(10));
+    });
+    let waker = relay_thread.thread().clone();
+
+    Box::new(move |stamp, mesg| {
+        match realtime {
+            true => producer.push(stamp, mesg),
+            false => producer.push_waiting(stamp, mesg, &waker),
+        };
+        waker.unpark();
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi_server::sysex::MAX_SYSEX_SIZE;
+    use std::sync::mpsc::channel;
+    use std::thread::current;
+
+    #[test]
+    fn full_ring_drops_realtime_messages() {
+        let (mut producer, mut consumer) = input_ring();
+
+        for note in 0..RING_SIZE + 10 {
+            producer.push(note as u64, &[0x90, note as u8 & 0x7F, 0x7F]);
+        }
+
+        assert_eq!(consumer.take_dropped(), 10);
+        assert_eq!(std::iter::from_fn(|| consumer.pop()).count(), RING_SIZE);
+    }
+
+    #[test]
+    fn waiting_push_loses_nothing() {
+        let count = RING_SIZE * 4;
+        let (mut producer, mut consumer) = input_ring();
+        let (tx, rx) = channel();
+
+        let worker = spawn(move || {
+            let mut stamps = vec![];
+
+            while stamps.len() < count {
+                match consumer.pop() {
+                    Some(raw_midi) => stamps.push(raw_midi.delta_frames),
+                    None => park_timeout(Duration::from_millis(10)),
+                }
+            }
+            let _ = tx.send((stamps, consumer.take_dropped()));
+        });
+
+        for stamp in 0..count as u64 {
+            assert!(producer.push_waiting(stamp, &[0xE0, 0x00, 0x40], worker.thread()));
+        }
+
+        let (stamps, dropped) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(stamps, (0..count as u64).collect::<Vec<u64>>());
+        assert_eq!(dropped, 0);
+
+        // The consumer is gone, the producer does not wait forever on a full ring
+        worker.join().unwrap();
+        for stamp in 0..RING_SIZE as u64 {
+            assert!(producer.push(stamp, &[0xE0, 0x00, 0x40]));
+        }
+        assert!(!producer.push_waiting(0, &[0xE0, 0x00, 0x40], &current()));
+    }
+
+    /// The messages of the fragments handed by a backend, through the ring.
+    fn received(fragments: &[&[u8]]) -> Vec<Vec<u8>> {
+        let (mut producer, mut consumer) = input_ring();
+
+        for (stamp, fragment) in fragments.iter().enumerate() {
+            assert!(producer.push(stamp as u64, fragment));
+        }
+
+        std::iter::from_fn(|| consumer.pop())
+            .map(|raw_midi| raw_midi.data().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn split_sysex_is_joined() {
+        let lcd: Vec<u8> = [0xF0, 0x00, 0x00, 0x66, 0x14, 0x12, 0x00]
+            .into_iter()
+            .chain(b"Hello MiBL, a line longer than a slot".iter().copied())
+            .chain([0xF7])
+            .collect();
+        let (first, second) = lcd.split_at(20);
+
+        assert_eq!(received(&[first, second]), vec![lcd.clone()]);
+
+        // The stamp of the SysEx is the one of its first part
+        let (mut producer, mut consumer) = input_ring();
+        producer.push(7, first);
+        producer.push(8, second);
+        assert_eq!(*consumer.pop().unwrap().delta_frames(), 7);
+    }
+
+    #[test]
+    fn status_byte_aborts_the_sysex() {
+        let note: &[u8] = &[0x90, 0x10, 0x7F];
+
+        assert_eq!(received(&[&[0xF0, 0x01, 0x02], note]), vec![note.to_vec()]);
+        assert_eq!(
+            received(&[&[0xF0, 0x01, 0x02, 0x90, 0x10, 0x7F]]),
+            vec![note.to_vec()]
+        );
+        // The rest of the aborted SysEx is dropped
+        assert_eq!(
+            received(&[&[0xF0, 0x01], note, &[0x02, 0xF7], note]),
+            vec![note.to_vec(), note.to_vec()]
+        );
+    }
+
+    #[test]
+    fn overlong_sysex_is_dropped() {
+        let (mut producer, mut consumer) = input_ring();
+        let data = [0x01; MAX_MIDI_MSG_SIZE];
+        let mut received = vec![];
+
+        producer.push(0, &[0xF0, 0x01]);
+        for _ in 0..MAX_SYSEX_SIZE / MAX_MIDI_MSG_SIZE + 1 {
+            assert!(producer.push(0, &data));
+            received.extend(std::iter::from_fn(|| consumer.pop()));
+        }
+        producer.push(0, &[0x01, 0xF7, 0x90, 0x10, 0x7F]);
+        received.extend(std::iter::from_fn(|| consumer.pop()));
+
+        let received: Vec<&[u8]> = received.iter().map(RawMidi::data).collect();
+        assert_eq!(received, vec![&[0x90, 0x10, 0x7F]]);
+    }
+
+    #[test]
+    fn real_time_bytes_are_passed_on() {
+        assert_eq!(
+            received(&[
+                &[0xF0, 0x01, 0xF8, 0x02],
+                &[0x03, 0xFE, 0xF7, 0x90, 0x10, 0x7F]
+            ]),
+            vec![
+                vec![0xF8],
+                vec![0xFE],
+                vec![0xF0, 0x01, 0x02, 0x03, 0xF7],
+                vec![0x90, 0x10, 0x7F]
+            ]
+        );
+    }
+
+    #[test]
+    fn relay_hands_a_burst_over() {
+        let count = RING_SIZE * 4;
+        let (tx, rx) = channel();
+        let mut callback = relay_input(
+            Box::new(move |stamp, mesg| {
+                let _ = tx.send((stamp, mesg.to_vec()));
+            }),
+            false,
+        );
+
+        for stamp in 0..count as u64 {
+            callback(stamp, &[0x90, 0x10, 0x7F]);
+        }
+        drop(callback);
+
+        let relayed: Vec<u64> = rx.iter().map(|(stamp, _)| stamp).collect();
+        assert_eq!(relayed, (0..count as u64).collect::<Vec<u64>>());
+    }
+}
@@ -0,0 +1,705 @@
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{spawn, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::midi_server::logger::{INPUT, SERVER};
+use crate::midi_server::transport::{InputCallback, MidiTransport, TransportError};
+
+// RTP-MIDI (RFC 6295) with the AppleMIDI session protocol
+// A session use two UDP ports : control (N) and data (N + 1)
+// Session command = 0xFFFF + command (2 ASCII chars) + payload
+//   IN/OK/NO/BY : protocol version (u32) + initiator token (u32) + sender SSRC (u32) + name ('\0' terminated)
+//   CK : sender SSRC (u32) + count (u8) + padding (3 bytes) + 3 timestamps (u64, 100 µs units)
+//   RS : sender SSRC (u32) + sequence number (u16) + padding (2 bytes)
+// RTP packet = 12 bytes header (V=2, PT=0x61) + MIDI command section (no journal sent)
+//   MIDI command section header : B J Z P LEN (short header, 4 bits length) or B=1 (long header, 12 bits length)
+
+const APPLEMIDI_SIGNATURE: [u8; 2] = [0xFF, 0xFF];
+const APPLEMIDI_VERSION: u32 = 2;
+const RTP_VERSION: u8 = 0x80;
+const RTP_MIDI_PAYLOAD_TYPE: u8 = 0x61;
+const RTP_HEADER_SIZE: usize = 12;
+const RTP_MAX_PACKET: usize = 1500;
+
+const INVITE_RETRIES: usize = 12;
+const INVITE_TIMEOUT: Duration = Duration::from_millis(1500);
+const SYNC_PERIOD: Duration = Duration::from_secs(10);
+const POLL_TIMEOUT: Duration = Duration::from_millis(5);
+
+/// An AppleMIDI session command.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionCommand {
+    Invitation {
+        token: u32,
+        ssrc: u32,
+        name: String,
+    },
+    Accepted {
+        token: u32,
+        ssrc: u32,
+        name: String,
+    },
+    Rejected {
+        token: u32,
+        ssrc: u32,
+    },
+    End {
+        token: u32,
+        ssrc: u32,
+    },
+    Sync {
+        ssrc: u32,
+        count: u8,
+        timestamps: [u64; 3],
+    },
+    Feedback {
+        ssrc: u32,
+        seq: u16,
+    },
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Result<u32, String> {
+    match data.get(pos..pos + 4) {
+        Some(bytes) => Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        None => Err("Truncated session command".to_string()),
+    }
+}
+
+fn read_u64(data: &[u8], pos: usize) -> Result<u64, String> {
+    Ok(((read_u32(data, pos)? as u64) << 32) | read_u32(data, pos + 4)? as u64)
+}
+
+pub fn is_session_command(data: &[u8]) -> bool {
+    data.len() >= 4 && data[..2] == APPLEMIDI_SIGNATURE
+}
+
+pub fn encode_session_command(command: &SessionCommand) -> Vec<u8> {
+    let mut packet = APPLEMIDI_SIGNATURE.to_vec();
+
+    let mut push_invitation = |tag: &[u8; 2], token: &u32, ssrc: &u32, name: Option<&str>| {
+        packet.extend(tag);
+        packet.extend(APPLEMIDI_VERSION.to_be_bytes());
+        packet.extend(token.to_be_bytes());
+        packet.extend(ssrc.to_be_bytes());
+
+        if let Some(name) = name {
+            packet.extend(name.as_bytes());
+            packet.push(0);
+        }
+    };
+
+    match command {
+        SessionCommand::Invitation { token, ssrc, name } => {
+            push_invitation(b"IN", token, ssrc, Some(name))
+        }
+        SessionCommand::Accepted { token, ssrc, name } => {
+            push_invitation(b"OK", token, ssrc, Some(name))
+        }
+        SessionCommand::Rejected { token, ssrc } => push_invitation(b"NO", token, ssrc, None),
+        SessionCommand::End { token, ssrc } => push_invitation(b"BY", token, ssrc, None),
+        SessionCommand::Sync {
+            ssrc,
+            count,
+            timestamps,
+        } => {
+            packet.extend(b"CK");
+            packet.extend(ssrc.to_be_bytes());
+            packet.extend([*count, 0, 0, 0]);
+
+            for timestamp in timestamps {
+                packet.extend(timestamp.to_be_bytes());
+            }
+        }
+        SessionCommand::Feedback { ssrc, seq } => {
+            packet.extend(b"RS");
+            packet.extend(ssrc.to_be_bytes());
+            packet.extend(seq.to_be_bytes());
+            packet.extend([0, 0]);
+        }
+    }
+
+    packet
+}
+
+pub fn decode_session_command(data: &[u8]) -> Result<SessionCommand, String> {
+    if !is_session_command(data) {
+        return Err("Not an AppleMIDI session command".to_string());
+    }
+
+    let name = || -> String {
+        let name = data.get(16..).unwrap_or_default();
+        let end = name
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(name.len());
+        String::from_utf8_lossy(&name[..end]).to_string()
+    };
+
+    match &data[2..4] {
+        b"IN" => Ok(SessionCommand::Invitation {
+            token: read_u32(data, 8)?,
+            ssrc: read_u32(data, 12)?,
+            name: name(),
+        }),
+        b"OK" => Ok(SessionCommand::Accepted {
+            token: read_u32(data, 8)?,
+            ssrc: read_u32(data, 12)?,
+            name: name(),
+        }),
+        b"NO" => Ok(SessionCommand::Rejected {
+            token: read_u32(data, 8)?,
+            ssrc: read_u32(data, 12)?,
+        }),
+        b"BY" => Ok(SessionCommand::End {
+            token: read_u32(data, 8)?,
+            ssrc: read_u32(data, 12)?,
+        }),
+        b"CK" => Ok(SessionCommand::Sync {
+            ssrc: read_u32(data, 4)?,
+            count: *data.get(8).ok_or("Truncated session command")?,
+            timestamps: [
+                read_u64(data, 12)?,
+                read_u64(data, 20)?,
+                read_u64(data, 28)?,
+            ],
+        }),
+        b"RS" => Ok(SessionCommand::Feedback {
+            ssrc: read_u32(data, 4)?,
+            seq: (read_u32(data, 8)? >> 16) as u16,
+        }),
+        other => Err(format!(
+            "Unknown session command : {}",
+            String::from_utf8_lossy(other)
+        )),
+    }
+}
+
+/// Build a RTP-MIDI packet holding one MIDI message, without journal.
+pub fn encode_rtp_midi(seq: u16, timestamp: u32, ssrc: u32, mesg: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(RTP_HEADER_SIZE + 2 + mesg.len());
+
+    packet.push(RTP_VERSION);
+    packet.push(RTP_MIDI_PAYLOAD_TYPE);
+    packet.extend(seq.to_be_bytes());
+    packet.extend(timestamp.to_be_bytes());
+    packet.extend(ssrc.to_be_bytes());
+
+    match mesg.len() {
+        0..=0x0F => packet.push(mesg.len() as u8),
+        len => {
+            packet.push(0x80 | ((len >> 8) as u8 & 0x0F));
+            packet.push(len as u8);
+        }
+    }
+
+    packet.extend(mesg);
+    packet
+}
+
+/// Number of data bytes following a status byte (SysEx excluded).
+fn data_len(status: u8) -> usize {
+    match status {
+        0x80..=0xBF | 0xE0..=0xEF => 2,
+        0xC0..=0xDF => 1,
+        0xF1 | 0xF3 => 1,
+        0xF2 => 2,
+        _ => 0,
+    }
+}
+
+/// Extract all MIDI messages of a RTP-MIDI packet, the journal (if any) is ignored.
+///
+/// Running status and delta times are handled. Segmented SysEx are dropped.
+pub fn decode_rtp_midi(packet: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    if packet.len() < RTP_HEADER_SIZE + 1 || packet[0] & 0xC0 != RTP_VERSION {
+        return Err("Not a RTP packet".to_string());
+    }
+
+    if packet[1] & 0x7F != RTP_MIDI_PAYLOAD_TYPE {
+        return Err(format!("Not a RTP-MIDI payload type : {:02X}", packet[1]));
+    }
+
+    let header = packet[RTP_HEADER_SIZE];
+    let (len, mut pos) = match header & 0x80 {
+        0 => ((header & 0x0F) as usize, RTP_HEADER_SIZE + 1),
+        _ => {
+            let low = *packet
+                .get(RTP_HEADER_SIZE + 1)
+                .ok_or("Truncated MIDI command section")?;
+            (
+                ((header & 0x0F) as usize) << 8 | low as usize,
+                RTP_HEADER_SIZE + 2,
+            )
+        }
+    };
+    let with_first_delta = header & 0x20 != 0;
+
+    let list = packet
+        .get(pos..pos + len)
+        .ok_or("Truncated MIDI command section")?;
+    pos = 0;
+
+    let mut mesgs = vec![];
+    let mut running_status: Option<u8> = None;
+    let mut first = true;
+
+    while pos < list.len() {
+        if !first || with_first_delta {
+            // Delta time : 1 to 4 bytes, high bit set on all but the last one
+            while pos < list.len() && list[pos] & 0x80 != 0 {
+                pos += 1;
+            }
+            pos += 1;
+
+            if pos >= list.len() {
+                break;
+            }
+        }
+        first = false;
+
+        let status = match list[pos] {
+            byte if byte & 0x80 != 0 => {
+                pos += 1;
+                if byte < 0xF0 {
+                    running_status = Some(byte);
+                } else if byte < 0xF8 {
+                    running_status = None;
+                }
+                byte
+            }
+            _ => running_status.ok_or("Data byte without running status")?,
+        };
+
+        if status == 0xF0 || status == 0xF7 {
+            // SysEx ends on 0xF7 (complete) or 0xF0/0xF4 (segmented, dropped)
+            let end = list[pos..]
+                .iter()
+                .position(|&byte| matches!(byte, 0xF0 | 0xF4 | 0xF7))
+                .map(|idx| pos + idx)
+                .ok_or("SysEx without end")?;
+
+            if status == 0xF0 && list[end] == 0xF7 {
+                let mut sysex = vec![status];
+                sysex.extend(&list[pos..=end]);
+                mesgs.push(sysex);
+            }
+
+            pos = end + 1;
+            continue;
+        }
+
+        let end = pos + data_len(status);
+        let data = list.get(pos..end).ok_or("Truncated MIDI message")?;
+
+        let mut mesg = vec![status];
+        mesg.extend(data);
+        mesgs.push(mesg);
+
+        pos = end;
+    }
+
+    Ok(mesgs)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RtpPeer {
+    control: SocketAddr,
+    data: Option<SocketAddr>,
+    ssrc: u32,
+    token: u32,
+}
+
+struct RtpSession {
+    name: String,
+    ssrc: u32,
+    start: Instant,
+    control: UdpSocket,
+    data: UdpSocket,
+    peer: Mutex<Option<RtpPeer>>,
+    seq: Mutex<u16>,
+    callback: Mutex<Option<InputCallback>>,
+    initiator: bool,
+    closed: AtomicBool,
+}
+
+impl RtpSession {
+    /// Session clock, in 100 µs units.
+    fn now(&self) -> u64 {
+        (self.start.elapsed().as_micros() / 100) as u64
+    }
+
+    fn send_command(&self, socket: &UdpSocket, command: &SessionCommand, to: SocketAddr) {
+        if let Err(err) = socket.send_to(&encode_session_command(command), to) {
+            log::warn!(target: SERVER, "Unable to send RTP-MIDI session command : {}", err);
+        }
+    }
+
+    fn sync(&self, to: SocketAddr) {
+        self.send_command(
+            &self.data,
+            &SessionCommand::Sync {
+                ssrc: self.ssrc,
+                count: 0,
+                timestamps: [self.now(), 0, 0],
+            },
+            to,
+        );
+    }
+
+    fn handle_command(&self, data_port: bool, command: SessionCommand, from: SocketAddr) {
+        let socket = match data_port {
+            true => &self.data,
+            false => &self.control,
+        };
+        let mut peer = self.peer.lock().unwrap();
+
+        match command {
+            SessionCommand::Invitation { token, ssrc, name } => {
+                let accept = match (*peer, data_port) {
+                    (None, false) => true,
+                    (Some(current), _) => current.ssrc == ssrc,
+                    (None, true) => false,
+                };
+
+                if !accept {
+                    self.send_command(
+                        socket,
+                        &SessionCommand::Rejected {
+                            token,
+                            ssrc: self.ssrc,
+                        },
+                        from,
+                    );
+                    return;
+                }
+
+                match data_port {
+                    true => {
+                        if let Some(current) = peer.as_mut() {
+                            current.data = Some(from);
+                        }
+                        log::info!(
+                            target: SERVER,
+                            "RTP-MIDI session open with {} ({})",
+                            name,
+                            from
+                        );
+                    }
+                    false => {
+                        *peer = Some(RtpPeer {
+                            control: from,
+                            data: None,
+                            ssrc,
+                            token,
+                        })
+                    }
+                }
+
+                self.send_command(
+                    socket,
+                    &SessionCommand::Accepted {
+                        token,
+                        ssrc: self.ssrc,
+                        name: self.name.clone(),
+                    },
+                    from,
+                );
+            }
+            SessionCommand::End { ssrc, .. } => {
+                if peer.is_some_and(|current| current.ssrc == ssrc) {
+                    log::info!(target: SERVER, "RTP-MIDI session closed by peer");
+                    *peer = None;
+                }
+            }
+            SessionCommand::Sync {
+                ssrc,
+                count,
+                timestamps,
+            } => {
+                if !data_port || peer.is_none_or(|current| current.ssrc != ssrc) {
+                    return;
+                }
+
+                let reply = match count {
+                    0 => Some(SessionCommand::Sync {
+                        ssrc: self.ssrc,
+                        count: 1,
+                        timestamps: [timestamps[0], self.now(), 0],
+                    }),
+                    1 => Some(SessionCommand::Sync {
+                        ssrc: self.ssrc,
+                        count: 2,
+                        timestamps: [timestamps[0], timestamps[1], self.now()],
+                    }),
+                    _ => None,
+                };
+
+                if let Some(reply) = reply {
+                    self.send_command(socket, &reply, from);
+                }
+            }
+            SessionCommand::Accepted { .. }
+            | SessionCommand::Rejected { .. }
+            | SessionCommand::Feedback { .. } => (),
+        }
+    }
+
+    fn handle_packet(&self, data_port: bool, packet: &[u8], from: SocketAddr) {
+        if is_session_command(packet) {
+            match decode_session_command(packet) {
+                Ok(command) => self.handle_command(data_port, command, from),
+                Err(err) => {
+                    log::warn!(target: SERVER, "RTP-MIDI session command ignored : {}", err)
+                }
+            }
+            return;
+        }
+
+        if !data_port {
+            return;
+        }
+
+        match decode_rtp_midi(packet) {
+            Ok(mesgs) => {
+                let stamp = self.start.elapsed().as_micros() as u64;
+
+                if let Some(callback) = self.callback.lock().unwrap().as_mut() {
+                    for mesg in mesgs {
+                        callback(stamp, &mesg);
+                    }
+                }
+            }
+            Err(err) => log::warn!(target: INPUT, "RTP-MIDI packet ignored : {}", err),
+        }
+    }
+
+    /// Receive and answer on both ports until the transport is closed.
+    fn run(&self) {
+        let mut buffer = [0u8; RTP_MAX_PACKET];
+        let mut last_sync: Option<Instant> = None;
+
+        while !self.closed.load(Ordering::Relaxed) {
+            for (data_port, socket) in [(false, &self.control), (true, &self.data)] {
+                match socket.recv_from(&mut buffer) {
+                    Ok((len, from)) => self.handle_packet(data_port, &buffer[..len], from),
+                    Err(err)
+                        if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                    Err(err) => {
+                        log::error!(target: SERVER, "Unable to read RTP-MIDI socket : {}", err)
+                    }
+                }
+            }
+
+            // The session initiator keeps the clocks in sync
+            let peer_data = self.peer.lock().unwrap().and_then(|peer| peer.data);
+
+            if let (true, Some(peer_data)) = (self.initiator, peer_data) {
+                if last_sync.is_none_or(|last| last.elapsed() >= SYNC_PERIOD) {
+                    self.sync(peer_data);
+                    last_sync = Some(Instant::now());
+                }
+            }
+        }
+    }
+}
+
+/// Invite `to` on `socket`, returns the peer SSRC once accepted.
+fn invite(
+    session: &RtpSession,
+    socket: &UdpSocket,
+    token: u32,
+    to: SocketAddr,
+) -> Result<u32, TransportError> {
+    let mut buffer = [0u8; RTP_MAX_PACKET];
+    let invitation = SessionCommand::Invitation {
+        token,
+        ssrc: session.ssrc,
+        name: session.name.clone(),
+    };
+
+    for _ in 0..INVITE_RETRIES {
+        session.send_command(socket, &invitation, to);
+
+        let deadline = Instant::now() + INVITE_TIMEOUT;
+
+        while Instant::now() < deadline {
+            let len = match socket.recv_from(&mut buffer) {
+                Ok((len, from)) if from == to => len,
+                Ok(_) => continue,
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    continue
+                }
+                Err(err) => return Err(TransportError::Connect(err.to_string())),
+            };
+
+            match decode_session_command(&buffer[..len]) {
+                Ok(SessionCommand::Accepted {
+                    token: reply_token,
+                    ssrc,
+                    ..
+                }) if reply_token == token => return Ok(ssrc),
+                Ok(SessionCommand::Rejected {
+                    token: reply_token, ..
+                }) if reply_token == token => {
+                    return Err(TransportError::Connect(format!(
+                        "Invitation rejected by {}",
+                        to
+                    )))
+                }
+                _ => (),
+            }
+        }
+    }
+
+    Err(TransportError::Connect(format!("No answer from {}", to)))
+}
+
+/// A network MIDI session (RTP-MIDI / AppleMIDI), to use a networked control surface or a DAW
+/// like the locally connected device.
+///
+/// Without `peer`, wait for an invitation (the first participant is accepted, others are rejected).
+/// With `peer` ("host:control_port"), invite it.
+/// Messages sent while no participant is connected are dropped.
+pub struct RtpMidiTransport {
+    session: Arc<RtpSession>,
+    session_thread: Option<JoinHandle<()>>,
+}
+
+impl RtpMidiTransport {
+    pub fn connect(name: &str, port: u16, peer: Option<&str>) -> Result<Self, TransportError> {
+        let bind = |port: u16| -> Result<UdpSocket, TransportError> {
+            let socket = UdpSocket::bind(("0.0.0.0", port))
+                .map_err(|err| TransportError::Connect(format!("UDP port {} : {}", port, err)))?;
+            socket
+                .set_read_timeout(Some(POLL_TIMEOUT))
+                .map_err(|err| TransportError::Connect(err.to_string()))?;
+            Ok(socket)
+        };
+
+        let control = bind(port)?;
+        let data = bind(control.local_addr().map_or(0, |addr| addr.port() + 1))?;
+
+        let session = RtpSession {
+            name: name.to_string(),
+            ssrc: rand::random(),
+            start: Instant::now(),
+            control,
+            data,
+            peer: Mutex::new(None),
+            seq: Mutex::new(rand::random()),
+            callback: Mutex::new(None),
+            initiator: peer.is_some(),
+            closed: AtomicBool::new(false),
+        };
+
+        if let Some(peer) = peer {
+            let peer_control = peer
+                .to_socket_addrs()
+                .map_err(|err| TransportError::Connect(format!("{} : {}", peer, err)))?
+                .next()
+                .ok_or(TransportError::Connect(format!("{} not found", peer)))?;
+            let mut peer_data = peer_control;
+            peer_data.set_port(peer_control.port() + 1);
+
+            let token = rand::random();
+            let ssrc = invite(&session, &session.control, token, peer_control)?;
+            invite(&session, &session.data, token, peer_data)?;
+
+            log::info!(target: SERVER, "RTP-MIDI session open with {}", peer_control);
+
+            *session.peer.lock().unwrap() = Some(RtpPeer {
+                control: peer_control,
+                data: Some(peer_data),
+                ssrc,
+                token,
+            });
+        }
+
+        let session = Arc::new(session);
+        let session_clone = Arc::clone(&session);
+
+        Ok(Self {
+            session,
+            session_thread: Some(spawn(move || session_clone.run())),
+        })
+    }
+}
+
+impl MidiTransport for RtpMidiTransport {
+    fn port_name(&self) -> &str {
+        &self.session.name
+    }
+
+    fn ports(&self) -> Vec<String> {
+        let peer = *self.session.peer.lock().unwrap();
+
+        match peer {
+            Some(peer) => vec![peer.control.to_string()],
+            None => vec![],
+        }
+    }
+
+    fn send(&mut self, mesg: &[u8]) -> Result<(), TransportError> {
+        if self.session.closed.load(Ordering::Relaxed) {
+            return Err(TransportError::Closed);
+        }
+
+        let peer_data = match self.session.peer.lock().unwrap().and_then(|peer| peer.data) {
+            Some(peer_data) => peer_data,
+            None => return Ok(()),
+        };
+
+        let seq = {
+            let mut seq = self.session.seq.lock().unwrap();
+            *seq = seq.wrapping_add(1);
+            *seq
+        };
+
+        let packet = encode_rtp_midi(seq, self.session.now() as u32, self.session.ssrc, mesg);
+
+        self.session
+            .data
+            .send_to(&packet, peer_data)
+            .map(|_| ())
+            .map_err(|err| TransportError::Send(err.to_string()))
+    }
+
+    fn listen(&mut self, callback: InputCallback) -> Result<(), TransportError> {
+        if self.session.closed.load(Ordering::Relaxed) {
+            return Err(TransportError::Closed);
+        }
+
+        let mut current = self.session.callback.lock().unwrap();
+
+        if current.is_some() {
+            return Err(TransportError::AlreadyListening);
+        }
+
+        *current = Some(callback);
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        self.session.closed.store(true, Ordering::Relaxed);
+
+        if let Some(session_thread) = self.session_thread.take() {
+            let _ = session_thread.join();
+        }
+
+        if let Some(peer) = self.session.peer.lock().unwrap().take() {
+            self.session.send_command(
+                &self.session.control,
+                &SessionCommand::End {
+                    token: peer.token,
+                    ssrc: self.session.ssrc,
+                },
+                peer.control,
+            );
+        }
+
+        *self.session.callback.lock().unwrap() = None;
+    }
+}
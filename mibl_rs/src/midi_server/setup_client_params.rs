@@ -1,8 +1,9 @@
 use midir::{Ignore, MidiInput, MidiInputPort, MidiOutput, MidiOutputPort};
 
-use std::sync::{Arc, Mutex};
 use thiserror::Error;
 
+use crate::midi_server::logger::SERVER;
+
 pub struct AudioParams {
     pub port_name: String,
     pub midi_input: MidiInput,
@@ -87,10 +88,10 @@ pub fn setup_client_params() -> SetupResult {
         }
     };
 
-    println!("\nOpening connection");
+    log::debug!(target: SERVER, "Opening connection");
     let in_port_name = midi_in.port_name(in_port).unwrap();
 
-    println!("Connection open, reading input from '{}'…", in_port_name);
+    log::info!(target: SERVER, "Connection open, reading input from '{}'…", in_port_name);
 
     let parameters = AudioParams {
         port_name: in_port_name,
@@ -0,0 +1,513 @@
+use std::fs;
+
+use crate::midi_server::recorder::{Direction, RecordedMidi};
+
+// STANDARD MIDI FILE (SMF)
+// Chunk      Content
+// MThd       format (u16), tracks count (u16), division (u16)
+// MTrk       [delta time (VLQ), event]…, end of track (FF 2F 00)
+//
+// Division   bit 15 = 0 : ticks per quarter note
+//            bit 15 = 1 : -fps (i8) | ticks per frame (u8)
+
+const SMPTE_TICKS_PER_FRAME: u8 = 40;
+const METRICAL_TICKS_PER_FRAME: u64 = 20;
+const DEFAULT_TEMPO: u32 = 500_000; // µs per quarter note (120 bpm)
+
+/// (tick, raw midi) of a track.
+type TrackEvents = Vec<(u64, Vec<u8>)>;
+
+/// The track an incoming control belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum ControlTrack {
+    Strip(u8),
+    Master,
+    Global,
+}
+
+impl ControlTrack {
+    fn name(&self) -> String {
+        match self {
+            ControlTrack::Strip(strip) => format!("Strip {}", strip + 1),
+            ControlTrack::Master => "Master".to_string(),
+            ControlTrack::Global => "Global".to_string(),
+        }
+    }
+}
+
+/// Find the control track of an incoming Mackie Control message, `None` if not control data.
+fn control_track(data: &[u8]) -> Option<ControlTrack> {
+    if data.len() < 2 {
+        return None;
+    }
+
+    let clean_cmd = (data[0] >> 4) << 4;
+
+    match clean_cmd {
+        0xE0 => match data[0] & 0x0F {
+            strip @ 0x00..=0x07 => Some(ControlTrack::Strip(strip)),
+            _ => Some(ControlTrack::Master),
+        },
+        0xB0 => match data[1] {
+            vpot @ 0x10..=0x17 => Some(ControlTrack::Strip(vpot - 0x10)),
+            _ => Some(ControlTrack::Global),
+        },
+        0x80 | 0x90 => match data[1] {
+            note @ 0x00..=0x1F => Some(ControlTrack::Strip(note % 8)),
+            note @ 0x20..=0x27 => Some(ControlTrack::Strip(note - 0x20)),
+            note @ 0x68..=0x6F => Some(ControlTrack::Strip(note - 0x68)),
+            0x70 => Some(ControlTrack::Master),
+            _ => Some(ControlTrack::Global),
+        },
+        0xA0 | 0xC0 | 0xD0 => Some(ControlTrack::Global),
+        _ => None,
+    }
+}
+
+fn push_vlq(buffer: &mut Vec<u8>, value: u64) {
+    let mut value = value & 0x0FFF_FFFF;
+    let mut bytes = vec![(value & 0x7F) as u8];
+    value >>= 7;
+
+    while value > 0 {
+        bytes.push(((value & 0x7F) as u8) | 0x80);
+        value >>= 7;
+    }
+
+    bytes.reverse();
+    buffer.extend(bytes);
+}
+
+fn read_vlq(data: &[u8], pos: &mut usize) -> Result<u64, String> {
+    let mut value: u64 = 0;
+
+    for _ in 0..4 {
+        let byte = *data
+            .get(*pos)
+            .ok_or("Unexpected end of track in delta time")?;
+        *pos += 1;
+        value = (value << 7) | (byte & 0x7F) as u64;
+
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err("Variable length quantity too long".to_string())
+}
+
+fn push_chunk(buffer: &mut Vec<u8>, kind: &[u8; 4], content: &[u8]) {
+    buffer.extend(kind);
+    buffer.extend((content.len() as u32).to_be_bytes());
+    buffer.extend(content);
+}
+
+/// The time base of the file : SMPTE division when the fps allows it, ticks per quarter note otherwise.
+struct TimeBase {
+    division: u16,
+    smpte_rate: u8, // SMPTE offset rate bits : 0 = 24, 1 = 25, 2 = 29.97 drop, 3 = 30
+    ticks_per_second: f64,
+}
+
+impl TimeBase {
+    fn from_fps(fps: u64) -> Self {
+        match fps {
+            24 | 25 | 29 | 30 => {
+                let rate = match fps {
+                    24 => 0,
+                    25 => 1,
+                    29 => 2,
+                    _ => 3,
+                };
+                let real_fps = if fps == 29 {
+                    30_000.0 / 1001.0
+                } else {
+                    fps as f64
+                };
+
+                TimeBase {
+                    division: ((-(fps as i8) as u8 as u16) << 8) | SMPTE_TICKS_PER_FRAME as u16,
+                    smpte_rate: rate,
+                    ticks_per_second: real_fps * SMPTE_TICKS_PER_FRAME as f64,
+                }
+            }
+            _ => {
+                // One second per quarter note (see tempo meta event), so a frame is always
+                // METRICAL_TICKS_PER_FRAME ticks.
+                let fps = fps.clamp(1, 1600);
+                TimeBase {
+                    division: (fps * METRICAL_TICKS_PER_FRAME) as u16,
+                    smpte_rate: 3,
+                    ticks_per_second: (fps * METRICAL_TICKS_PER_FRAME) as f64,
+                }
+            }
+        }
+    }
+
+    fn tempo(&self) -> u32 {
+        if self.division & 0x8000 != 0 {
+            DEFAULT_TEMPO
+        } else {
+            1_000_000
+        }
+    }
+}
+
+/// Build a Standard MIDI File (format 1) from the incoming control data of a session.
+///
+/// Track 1 is the tempo track (tempo, SMPTE offset from `start` [h, m, s, f]), then one track by
+/// channel strip, one for the master fader and one for all others controls (only non empty tracks).
+pub fn session_to_smf(
+    session: &[RecordedMidi],
+    fps: u64,
+    start: [usize; 4],
+) -> Result<Vec<u8>, String> {
+    let time_base = TimeBase::from_fps(fps);
+
+    let mut tracks: Vec<(ControlTrack, TrackEvents)> = vec![];
+    let mut time_us: u64 = 0;
+
+    for recorded in session {
+        time_us += recorded.delta;
+
+        if recorded.direction != Direction::In {
+            continue;
+        }
+
+        if let Some(track) = control_track(&recorded.data) {
+            let tick = ((time_us as f64) * time_base.ticks_per_second / 1_000_000.0).round();
+
+            match tracks.iter_mut().find(|(kind, _)| *kind == track) {
+                Some((_, events)) => events.push((tick as u64, recorded.data.clone())),
+                None => tracks.push((track, vec![(tick as u64, recorded.data.clone())])),
+            }
+        }
+    }
+
+    tracks.sort_by_key(|(kind, _)| *kind);
+
+    let mut smf: Vec<u8> = vec![];
+
+    let mut header = vec![];
+    header.extend(1u16.to_be_bytes());
+    header.extend(((tracks.len() + 1) as u16).to_be_bytes());
+    header.extend(time_base.division.to_be_bytes());
+    push_chunk(&mut smf, b"MThd", &header);
+
+    let mut tempo_track = vec![];
+    let name = b"MiBL session";
+    tempo_track.extend([0x00, 0xFF, 0x03, name.len() as u8]);
+    tempo_track.extend(name);
+    let tempo = time_base.tempo().to_be_bytes();
+    tempo_track.extend([0x00, 0xFF, 0x51, 0x03, tempo[1], tempo[2], tempo[3]]);
+    tempo_track.extend([
+        0x00,
+        0xFF,
+        0x54,
+        0x05,
+        (time_base.smpte_rate << 5) | (start[0].min(23) as u8),
+        start[1].min(59) as u8,
+        start[2].min(59) as u8,
+        start[3].min(29) as u8,
+        0x00,
+    ]);
+    tempo_track.extend([0x00, 0xFF, 0x2F, 0x00]);
+    push_chunk(&mut smf, b"MTrk", &tempo_track);
+
+    for (kind, events) in tracks {
+        let mut track = vec![];
+        let name = kind.name();
+        track.extend([0x00, 0xFF, 0x03, name.len() as u8]);
+        track.extend(name.as_bytes());
+
+        let mut last_tick = 0;
+        for (tick, data) in events {
+            push_vlq(&mut track, tick - last_tick);
+            track.extend(data);
+            last_tick = tick;
+        }
+
+        track.extend([0x00, 0xFF, 0x2F, 0x00]);
+        push_chunk(&mut smf, b"MTrk", &track);
+    }
+
+    Ok(smf)
+}
+
+pub fn export_smf(
+    session: &[RecordedMidi],
+    fps: u64,
+    start: [usize; 4],
+    path: &str,
+) -> Result<(), String> {
+    let smf = session_to_smf(session, fps, start)?;
+    fs::write(path, smf).map_err(|err| format!("Unable to write {} : {}", path, err))
+}
+
+pub fn is_smf(path: &str) -> bool {
+    match fs::read(path) {
+        Ok(data) => data.starts_with(b"MThd"),
+        Err(_) => false,
+    }
+}
+
+/// Read all tracks of a Standard MIDI File (format 0 or 1) and merge them in a session of incoming
+/// messages, scheduled with the tempo map of the file.
+pub fn smf_to_session(data: &[u8]) -> Result<Vec<RecordedMidi>, String> {
+    if data.len() < 14 || &data[0..4] != b"MThd" {
+        return Err("Not a Standard MIDI File".to_string());
+    }
+
+    let header_len = u32::from_be_bytes([data[4], data[5], data[6], data[7]]) as usize;
+    let format = u16::from_be_bytes([data[8], data[9]]);
+    let division = u16::from_be_bytes([data[12], data[13]]);
+
+    if format > 1 {
+        return Err(format!("SMF format {} not supported", format));
+    }
+
+    // Ticks per second of a SMPTE division, `None` for ticks per quarter note
+    let smpte_ticks_per_second = match division & 0x8000 {
+        0 => None,
+        _ => {
+            let fps = 0x100 - (division >> 8);
+            let ticks_per_frame = division & 0xFF;
+
+            match fps {
+                24 | 25 | 30 if ticks_per_frame > 0 => Some(fps as f64 * ticks_per_frame as f64),
+                29 if ticks_per_frame > 0 => Some(30_000.0 / 1001.0 * ticks_per_frame as f64),
+                _ => {
+                    return Err(format!(
+                        "Bad SMPTE division : {} fps, {} ticks per frame",
+                        fps, ticks_per_frame
+                    ))
+                }
+            }
+        }
+    };
+
+    let mut pos = 8 + header_len;
+
+    // (tick, track order, data)
+    let mut events: Vec<(u64, usize, Vec<u8>)> = vec![];
+    // (tick, µs per quarter note)
+    let mut tempo_map: Vec<(u64, u32)> = vec![];
+
+    while pos + 8 <= data.len() {
+        let kind = &data[pos..pos + 4];
+        let len = u32::from_be_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]])
+            as usize;
+        let start = pos + 8;
+        let end = start + len;
+
+        if end > data.len() {
+            return Err("Truncated chunk".to_string());
+        }
+
+        if kind == b"MTrk" {
+            read_track(&data[start..end], &mut events, &mut tempo_map)?;
+        }
+
+        pos = end;
+    }
+
+    tempo_map.sort_by_key(|(tick, _)| *tick);
+    events.sort_by_key(|(tick, order, _)| (*tick, *order));
+
+    let tick_to_us = |tick: u64| -> f64 {
+        if let Some(ticks_per_second) = smpte_ticks_per_second {
+            return tick as f64 * 1_000_000.0 / ticks_per_second;
+        }
+
+        let ppq = division.max(1) as f64;
+        let mut time_us = 0.0;
+        let mut last_tick = 0;
+        let mut tempo = DEFAULT_TEMPO;
+
+        for (tempo_tick, new_tempo) in &tempo_map {
+            if *tempo_tick >= tick {
+                break;
+            }
+            time_us += (tempo_tick - last_tick) as f64 * tempo as f64 / ppq;
+            last_tick = *tempo_tick;
+            tempo = *new_tempo;
+        }
+
+        time_us + (tick - last_tick) as f64 * tempo as f64 / ppq
+    };
+
+    let mut session = Vec::with_capacity(events.len());
+    let mut last_us: u64 = 0;
+
+    for (tick, _, data) in events {
+        let time_us = tick_to_us(tick).round() as u64;
+
+        session.push(RecordedMidi {
+            delta: time_us.saturating_sub(last_us),
+            direction: Direction::In,
+            data,
+        });
+
+        last_us = time_us.max(last_us);
+    }
+
+    Ok(session)
+}
+
+fn read_track(
+    track: &[u8],
+    events: &mut Vec<(u64, usize, Vec<u8>)>,
+    tempo_map: &mut Vec<(u64, u32)>,
+) -> Result<(), String> {
+    let mut pos = 0;
+    let mut tick: u64 = 0;
+    let mut running_status: Option<u8> = None;
+
+    while pos < track.len() {
+        tick += read_vlq(track, &mut pos)?;
+
+        let mut status = *track.get(pos).ok_or("Unexpected end of track")?;
+
+        if status & 0x80 == 0 {
+            // Running status, the byte is the first data byte.
+            status = running_status.ok_or("Running status without previous status")?;
+        } else {
+            pos += 1;
+        }
+
+        match status {
+            0xFF => {
+                let meta = *track.get(pos).ok_or("Truncated meta event")?;
+                pos += 1;
+                let len = read_vlq(track, &mut pos)? as usize;
+                let content = track.get(pos..pos + len).ok_or("Truncated meta event")?;
+                pos += len;
+
+                match meta {
+                    0x51 if len == 3 => tempo_map.push((
+                        tick,
+                        u32::from_be_bytes([0, content[0], content[1], content[2]]),
+                    )),
+                    0x2F => return Ok(()),
+                    _ => (),
+                }
+            }
+            0xF0 | 0xF7 => {
+                let len = read_vlq(track, &mut pos)? as usize;
+                let content = track.get(pos..pos + len).ok_or("Truncated SysEx event")?;
+                pos += len;
+
+                let mut sysex = vec![];
+                if status == 0xF0 {
+                    sysex.push(0xF0);
+                }
+                sysex.extend(content);
+                events.push((tick, events.len(), sysex));
+                running_status = None;
+            }
+            0x80..=0xEF => {
+                let data_len = match status & 0xF0 {
+                    0xC0 | 0xD0 => 1,
+                    _ => 2,
+                };
+                let content = track
+                    .get(pos..pos + data_len)
+                    .ok_or("Truncated channel event")?;
+                pos += data_len;
+
+                let mut mesg = vec![status];
+                mesg.extend(content);
+                events.push((tick, events.len(), mesg));
+                running_status = Some(status);
+            }
+            _ => return Err(format!("Unknown SMF event status {:02X}", status)),
+        }
+    }
+
+    Ok(())
+}
+
+pub fn import_smf(path: &str) -> Result<Vec<RecordedMidi>, String> {
+    let data = fs::read(path).map_err(|err| format!("Unable to read {} : {}", path, err))?;
+    smf_to_session(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A header chunk without track.
+    fn header(division: u16) -> Vec<u8> {
+        let mut smf = vec![];
+        let mut header = vec![];
+        header.extend(1u16.to_be_bytes());
+        header.extend(0u16.to_be_bytes());
+        header.extend(division.to_be_bytes());
+        push_chunk(&mut smf, b"MThd", &header);
+        smf
+    }
+
+    /// (time in µs, data) of the incoming messages of a session.
+    fn timeline(session: &[RecordedMidi]) -> Vec<(u64, Vec<u8>)> {
+        let mut time_us = 0;
+
+        session
+            .iter()
+            .filter_map(|recorded| {
+                time_us += recorded.delta;
+                (recorded.direction == Direction::In).then(|| (time_us, recorded.data.clone()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn session_round_trip() {
+        let recorded = |delta, direction, data: &[u8]| RecordedMidi {
+            delta,
+            direction,
+            data: data.to_vec(),
+        };
+        let session = vec![
+            recorded(0, Direction::In, &[0x90, 0x5E, 0x7F]),
+            recorded(40_000, Direction::In, &[0xE0, 0x00, 0x40]),
+            recorded(10_000, Direction::Out, &[0xE0, 0x00, 0x40]),
+            recorded(30_000, Direction::In, &[0xE8, 0x7F, 0x7F]),
+            recorded(120_000, Direction::In, &[0xB0, 0x12, 0x41]),
+            recorded(1_000_000, Direction::In, &[0x80, 0x5E, 0x00]),
+        ];
+        let expected = timeline(&session);
+
+        // SMPTE divisions, then ticks per quarter note
+        for fps in [24, 25, 29, 30, 60] {
+            let smf = session_to_smf(&session, fps, [1, 0, 0, 0]).unwrap();
+            let read = timeline(&smf_to_session(&smf).unwrap());
+
+            assert_eq!(read.len(), expected.len(), "{} fps", fps);
+            for ((read_us, read), (us, data)) in read.iter().zip(&expected) {
+                assert_eq!(read, data, "{} fps", fps);
+                assert!(
+                    read_us.abs_diff(*us) <= 1_000,
+                    "{} fps : {} µs",
+                    fps,
+                    read_us
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn bad_smpte_division_is_an_error() {
+        // -128 fps, 0 ticks per frame and an unknown rate
+        for division in [0x8028, 0xE700, 0xE628] {
+            let err = smf_to_session(&header(division)).unwrap_err();
+            assert!(
+                err.starts_with("Bad SMPTE division"),
+                "{:04X} : {}",
+                division,
+                err
+            );
+        }
+
+        assert!(smf_to_session(&header(0xE728)).unwrap().is_empty());
+    }
+}
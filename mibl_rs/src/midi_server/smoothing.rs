@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::midi_server::container::{ExtTrigger, IngredientOptions};
+
+// SMOOTHING
+// Declared per ingredient in the recipe options, for the values sent by the device :
+//   {"smooth": "80"}       One-pole low-pass, time constant in ms
+//   {"slew": "2.0"}        Slew-rate limit, in units by second
+//   {"deadband": "0.01"}   Dead-band, an input closer than this to the current target is ignored
+// The value of a smoothed event is not sent by `process_midi_mesg` : it sets the target, and the
+// server loop sends the smoothed value on each tick (at most one trigger by tick and event).
+
+const SETTLED: f32 = 1e-4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Smoothing {
+    time_constant: Option<f32>, // seconds
+    slew: Option<f32>,          // units by second
+    deadband: f32,
+}
+
+impl Smoothing {
+    /// The smoothing declared in the options of an ingredient, if any.
+    pub fn from_options(options: &IngredientOptions) -> Result<Option<Smoothing>, String> {
+        let number = |key: &str| -> Result<Option<f32>, String> {
+            match options.get(key) {
+                Some(value) => match value.trim().parse::<f32>() {
+                    Ok(number) if number >= 0.0 => Ok(Some(number)),
+                    Ok(_) => Err(format!("Negative {} {}", key, value)),
+                    Err(err) => Err(format!("Bad {} {} : {}", key, value, err)),
+                },
+                None => Ok(None),
+            }
+        };
+
+        let time_constant = number("smooth")?.map(|ms| ms / 1000.0);
+        let slew = number("slew")?;
+        let deadband = number("deadband")?;
+
+        if time_constant.is_none() && slew.is_none() && deadband.is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(Smoothing {
+            time_constant,
+            slew,
+            deadband: deadband.unwrap_or_default(),
+        }))
+    }
+
+    /// The value following `target` from `current` after `elapsed` seconds.
+    fn step(&self, current: f32, target: f32, elapsed: f32) -> f32 {
+        let mut next = match self.time_constant {
+            Some(time_constant) if time_constant > 0.0 => {
+                current + (target - current) * (1.0 - (-elapsed / time_constant).exp())
+            }
+            _ => target,
+        };
+
+        if let Some(slew) = self.slew {
+            let max_step = slew * elapsed;
+            next = current + (next - current).clamp(-max_step, max_step);
+        }
+
+        if (target - next).abs() < SETTLED {
+            target
+        } else {
+            next
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SmoothedValue {
+    smoothing: Smoothing,
+    current: f32,
+    target: f32,
+    sent: Option<f32>,
+    last_tick: Instant,
+}
+
+/// The smoothed values by event index, stored in `SIGflag`.
+#[derive(Debug, Clone, Default)]
+pub struct Smoother {
+    values: HashMap<u64, SmoothedValue>,
+}
+
+impl Smoother {
+    /// A new value of the device for the event `index`.
+    pub fn set_target(&mut self, index: u64, value: f32, smoothing: Smoothing, now: Instant) {
+        match self.values.get_mut(&index) {
+            Some(smoothed) => {
+                if (value - smoothed.target).abs() >= smoothing.deadband {
+                    smoothed.target = value;
+                }
+                smoothed.smoothing = smoothing;
+            }
+            // The first value is sent as is on the next tick
+            None => {
+                self.values.insert(
+                    index,
+                    SmoothedValue {
+                        smoothing,
+                        current: value,
+                        target: value,
+                        sent: None,
+                        last_tick: now,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Move the values toward their targets, return the triggers of the values changed.
+    pub fn tick(&mut self, now: Instant) -> Vec<ExtTrigger> {
+        let mut triggers = vec![];
+
+        for (index, smoothed) in self.values.iter_mut() {
+            let elapsed = now.duration_since(smoothed.last_tick).as_secs_f32();
+            smoothed.last_tick = now;
+
+            if smoothed.current != smoothed.target {
+                smoothed.current =
+                    smoothed
+                        .smoothing
+                        .step(smoothed.current, smoothed.target, elapsed);
+            }
+
+            if smoothed.sent != Some(smoothed.current) {
+                smoothed.sent = Some(smoothed.current);
+                triggers.push((*index, smoothed.current));
+            }
+        }
+
+        triggers
+    }
+
+    /// Forget the values, *e.g* after a panic.
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+}
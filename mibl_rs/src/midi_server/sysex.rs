@@ -0,0 +1,116 @@
+use std::collections::VecDeque;
+
+use crate::midi_server::container::RawMidi;
+use crate::midi_server::logger::INPUT;
+
+// SYSTEM EXCLUSIVE
+// A SysEx (F0 … F7) can be longer than `MAX_MIDI_MSG_SIZE` : the input ring carries it in
+// fragments, and a backend may split it across its callbacks (the next parts start with data
+// bytes). The worker joins the fragments until the F7 :
+//   - A real-time message (F8 -> FF) inside a SysEx is passed on at once.
+//   - Any other status byte aborts the SysEx, it is dropped.
+//   - The messages following the F7 (or the aborting status byte) in a fragment are kept.
+//   - A SysEx longer than `MAX_SYSEX_SIZE` is dropped.
+
+pub const MAX_SYSEX_SIZE: usize = 65536;
+
+/// Joins the fragments of the SysEx, stored by the reading end of the input ring.
+#[derive(Debug, Clone, Default)]
+pub struct SysExAssembler {
+    buffer: Vec<u8>, // empty out of a SysEx
+    stamp: u64,
+    ready: VecDeque<RawMidi>, // messages completed, oldest first
+}
+
+impl SysExAssembler {
+    /// Feed a fragment received from the device, the messages completed are queued.
+    pub fn feed(&mut self, fragment: RawMidi) {
+        let stamp = *fragment.delta_frames();
+        let data = fragment.data();
+
+        let Some(&status) = data.first() else {
+            return;
+        };
+
+        let body = match status {
+            0xF8..=0xFF => return self.ready.push_back(fragment),
+            0xF0 => {
+                if !self.buffer.is_empty() {
+                    log::warn!(
+                        target: INPUT,
+                        "SysEx without tail, dropped ({} bytes)",
+                        self.buffer.len()
+                    );
+                    self.buffer.clear();
+                }
+                self.stamp = stamp;
+                self.buffer.push(0xF0);
+                &data[1..]
+            }
+            0x00..=0x7F | 0xF7 => data, // Rest of a SysEx, joined or dropped
+            _ => {
+                if !self.buffer.is_empty() {
+                    log::warn!(target: INPUT, "SysEx aborted by {:02X?}, dropped", status);
+                    self.buffer.clear();
+                }
+                return self.ready.push_back(fragment);
+            }
+        };
+        let joined = !self.buffer.is_empty();
+
+        // The SysEx goes on up to the F7 or any other status byte, except the real-time ones
+        let end = body
+            .iter()
+            .position(|byte| matches!(byte, 0x80..=0xF7))
+            .unwrap_or(body.len());
+
+        for &byte in &body[..end] {
+            match byte {
+                0xF8..=0xFF => self.ready.extend(RawMidi::new(stamp, &[byte])),
+                _ if joined => self.buffer.push(byte),
+                _ => (),
+            }
+        }
+
+        let mut rest = &body[end..];
+        match rest.first() {
+            Some(0xF7) => {
+                rest = &rest[1..];
+
+                if joined {
+                    self.buffer.push(0xF7);
+                    let sysex = std::mem::take(&mut self.buffer);
+                    self.ready.extend(RawMidi::new(self.stamp, &sysex));
+                }
+            }
+            Some(status) => {
+                if joined {
+                    log::warn!(target: INPUT, "SysEx aborted by {:02X?}, dropped", status);
+                    self.buffer.clear();
+                }
+            }
+            None => {
+                if self.buffer.len() > MAX_SYSEX_SIZE {
+                    log::warn!(
+                        target: INPUT,
+                        "SysEx longer than {} bytes, dropped",
+                        MAX_SYSEX_SIZE
+                    );
+                    self.buffer.clear();
+                }
+            }
+        }
+
+        // Messages packed after the SysEx in the same fragment
+        if !rest.is_empty() {
+            if let Ok(next) = RawMidi::new(stamp, rest) {
+                self.feed(next);
+            }
+        }
+    }
+
+    /// The oldest message completed, if any.
+    pub fn take_ready(&mut self) -> Option<RawMidi> {
+        self.ready.pop_front()
+    }
+}
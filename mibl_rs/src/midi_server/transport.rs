@@ -0,0 +1,388 @@
+use midir::{MidiInput, MidiInputConnection, MidiInputPort, MidiOutput, MidiOutputConnection};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+#[cfg(feature = "websocket")]
+use crate::midi_server::ring::relay_input;
+use crate::midi_server::setup_client_params::{setup_client_params, ParamsInitError};
+
+/// Callback called by a transport each time a MIDI message is received : (timestamp, raw bytes)
+pub type InputCallback = Box<dyn FnMut(u64, &[u8]) + Send + 'static>;
+
+#[derive(Error, Debug)]
+pub enum TransportError {
+    #[error("Unable to setup the transport : {0}")]
+    Setup(#[from] ParamsInitError),
+    #[error("Unable to connect to port : {0}")]
+    Connect(String),
+    #[error("Unable to send message : {0}")]
+    Send(String),
+    #[error("Transport is closed")]
+    Closed,
+    #[error("Transport is already listening")]
+    AlreadyListening,
+}
+
+/// A way to talk with a MIDI device, whatever the backend used (midir, in-memory loopback, …).
+///
+/// The server only use this trait, so the same logic can run over any transport.
+pub trait MidiTransport: Send {
+    /// Human readable name of the port the transport is connected to.
+    fn port_name(&self) -> &str;
+
+    /// Enumerate all ports reachable through this transport.
+    fn ports(&self) -> Vec<String>;
+
+    /// Send raw bytes to the device.
+    fn send(&mut self, mesg: &[u8]) -> Result<(), TransportError>;
+
+    /// Start receiving messages, each timestamped message is given to `callback`.
+    ///
+    /// Can only be called once per transport.
+    fn listen(&mut self, callback: InputCallback) -> Result<(), TransportError>;
+
+    /// Close the connection, after this call `send` and `listen` return `TransportError::Closed`.
+    fn close(&mut self);
+
+    /// `false` if the callback may wait (c.f `src/midi_server/ring.rs`) : the messages do not come
+    /// from a device thread, *e.g* a replay or a loopback.
+    fn is_realtime(&self) -> bool {
+        true
+    }
+}
+
+impl<T: MidiTransport + ?Sized> MidiTransport for Box<T> {
+    fn port_name(&self) -> &str {
+        (**self).port_name()
+    }
+
+    fn ports(&self) -> Vec<String> {
+        (**self).ports()
+    }
+
+    fn send(&mut self, mesg: &[u8]) -> Result<(), TransportError> {
+        (**self).send(mesg)
+    }
+
+    fn listen(&mut self, callback: InputCallback) -> Result<(), TransportError> {
+        (**self).listen(callback)
+    }
+
+    fn close(&mut self) {
+        (**self).close()
+    }
+
+    fn is_realtime(&self) -> bool {
+        (**self).is_realtime()
+    }
+}
+
+/// The system MIDI backend, using `midir`.
+pub struct MidirTransport {
+    port_name: String,
+    ports: Vec<String>,
+    midi_input: Option<MidiInput>,
+    midi_input_port: MidiInputPort,
+    conn_in: Option<MidiInputConnection<()>>,
+    conn_out: Option<MidiOutputConnection>,
+}
+
+impl MidirTransport {
+    /// Connect to the system MIDI device (c.f `setup_client_params`).
+    pub fn connect() -> Result<Self, TransportError> {
+        let params = setup_client_params()?;
+
+        let ports = list_midir_ports(&params.midi_output);
+
+        let conn_out = match params
+            .midi_output
+            .connect(&params.midi_output_port, "bl-midi-out")
+        {
+            Ok(out) => out,
+            Err(err) => return Err(TransportError::Connect(err.to_string())),
+        };
+
+        Ok(Self {
+            port_name: params.port_name,
+            ports,
+            midi_input: Some(params.midi_input),
+            midi_input_port: params.midi_input_port,
+            conn_in: None,
+            conn_out: Some(conn_out),
+        })
+    }
+}
+
+fn list_midir_ports(midi_output: &MidiOutput) -> Vec<String> {
+    midi_output
+        .ports()
+        .iter()
+        .filter_map(|port| midi_output.port_name(port).ok())
+        .collect()
+}
+
+impl MidiTransport for MidirTransport {
+    fn port_name(&self) -> &str {
+        &self.port_name
+    }
+
+    fn ports(&self) -> Vec<String> {
+        self.ports.clone()
+    }
+
+    fn send(&mut self, mesg: &[u8]) -> Result<(), TransportError> {
+        match self.conn_out {
+            Some(ref mut conn_out) => conn_out
+                .send(mesg)
+                .map_err(|err| TransportError::Send(err.to_string())),
+            None => Err(TransportError::Closed),
+        }
+    }
+
+    fn listen(&mut self, mut callback: InputCallback) -> Result<(), TransportError> {
+        if self.conn_in.is_some() {
+            return Err(TransportError::AlreadyListening);
+        }
+
+        let midi_input = match self.midi_input.take() {
+            Some(midi_input) => midi_input,
+            None => return Err(TransportError::Closed),
+        };
+
+        match midi_input.connect(
+            &self.midi_input_port,
+            "bl-midi-in",
+            move |stamp, message, _| callback(stamp, message),
+            (),
+        ) {
+            Ok(conn_in) => {
+                self.conn_in = Some(conn_in);
+                Ok(())
+            }
+            Err(err) => Err(TransportError::Connect(err.to_string())),
+        }
+    }
+
+    fn close(&mut self) {
+        if let Some(conn_in) = self.conn_in.take() {
+            conn_in.close();
+        }
+
+        if let Some(conn_out) = self.conn_out.take() {
+            conn_out.close();
+        }
+
+        self.midi_input = None;
+    }
+}
+
+#[derive(Default)]
+struct LoopbackShared {
+    callback: Option<InputCallback>,
+    sent: Vec<Vec<u8>>,
+    closed: bool,
+}
+
+/// An in-memory transport, no device needed.
+///
+/// Messages sent by the server are stored and can be read back with `LoopbackHandle::take_sent`,
+/// messages injected with `LoopbackHandle::inject` are handed to the server like a device would.
+pub struct LoopbackTransport {
+    name: String,
+    shared: Arc<Mutex<LoopbackShared>>,
+}
+
+/// The "device side" of a `LoopbackTransport`.
+#[derive(Clone)]
+pub struct LoopbackHandle {
+    shared: Arc<Mutex<LoopbackShared>>,
+}
+
+impl LoopbackTransport {
+    pub fn new(name: &str) -> (LoopbackTransport, LoopbackHandle) {
+        let shared = Arc::new(Mutex::new(LoopbackShared::default()));
+
+        (
+            LoopbackTransport {
+                name: name.to_string(),
+                shared: Arc::clone(&shared),
+            },
+            LoopbackHandle { shared },
+        )
+    }
+}
+
+impl MidiTransport for LoopbackTransport {
+    fn port_name(&self) -> &str {
+        &self.name
+    }
+
+    fn ports(&self) -> Vec<String> {
+        vec![self.name.clone()]
+    }
+
+    fn send(&mut self, mesg: &[u8]) -> Result<(), TransportError> {
+        let mut shared = self.shared.lock().unwrap();
+
+        if shared.closed {
+            return Err(TransportError::Closed);
+        }
+
+        shared.sent.push(mesg.to_vec());
+        Ok(())
+    }
+
+    fn listen(&mut self, callback: InputCallback) -> Result<(), TransportError> {
+        let mut shared = self.shared.lock().unwrap();
+
+        if shared.closed {
+            return Err(TransportError::Closed);
+        }
+
+        if shared.callback.is_some() {
+            return Err(TransportError::AlreadyListening);
+        }
+
+        shared.callback = Some(callback);
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.callback = None;
+        shared.closed = true;
+    }
+
+    fn is_realtime(&self) -> bool {
+        false
+    }
+}
+
+impl std::fmt::Debug for LoopbackHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let shared = self.shared.lock().unwrap();
+        write!(
+            f,
+            "Loopback handle : {{ listening: {}, pending sent: {}, closed: {} }}",
+            shared.callback.is_some(),
+            shared.sent.len(),
+            shared.closed
+        )
+    }
+}
+
+impl LoopbackHandle {
+    /// Hand a message to the server as if the device sent it.
+    ///
+    /// Returns `false` if nobody listen (yet) or if the transport is closed.
+    pub fn inject(&self, stamp: u64, mesg: &[u8]) -> bool {
+        // Take the callback out of the lock, so the server can send messages from inside it.
+        let callback = self.shared.lock().unwrap().callback.take();
+
+        match callback {
+            Some(mut callback) => {
+                callback(stamp, mesg);
+
+                let mut shared = self.shared.lock().unwrap();
+                if !shared.closed && shared.callback.is_none() {
+                    shared.callback = Some(callback);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Drain all messages sent by the server so far.
+    pub fn take_sent(&self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.shared.lock().unwrap().sent)
+    }
+}
+
+#[cfg(feature = "websocket")]
+/// Shared input callback of an `InjectingTransport`.
+type SharedCallback = Arc<Mutex<Option<InputCallback>>>;
+
+#[cfg(feature = "websocket")]
+/// Wrap any transport, so messages can be handed to the server from elsewhere (the web surface)
+/// alongside the messages of the device.
+pub struct InjectingTransport<T: MidiTransport> {
+    inner: T,
+    callback: SharedCallback,
+}
+
+#[cfg(feature = "websocket")]
+/// Hand messages to an `InjectingTransport`, can be created before the transport.
+#[derive(Clone, Default)]
+pub struct InjectHandle {
+    callback: SharedCallback,
+}
+
+#[cfg(feature = "websocket")]
+impl<T: MidiTransport> InjectingTransport<T> {
+    pub fn new(inner: T, handle: &InjectHandle) -> Self {
+        Self {
+            inner,
+            callback: Arc::clone(&handle.callback),
+        }
+    }
+}
+
+#[cfg(feature = "websocket")]
+impl<T: MidiTransport> MidiTransport for InjectingTransport<T> {
+    fn port_name(&self) -> &str {
+        self.inner.port_name()
+    }
+
+    fn ports(&self) -> Vec<String> {
+        self.inner.ports()
+    }
+
+    fn send(&mut self, mesg: &[u8]) -> Result<(), TransportError> {
+        self.inner.send(mesg)
+    }
+
+    fn listen(&mut self, callback: InputCallback) -> Result<(), TransportError> {
+        if self.callback.lock().unwrap().is_some() {
+            return Err(TransportError::AlreadyListening);
+        }
+
+        // The callback is shared with the inject handle, it is locked on the relay thread
+        let shared = Arc::clone(&self.callback);
+        let relay = Box::new(move |stamp, mesg: &[u8]| {
+            if let Some(callback) = shared.lock().unwrap().as_mut() {
+                callback(stamp, mesg);
+            }
+        });
+        self.inner
+            .listen(relay_input(relay, self.inner.is_realtime()))?;
+
+        *self.callback.lock().unwrap() = Some(callback);
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        self.inner.close();
+        *self.callback.lock().unwrap() = None;
+    }
+
+    fn is_realtime(&self) -> bool {
+        self.inner.is_realtime()
+    }
+}
+
+#[cfg(feature = "websocket")]
+impl InjectHandle {
+    /// Hand a message to the server as if the device sent it.
+    ///
+    /// Returns `false` if the transport does not listen (yet) or is closed.
+    pub fn inject(&self, stamp: u64, mesg: &[u8]) -> bool {
+        match self.callback.lock().unwrap().as_mut() {
+            Some(callback) => {
+                callback(stamp, mesg);
+                true
+            }
+            None => false,
+        }
+    }
+}
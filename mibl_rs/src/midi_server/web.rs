@@ -0,0 +1,300 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tungstenite::{accept, Message, WebSocket};
+
+use crate::midi_server::container::{DeviceState, Event, ExtTrigger};
+use crate::midi_server::logger::{INPUT, SERVER};
+use crate::midi_server::midi_send_mesg::convert_value_to_lsb_msb;
+
+// HTTP and WebSocket share the same port :
+//   GET / => the virtual control surface (web/surface.html)
+//   GET / with "Upgrade: websocket" => JSON stream
+// Server => client : {"type": "state", …} on each DeviceState change, {"type": "trigger", …} on each ExtTrigger
+// Client => server : {"type": "fader", "strip": 0-8, "value": 0.0-1.0}
+//                    {"type": "button", "note": 0x00-0x7F, "pressed": bool}
+//                    {"type": "encoder", "vpot": 0-7, "delta": -63-63}
+
+const SURFACE_HTML: &str = include_str!("../../web/surface.html");
+const HTTP_MAX_REQUEST: usize = 4096;
+const ACCEPT_POLL: Duration = Duration::from_millis(20);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// An input of a web client, converted to the MIDI message the device would send.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WebInput {
+    Fader(u8, f32),   // strip (8 = master), value
+    Button(u8, bool), // note, pressed
+    Encoder(u8, i8),  // vpot_idx, delta (> 0 clockwise)
+}
+
+impl WebInput {
+    /// The Mackie Control message for this input.
+    pub fn to_midi(&self) -> Vec<u8> {
+        match self {
+            WebInput::Fader(strip, value) => {
+                let (lsb, msb) = convert_value_to_lsb_msb(value.clamp(0.0, 1.0));
+                vec![0xE0 | (strip & 0x0F), lsb & 0x7F, msb & 0x7F]
+            }
+            WebInput::Button(note, pressed) => {
+                vec![0x90, note & 0x7F, if *pressed { 0x7F } else { 0x00 }]
+            }
+            WebInput::Encoder(vpot_idx, delta) => {
+                let ticks = delta.unsigned_abs().min(0x3F);
+                let value = if *delta < 0 { 0x40 | ticks } else { ticks };
+                vec![0xB0, 0x10 + (vpot_idx & 0x07), value]
+            }
+        }
+    }
+}
+
+/// Parse a JSON message of a web client.
+pub fn parse_input(text: &str) -> Result<WebInput, String> {
+    let value: Value = serde_json::from_str(text).map_err(|err| err.to_string())?;
+    let int_field = |field: &str| -> Result<i64, String> {
+        value[field]
+            .as_i64()
+            .ok_or(format!("Missing integer field \"{}\"", field))
+    };
+
+    match value["type"].as_str() {
+        Some("fader") => Ok(WebInput::Fader(
+            int_field("strip")?.clamp(0, 8) as u8,
+            value["value"]
+                .as_f64()
+                .ok_or("Missing number field \"value\"")? as f32,
+        )),
+        Some("button") => Ok(WebInput::Button(
+            int_field("note")?.clamp(0, 0x7F) as u8,
+            value["pressed"]
+                .as_bool()
+                .ok_or("Missing boolean field \"pressed\"")?,
+        )),
+        Some("encoder") => Ok(WebInput::Encoder(
+            int_field("vpot")?.clamp(0, 7) as u8,
+            int_field("delta")?.clamp(-0x3F, 0x3F) as i8,
+        )),
+        other => Err(format!("Unknown input type : {:?}", other)),
+    }
+}
+
+pub fn state_json(device_state: &DeviceState) -> String {
+    json!({
+        "type": "state",
+        "timecode": device_state.get_timestamp(),
+        "lcd": device_state.get_lcd_string(),
+        "lcd_vec": device_state.get_lcd_vec(),
+        "vpots": device_state.get_vpots(),
+        "faders": device_state.get_faders(),
+        "chan_btns": device_state.get_chan_btns(),
+        "fps": device_state.get_fps(),
+    })
+    .to_string()
+}
+
+pub fn trigger_json(index: u64, name: Option<&str>, value: f32) -> String {
+    json!({
+        "type": "trigger",
+        "index": index,
+        "name": name,
+        "value": value,
+    })
+    .to_string()
+}
+
+type Clients = Arc<Mutex<Vec<WebSocket<TcpStream>>>>;
+
+/// Embedded HTTP/WebSocket server, serving the virtual control surface.
+///
+/// Connections are accepted by a thread, the clients are polled by the server loop (like `OscBridge`).
+pub struct WebBridge {
+    clients: Clients,
+    last_state: Arc<Mutex<Option<String>>>,
+    closed: Arc<AtomicBool>,
+    accept_thread: Option<JoinHandle<()>>,
+}
+
+fn http_response(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+
+    if let Err(err) = stream.write_all(response.as_bytes()) {
+        log::warn!(target: SERVER, "Unable to answer HTTP request : {}", err);
+    }
+}
+
+/// Serve the page, or upgrade the connection to a WebSocket client.
+fn handle_connection(
+    mut stream: TcpStream,
+    clients: &Clients,
+    last_state: &Arc<Mutex<Option<String>>>,
+) -> Result<(), String> {
+    stream
+        .set_nonblocking(false)
+        .and_then(|_| stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)))
+        .map_err(|err| err.to_string())?;
+
+    let mut buffer = [0u8; HTTP_MAX_REQUEST];
+    let len = stream.peek(&mut buffer).map_err(|err| err.to_string())?;
+    let request = String::from_utf8_lossy(&buffer[..len]).to_lowercase();
+
+    if request.contains("upgrade: websocket") {
+        let mut socket = accept(stream).map_err(|err| err.to_string())?;
+
+        if let Some(state) = last_state.lock().unwrap().clone() {
+            socket
+                .send(Message::text(state))
+                .map_err(|err| err.to_string())?;
+        }
+
+        socket
+            .get_mut()
+            .set_nonblocking(true)
+            .map_err(|err| err.to_string())?;
+        clients.lock().unwrap().push(socket);
+
+        return Ok(());
+    }
+
+    // Consume the request, then answer
+    let _ = stream.read(&mut buffer);
+
+    match request.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        ["get", "/"] | ["get", "/index.html"] => http_response(
+            &mut stream,
+            "200 OK",
+            "text/html; charset=utf-8",
+            SURFACE_HTML,
+        ),
+        _ => http_response(&mut stream, "404 Not Found", "text/plain", "Not found"),
+    }
+
+    Ok(())
+}
+
+impl WebBridge {
+    pub fn bind(listen: &str) -> Result<Self, String> {
+        let listener = TcpListener::bind(listen)
+            .map_err(|err| format!("Unable to bind {} : {}", listen, err))?;
+
+        listener
+            .set_nonblocking(true)
+            .map_err(|err| format!("Unable to set web socket non blocking : {}", err))?;
+
+        let clients: Clients = Arc::new(Mutex::new(vec![]));
+        let last_state = Arc::new(Mutex::new(None));
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let accept_thread = {
+            let clients = Arc::clone(&clients);
+            let last_state = Arc::clone(&last_state);
+            let closed = Arc::clone(&closed);
+
+            spawn(move || {
+                while !closed.load(Ordering::Relaxed) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            if let Err(err) = handle_connection(stream, &clients, &last_state) {
+                                log::warn!(target: SERVER, "Web connection dropped : {}", err);
+                            }
+                        }
+                        Err(err) if err.kind() == ErrorKind::WouldBlock => sleep(ACCEPT_POLL),
+                        Err(err) => {
+                            log::warn!(target: SERVER, "Unable to accept web connection : {}", err);
+                            sleep(ACCEPT_POLL);
+                        }
+                    }
+                }
+            })
+        };
+
+        log::info!(target: SERVER, "Virtual control surface on http://{}", listen);
+
+        Ok(Self {
+            clients,
+            last_state,
+            closed,
+            accept_thread: Some(accept_thread),
+        })
+    }
+
+    /// Send `text` to all clients, disconnected clients are dropped.
+    fn broadcast(&self, text: &str) {
+        self.clients
+            .lock()
+            .unwrap()
+            .retain_mut(|socket| match socket.send(Message::text(text)) {
+                Ok(()) => true,
+                Err(tungstenite::Error::Io(err)) if err.kind() == ErrorKind::WouldBlock => true,
+                Err(_) => false,
+            });
+    }
+
+    pub fn publish_state(&self, device_state: &DeviceState) {
+        let state = state_json(device_state);
+        self.broadcast(&state);
+        *self.last_state.lock().unwrap() = Some(state);
+    }
+
+    pub fn publish_triggers(&self, triggers: &[ExtTrigger], events: &Option<Vec<Event>>) {
+        for (index, value) in triggers {
+            let name = events.as_ref().and_then(|events| {
+                events
+                    .iter()
+                    .find(|event| event.get_index() == index)
+                    .map(Event::get_name)
+            });
+
+            self.broadcast(&trigger_json(*index, name, *value));
+        }
+    }
+
+    /// Read all pending client messages and return the inputs to hand to the server.
+    pub fn poll(&self) -> Vec<WebInput> {
+        let mut inputs = vec![];
+
+        self.clients.lock().unwrap().retain_mut(|socket| loop {
+            match socket.read() {
+                Ok(Message::Text(text)) => match parse_input(text.as_str()) {
+                    Ok(input) => inputs.push(input),
+                    Err(err) => log::warn!(target: INPUT, "Web input ignored : {}", err),
+                },
+                Ok(Message::Close(_)) => return false,
+                Ok(_) => (),
+                Err(tungstenite::Error::Io(err)) if err.kind() == ErrorKind::WouldBlock => {
+                    // Flush the pending outgoing messages, if any
+                    let _ = socket.flush();
+                    return true;
+                }
+                Err(_) => return false,
+            }
+        });
+
+        inputs
+    }
+}
+
+impl Drop for WebBridge {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+
+        if let Some(accept_thread) = self.accept_thread.take() {
+            let _ = accept_thread.join();
+        }
+
+        for socket in self.clients.lock().unwrap().iter_mut() {
+            let _ = socket.close(None);
+            let _ = socket.flush();
+        }
+    }
+}
@@ -58,9 +58,9 @@ pub fn compare(a: f32, b: f32, e: f32) -> bool {
     if a == b {
         true
     } else if a == 0.0 || b == 0.0 || (abs_a + abs_b) < BLENDER_ZMAX {
-        return diff < (e * f32::MIN_POSITIVE);
+        diff < (e * f32::MIN_POSITIVE)
     } else {
-        return diff / f32::min(abs_a + abs_b, f32::MAX) < e;
+        diff / f32::min(abs_a + abs_b, f32::MAX) < e
     }
 }
 
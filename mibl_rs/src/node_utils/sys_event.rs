@@ -1,8 +1,10 @@
+use crate::midi_server::logger::RECIPE;
 use crate::midi_server::sys_event::SYS_EVENT_ARRAY;
 
 pub fn get_event_by_index(idx: usize) -> Option<(u8, String)> {
     if idx >= SYS_EVENT_ARRAY.len() {
-        println!(
+        log::warn!(
+            target: RECIPE,
             "Unable to pick event at index {} index out of range ({})",
             idx,
             SYS_EVENT_ARRAY.len()